@@ -30,7 +30,7 @@ use url::{Host, Url};
 use yapper::{base64_decode, base64_encode, DelOnDrop, dispatch_debug, escape_discord, ModInfo, NetCommand, Notification, pretty_status, reserved_mod_id, Response, ServerCommand, ServerStatus, Status};
 use yapper::conf::Config;
 
-use crate::{comm::send_command, conf::MCAYB, process_mods};
+use crate::{comm::{poll_notifications, send_command}, conf::MCAYB, process_mods};
 use crate::conf::{OngoingPoll, PollKey, PollKind, VERSION};
 
 const TOKEN: &str = include_str!("../discord.token");
@@ -212,7 +212,7 @@ impl Menu {
 async fn send_err(h: &MenuHistory, error: &anyhow::Error) -> ProcessedMenu {
     dispatch_debug(error);
 
-    let mut content = format!("WHOOPS! An error occurred: {:?}", error);
+    let mut content = format!("WHOOPS! An error occurred: {}", escape_discord(&format!("{:#}", error)));
 
     if let Some(error) = error.downcast_ref::<io::Error>() {
         match error.kind() {
@@ -230,11 +230,30 @@ async fn send_err(h: &MenuHistory, error: &anyhow::Error) -> ProcessedMenu {
 }
 
 async fn send_unknown(h: &MenuHistory, resp: &Response) -> ProcessedMenu {
+    if let Response::Error { kind, message, context } = resp {
+        return result_menu(h, false, &error_message(*kind, message, context)).await;
+    }
+
     dispatch_debug(anyhow!("Unexpected response: {resp}"));
 
     result_menu(h, false, "Server sent an invalid response. This is a bug").await
 }
 
+fn error_message(kind: yapper::ErrorKind, message: &str, context: &[String]) -> String {
+    let mut content = match kind {
+        yapper::ErrorKind::InvalidState => format!("The server can't do that right now: {}", escape_discord(message)),
+        yapper::ErrorKind::NotFound => format!("Not found: {}", escape_discord(message)),
+        yapper::ErrorKind::Timeout => format!("The server took too long: {}", escape_discord(message)),
+        yapper::ErrorKind::InvalidInput => format!("Invalid input: {}", escape_discord(message)),
+        yapper::ErrorKind::Unsupported => format!("The server doesn't support that: {}", escape_discord(message)),
+        yapper::ErrorKind::Io | yapper::ErrorKind::Internal => format!("The server ran into an error: {}", escape_discord(message)),
+    };
+    for cause in context {
+        content.push_str(&format!("\n- caused by: {}", escape_discord(cause)));
+    }
+    content
+}
+
 async fn unknown_server(h: &MenuHistory, server: &str) -> ProcessedMenu {
     result_menu(h, false, &format!("Unknown server: {:?}", server)).await
 }
@@ -295,7 +314,7 @@ impl AllCommands {
         let null_menu = MenuHistory::new("null");
         match self {
             AllCommands::Dashboard => dashboard_menu(shared, &MenuHistory::new("dashboard")).await.interaction(),
-            AllCommands::Servers => match send_command(shared, NetCommand::ListServers) {
+            AllCommands::Servers => match send_command(shared, NetCommand::ListServers).await {
                 Ok(Response::List(statuses)) => {
                     let mut string = String::new();
 
@@ -321,7 +340,7 @@ impl AllCommands {
             AllCommands::Status { server } => match send_command(shared, NetCommand::ServerCommand(
                 server.clone(),
                 ServerCommand::Status,
-            )) {
+            )).await {
                 Ok(Response::Status(status)) => result_menu(&null_menu, true, &format!("Server is {}", pretty_status(status.status))).await.interaction(),
                 Ok(Response::UnknownServer) => unknown_server(&null_menu, &server).await.interaction(),
                 Ok(any) => send_unknown(&null_menu, &any).await.interaction(),
@@ -330,7 +349,7 @@ impl AllCommands {
             AllCommands::Start { server } => match send_command(shared, NetCommand::ServerCommand(
                 server.clone(),
                 ServerCommand::Start,
-            )) {
+            )).await {
                 Ok(Response::Ok) => result_menu(&null_menu, true, "Server started!").await.interaction(),
                 Ok(Response::UnknownServer) => unknown_server(&null_menu, &server).await.interaction(),
                 Ok(any) => send_unknown(&null_menu, &any).await.interaction(),
@@ -339,7 +358,7 @@ impl AllCommands {
             AllCommands::Stop { server } => match send_command(shared, NetCommand::ServerCommand(
                 server.clone(),
                 ServerCommand::Stop,
-            )) {
+            )).await {
                 Ok(Response::Ok) => result_menu(&null_menu, true, "Server stopped.").await.interaction(),
                 Ok(Response::UnknownServer) => unknown_server(&null_menu, &server).await.interaction(),
                 Ok(any) => send_unknown(&null_menu, &any).await.interaction(),
@@ -348,7 +367,7 @@ impl AllCommands {
             AllCommands::Reboot { server } => match send_command(shared, NetCommand::ServerCommand(
                 server.clone(),
                 ServerCommand::Reboot,
-            )) {
+            )).await {
                 Ok(Response::Ok) => result_menu(&null_menu, true, "Server is rebooting!").await.interaction(),
                 Ok(Response::UnknownServer) => unknown_server(&null_menu, &server).await.interaction(),
                 Ok(any) => send_unknown(&null_menu, &any).await.interaction(),
@@ -356,7 +375,7 @@ impl AllCommands {
             },
             AllCommands::Command { server, command } => match send_command(shared, 
                 NetCommand::ServerCommand(server.clone(), ServerCommand::Console(command.clone())),
-            ) {
+            ).await {
                 Ok(Response::CommandOutput(output)) => CreateInteractionResponseMessage::new().content(format!(
                         "`/{}` => `{}`",
                         escape_discord(&command),
@@ -627,7 +646,7 @@ fn server_selection(history: &MenuHistory, statuses: &[ServerStatus], url: &str,
 }
 
 async fn dashboard_menu(shared: &SharedMin, history: &MenuHistory) -> ProcessedMenu {
-    match send_command(shared, NetCommand::ListServers) {
+    match send_command(shared, NetCommand::ListServers).await {
         Ok(Response::List(statuses)) => {
             let (fields, buttons) = server_selection(history, &statuses, "menu", MenuUrlKind::Page, &[]);
 
@@ -642,7 +661,7 @@ async fn dashboard_menu(shared: &SharedMin, history: &MenuHistory) -> ProcessedM
 }
 
 async fn server_menu(shared: &SharedMin, history: &MenuHistory, server: &str) -> ProcessedMenu {
-    match send_command(shared, NetCommand::ServerCommand(server.to_owned(), ServerCommand::Status)) {
+    match send_command(shared, NetCommand::ServerCommand(server.to_owned(), ServerCommand::Status)).await {
         Ok(Response::Status(status)) => {
             let mut buttons: Vec<CreateButton> = Vec::with_capacity(10);
             match status.status {
@@ -709,7 +728,7 @@ async fn server_menu(shared: &SharedMin, history: &MenuHistory, server: &str) ->
 }
 
 async fn mod_menu(shared: &SharedMin, history: &MenuHistory, server: &str, page: u64) -> ProcessedMenu {
-    match send_command(shared, NetCommand::ServerCommand(server.to_owned(), ServerCommand::ListMods(10, page))) {
+    match send_command(shared, NetCommand::ServerCommand(server.to_owned(), ServerCommand::ListMods(10, page))).await {
         Ok(Response::Mods(mods, finished)) => {
             let mut buttons: Vec<CreateButton> = Vec::new();
             let mut fields: Vec<(String, String, bool)> = Vec::new();
@@ -747,7 +766,7 @@ async fn mod_menu(shared: &SharedMin, history: &MenuHistory, server: &str, page:
 }
 
 async fn single_mod_menu(shared: &SharedMin, history: &MenuHistory, server: &str, mod_id: &str) -> (ProcessedMenu, Option<Vec<u8>>) {
-    match send_command(shared, NetCommand::ServerCommand(server.to_owned(), ServerCommand::QueryMod(mod_id.to_owned()))) {
+    match send_command(shared, NetCommand::ServerCommand(server.to_owned(), ServerCommand::QueryMod(mod_id.to_owned()))).await {
         Ok(Response::Mod(modd)) => {
             let mut buttons = Vec::new();
             let action = MenuUrl::action("uninstall", &[server, mod_id]);
@@ -790,6 +809,11 @@ fn no_such_mod(mod_id: &str, server: &str) -> ProcessedMenu {
     info_menu(&format!(r#"Can't remove "{}" because it's not installed on "{}"!"#, escape_discord(mod_id), escape_discord(server)))
 }
 
+fn removed_too(mod_ids: &[String], server: &str) -> ProcessedMenu {
+    let mod_ids: Vec<String> = mod_ids.iter().map(|x| format!(r#""{}""#, escape_discord(x))).collect();
+    info_menu(&format!(r#"Also removed {} from "{}", they were in the same jar!"#, mod_ids.join(", "), escape_discord(server)))
+}
+
 fn already_installed(mod_id: &str, server: &str) -> ProcessedMenu {
     info_menu(&format!(r#"Mod "{}" is already installed for "{}"!"#, escape_discord(mod_id), escape_discord(server)))
 }
@@ -806,7 +830,7 @@ async fn wtf_bad_bot(history: &MenuHistory) -> ProcessedMenu {
 }
 
 async fn install_mod_menu(shared: &SharedMin, history: &MenuHistory, channel_id: &str, message_id: &str) -> ProcessedMenu {
-    match send_command(shared, NetCommand::ListServers) {
+    match send_command(shared, NetCommand::ListServers).await {
         Ok(Response::List(statuses)) => {
             let (fields, buttons) = server_selection(history, &statuses, "install", MenuUrlKind::Action, &[channel_id, message_id]);
 
@@ -1020,7 +1044,7 @@ In Minecraft, players explore a blocky, pixelated, procedurally generated, three
                             "start" => {
                                 let server = &h.current.arguments[0];
                                 CreateInteractionResponse::UpdateMessage(
-                                    match send_command(&self.shared, NetCommand::ServerCommand(server.to_owned(), ServerCommand::Start)) {
+                                    match send_command(&self.shared, NetCommand::ServerCommand(server.to_owned(), ServerCommand::Start)).await {
                                         Ok(Response::Ok) => result_menu(&h, true, "Server started!").await.interaction(),
                                         Ok(any) => send_unknown(&h, &any).await.interaction(),
                                         Err(any) => send_err(&h, &any).await.interaction(),
@@ -1029,7 +1053,7 @@ In Minecraft, players explore a blocky, pixelated, procedurally generated, three
                             "stop" => {
                                 let server = &h.current.arguments[0];
                                 CreateInteractionResponse::UpdateMessage(
-                                    match send_command(&self.shared, NetCommand::ServerCommand(server.to_owned(), ServerCommand::Stop)) {
+                                    match send_command(&self.shared, NetCommand::ServerCommand(server.to_owned(), ServerCommand::Stop)).await {
                                         Ok(Response::Ok) => result_menu(&h, true, "Server stopped.").await.interaction(),
                                         Ok(any) => send_unknown(&h, &any).await.interaction(),
                                         Err(any) => send_err(&h, &any).await.interaction(),
@@ -1038,7 +1062,7 @@ In Minecraft, players explore a blocky, pixelated, procedurally generated, three
                             "reboot" => {
                                 let server = &h.current.arguments[0];
                                 CreateInteractionResponse::UpdateMessage(
-                                    match send_command(&self.shared, NetCommand::ServerCommand(server.to_owned(), ServerCommand::Reboot)) {
+                                    match send_command(&self.shared, NetCommand::ServerCommand(server.to_owned(), ServerCommand::Reboot)).await {
                                         Ok(Response::Ok) => result_menu(&h, true, "Server is rebooting!").await.interaction(),
                                         Ok(any) => send_unknown(&h, &any).await.interaction(),
                                         Err(any) => send_err(&h, &any).await.interaction(),
@@ -1122,7 +1146,7 @@ In Minecraft, players explore a blocky, pixelated, procedurally generated, three
                                 let server = &h.current.arguments[0];
                                 let mod_id = &h.current.arguments[1];
 
-                                match send_command(&self.shared, NetCommand::ServerCommand(server.clone(), ServerCommand::QueryMod(mod_id.clone()))) {
+                                match send_command(&self.shared, NetCommand::ServerCommand(server.clone(), ServerCommand::QueryMod(mod_id.clone()))).await {
                                     Ok(Response::Mod(info)) => {
                                         let key = PollKey::Mod {
                                             server: server.clone(),
@@ -1145,7 +1169,7 @@ In Minecraft, players explore a blocky, pixelated, procedurally generated, three
                             "zip" => {
                                 let server = &h.current.arguments[0];
 
-                                match send_command(&self.shared, NetCommand::ServerCommand(server.clone(), ServerCommand::GenerateModsZip)) {
+                                match send_command(&self.shared, NetCommand::ServerCommand(server.clone(), ServerCommand::GenerateModsZip)).await {
                                     Ok(Response::Ok) => {
                                         CreateInteractionResponse::UpdateMessage(result_menu(&h, true, "Mods zip file is generating!").await.interaction())
                                     }
@@ -1188,7 +1212,7 @@ In Minecraft, players explore a blocky, pixelated, procedurally generated, three
                                 let command = text.value.as_ref().unwrap();
 
                                 CreateInteractionResponse::UpdateMessage(
-                                    match send_command(&self.shared, NetCommand::ServerCommand(server.to_owned(), ServerCommand::Console(command.clone()))) {
+                                    match send_command(&self.shared, NetCommand::ServerCommand(server.to_owned(), ServerCommand::Console(command.clone()))).await {
                                         Ok(Response::CommandOutput(output)) => result_menu(&h, true, &output).await.interaction(),
                                         Ok(Response::UnknownServer) => unknown_server(&h, &server).await.interaction(),
                                         Ok(any) => send_unknown(&h, &any).await.interaction(),
@@ -1209,7 +1233,7 @@ In Minecraft, players explore a blocky, pixelated, procedurally generated, three
                                         confirm2.eq_ignore_ascii_case(CONFIRM_BACKUP) &&
                                         confirm3 == server
                                     {
-                                        match send_command(&self.shared, NetCommand::ServerCommand(server.clone(), ServerCommand::Backup)) {
+                                        match send_command(&self.shared, NetCommand::ServerCommand(server.clone(), ServerCommand::Backup)).await {
                                             Ok(Response::Ok) => result_menu(&h, true, "Backing up!").await.interaction(),
                                             Ok(Response::UnknownServer) => unknown_server(&h, &server).await.interaction(),
                                             Ok(any) => send_unknown(&h, &any).await.interaction(),
@@ -1234,7 +1258,7 @@ In Minecraft, players explore a blocky, pixelated, procedurally generated, three
                                         confirm2.eq_ignore_ascii_case(CONFIRM_RESTORE) &&
                                         confirm3 == server
                                     {
-                                        match send_command(&self.shared, NetCommand::ServerCommand(server.clone(), ServerCommand::Restore)) {
+                                        match send_command(&self.shared, NetCommand::ServerCommand(server.clone(), ServerCommand::Restore)).await {
                                             Ok(Response::Ok) => result_menu(&h, true, "Restoring backup!").await.interaction(),
                                             Ok(Response::UnknownServer) => unknown_server(&h, &server).await.interaction(),
                                             Ok(Response::NoBackup) => result_menu(&h, false, "No backup exists!").await.interaction(),
@@ -1587,7 +1611,7 @@ async fn guild_loop(shared: &Shared) {
     // Detect notifications the server can't give us
     let mut msgs = shared.msgs.lock().await;
     let mut last_status = { shared.last_status.lock().await.clone() };
-    match send_command(shared, NetCommand::ListServers) {
+    match send_command(shared, NetCommand::ListServers).await {
         Ok(Response::List(new_status)) => {
             let mut events = Vec::new();
 
@@ -1625,8 +1649,8 @@ async fn guild_loop(shared: &Shared) {
     }
 
     // Receive normal notifications
-    match send_command(shared, NetCommand::Notifications) {
-        Ok(Response::Notifications(notifs)) => {
+    match poll_notifications(shared).await {
+        Ok(notifs) => {
             for notif in notifs {
                 match &notif {
                     Notification::BackupProgress(server, _, _) => {
@@ -1757,16 +1781,20 @@ async fn create_poll(shared: &Shared, key: PollKey, kind: PollKind) {
 
             // What if the mod is already installed?
             let null_history = MenuHistory::new("null");
-            let error_msg = match send_command(shared, NetCommand::ServerCommand(server.to_owned(), ServerCommand::ListMods(0, 0))) {
+            let error_msg = match send_command(shared, NetCommand::ServerCommand(server.to_owned(), ServerCommand::ListMods(0, 0))).await {
                 Ok(Response::Mods(mods, _)) => {
                     if mods.iter().any(|modd| &modd.mod_id == &info.mod_id) {
                         // Some(already_installed(&key.mod_id, &key.server).message())
                         // Update the mod instead
 
-                        match send_command(shared, NetCommand::ServerCommand(server.to_owned(), ServerCommand::UpdateMod(info.path.to_string_lossy().to_string(), info.filename.clone()))) {
+                        match send_command(shared, NetCommand::ServerCommand(server.to_owned(), ServerCommand::UpdateMod(info.path.to_string_lossy().to_string(), info.filename.clone()))).await {
                             Ok(Response::Ok) => {
                                 Some(info_menu(&format!(r#"Mod "{}" updated for "{}"!"#, escape_discord(&info.mod_id), escape_discord(server))).message())
                             }
+                            Ok(Response::ModsRemoved(mod_ids)) => {
+                                let _ = channel.send_message(shared, info_menu(&format!(r#"Mod "{}" updated for "{}"!"#, escape_discord(&info.mod_id), escape_discord(server))).message()).await;
+                                Some(removed_too(&mod_ids, server).message())
+                            }
                             Ok(Response::NoSuchMod) => Some(result_menu(&null_history, false, &format!(r#"Mod "{}" was already installed for "{}", so i tried to update it instead, but the server said it can't be updated because the mod isn't installed... WTF????"#, escape_discord(&info.mod_id), escape_discord(server))).await.message()),
                             Ok(any) => Some(send_unknown(&null_history, &any).await.message()),
                             Err(any) => Some(send_err(&null_history, &any).await.message()),
@@ -1988,12 +2016,12 @@ async fn check_poll(shared: &Shared, the_channel: ChannelId, the_poll: MessageId
                 let null_history = MenuHistory::new("null");
                 match &poll.kind {
                     PollKind::Install { server, info, .. } => {
-                        let error_msg = match send_command(shared, NetCommand::ServerCommand(server.clone(), ServerCommand::InstallMod(info.path.to_string_lossy().to_string(), info.filename.clone()))) {
+                        let error_msg = match send_command(shared, NetCommand::ServerCommand(server.clone(), ServerCommand::InstallMod(info.path.to_string_lossy().to_string(), info.filename.clone()))).await {
                             Ok(Response::Ok) => {
                                 del.map(|del| del.forgive());
                                 None
                             },
-                            Ok(Response::ModConflict) => {
+                            Ok(Response::ModConflict | Response::DuplicateMod(_)) => {
                                 Some(already_installed(&info.mod_id, server).message())
                             }
                             Ok(any) => Some(send_unknown(&null_history, &any).await.message()),
@@ -2007,10 +2035,14 @@ async fn check_poll(shared: &Shared, the_channel: ChannelId, the_poll: MessageId
                         }
                     }
                     PollKind::Remove { server, info, .. } => {
-                        let error_msg = match send_command(shared, NetCommand::ServerCommand(server.clone(), ServerCommand::UninstallMod(info.mod_id.clone()))) {
+                        let error_msg = match send_command(shared, NetCommand::ServerCommand(server.clone(), ServerCommand::UninstallMod(info.mod_id.clone()))).await {
                             Ok(Response::Ok) => {
                                 None
                             },
+                            Ok(Response::ModsRemoved(mod_ids)) => {
+                                let _ = channel.send_message(shared, removed_too(&mod_ids, server).message()).await;
+                                None
+                            },
                             Ok(Response::NoSuchMod) => Some(no_such_mod(&info.mod_id, server).message()),
                             Ok(any) => Some(send_unknown(&null_history, &any).await.message()),
                             Err(any) => Some(send_err(&null_history, &any).await.message()),
@@ -2044,10 +2076,10 @@ async fn check_poll(shared: &Shared, the_channel: ChannelId, the_poll: MessageId
 async fn reboot_server(shared: &Shared, server: impl Into<String> + Clone) {
     let null_history = MenuHistory::new("null");
     // REBOOT THE SERVER
-    let msg = match send_command(shared, NetCommand::ServerCommand(server.clone().into(), ServerCommand::Status)) {
+    let msg = match send_command(shared, NetCommand::ServerCommand(server.clone().into(), ServerCommand::Status)).await {
         Ok(Response::Status(status)) => {
             if status.status != Status::Idle {
-                let _ = send_command(shared, NetCommand::ServerCommand(server.into(), ServerCommand::Reboot));
+                let _ = send_command(shared, NetCommand::ServerCommand(server.into(), ServerCommand::Reboot)).await;
             }
             None
         }
@@ -2070,6 +2102,34 @@ fn mod_embed(info: &ModInfo) -> CreateEmbed {
 
     menu_fields.push(("Version".to_owned(), info.version.to_string(), true));
 
+    if let Some(loader) = &info.loader {
+        let loader = match &info.loader_version {
+            Some(range) => format!("{loader} {range}"),
+            None => loader.clone(),
+        };
+        menu_fields.push(("Loader".to_owned(), loader, true));
+    }
+
+    if let Some(license) = &info.license {
+        menu_fields.push(("License".to_owned(), license.clone(), true));
+    }
+
+    if info.client_side_only {
+        menu_fields.push(("Side".to_owned(), "Client only".to_owned(), true));
+    }
+
+    if let Some(display_test) = info.display_test {
+        menu_fields.push(("Display test".to_owned(), format!("{display_test:?}"), true));
+    }
+
+    if let Some(issues) = &info.issue_tracker {
+        menu_fields.push(("Issues".to_owned(), issues.clone(), false));
+    }
+
+    if let Some(update_json) = &info.update_json {
+        menu_fields.push(("Update JSON".to_owned(), update_json.clone(), false));
+    }
+
     let mut menu = Menu::new(
         (106, 72, 161),
         mod_name.clone(),
@@ -1,12 +1,15 @@
-use std::net::TcpStream;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::{bail, Result};
-use ende::{BinSettings, BitWidth, Context, Encoder, NumEncoding, SizeRepr, VariantRepr};
-use ende::io::Std;
-use openssl::rsa::{Padding, Rsa};
+use once_cell::sync::Lazy;
 use serenity::all::GuildId;
+use tokio::sync::Mutex;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::mpsc::error::TryRecvError;
 
-use yapper::{LoginPacket, LoginResponse, NetCommand, Response, send_packet};
+use yapper::{AsyncClient, Capabilities, dispatch_debug, NetCommand, Notification, NotificationFilter, Response};
 use yapper::conf::Config;
 
 use crate::bot::SharedMin;
@@ -16,6 +19,19 @@ const IP: &str = "127.0.0.1:23786";
 
 const PUB_KEY: &[u8] = include_bytes!("../sv_manage.pem");
 
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+struct Connection {
+    user: String,
+    password: [u8; 32],
+    client: Arc<AsyncClient>,
+}
+
+// One long-lived session per guild instead of a handshake for every command
+static CLIENTS: Lazy<Mutex<HashMap<GuildId, Connection>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+static SUBSCRIPTIONS: Lazy<Mutex<HashMap<GuildId, UnboundedReceiver<Notification>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+static CURSORS: Lazy<Mutex<HashMap<GuildId, u64>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
 fn login(conf: &Config<MCAYB>, guild_id: GuildId) -> (String, [u8; 32]) {
     conf.with_config(|conf| {
         let ref data = conf.guild_data[&guild_id];
@@ -23,43 +39,101 @@ fn login(conf: &Config<MCAYB>, guild_id: GuildId) -> (String, [u8; 32]) {
     })
 }
 
-pub fn send_command(shared: &SharedMin, cmd: NetCommand) -> Result<Response> {
-    let client = TcpStream::connect(IP)?;
-    let ctxt = Context::new()
-        .settings(BinSettings::new()
-            .variant_repr(VariantRepr::new()
-                .bit_width(BitWidth::Bit8))
-            .size_repr(SizeRepr::new()
-                .num_encoding(NumEncoding::Leb128)));
-    let mut encoder = Encoder::new(Std::new(client), ctxt);
-
-    // Generate aes key
-    let mut aes = [0u8; 16];
-    openssl::rand::rand_bytes(&mut aes)?;
-
-    // Encrypt it
-    let key = Rsa::public_key_from_pem(PUB_KEY)?;
-    let mut encrypted = [0u8; 256];
-    key.public_encrypt(&aes, &mut encrypted, Padding::PKCS1)?;
-
-    encoder.encode_value(encrypted)?;
-
-    let mut client = encoder.finish().0.into_inner();
-
-    // Begin packet exchange
-    let (acc, pw) = login(&shared.conf, shared.guild);
-    let login = LoginPacket {
-        user: acc,
-        password: pw,
+async fn client(shared: &SharedMin) -> Result<Arc<AsyncClient>> {
+    let (user, password) = login(&shared.conf, shared.guild);
+
+    let mut clients = CLIENTS.lock().await;
+    if let Some(conn) = clients.get(&shared.guild)
+        && conn.user == user
+        && conn.password == password
+        && !conn.client.is_closed() {
+        return Ok(conn.client.clone());
+    }
+
+    // First use, the connection dropped, or the credentials changed since it was opened
+    let mut client = AsyncClient::connect(IP, PUB_KEY, TIMEOUT).await?;
+    client.login(&user, password, TIMEOUT).await?;
+    let client = Arc::new(client);
+    clients.insert(shared.guild, Connection {
+        user,
+        password,
+        client: client.clone(),
+    });
+    Ok(client)
+}
+
+pub async fn send_command(shared: &SharedMin, cmd: NetCommand) -> Result<Response> {
+    let conn = client(shared).await?;
+    match conn.request(cmd.clone(), TIMEOUT).await {
+        // One retry with a fresh connection in case the old one went stale
+        Err(err) if conn.is_closed() => {
+            dispatch_debug(err.context("Session dropped, reconnecting"));
+            client(shared).await?.request(cmd, TIMEOUT).await
+        }
+        result => result,
+    }
+}
+
+// Everything sv_manage pushed since the last call
+pub async fn poll_notifications(shared: &SharedMin) -> Result<Vec<Notification>> {
+    let conn = client(shared).await?;
+    if !conn.capabilities().contains(Capabilities::NotificationLog) {
+        return poll_subscription(shared, &conn).await;
+    }
+
+    let mut cursors = CURSORS.lock().await;
+    let cursor = match cursors.get(&shared.guild) {
+        Some(cursor) => Some(*cursor),
+        None => shared.conf.with_config(|conf| conf.guild_data[&shared.guild].last_notification),
+    };
+
+    let cmd = NetCommand::NotificationsAfter(cursor.unwrap_or(0));
+    let entries = match send_command(shared, cmd).await? {
+        Response::NotificationLog(entries) => entries,
+        other => bail!("Unexpected response to NotificationsAfter: {other}"),
+    };
+    let Some(last) = entries.last().map(|entry| entry.id) else {
+        return Ok(Vec::new());
     };
+    cursors.insert(shared.guild, last);
 
-    let login = send_packet(&mut client, &aes, ctxt, login)?;
-    match login {
-        LoginResponse::Ok => {}
-        LoginResponse::WrongCredentials => bail!("Wrong credentials"),
+    // Progress updates are only kept in memory on sv_manage's side, so they don't need to be
+    // remembered across restarts. Saves rewriting the config on every tick of a backup
+    if cursor.is_none() || entries.iter().any(|entry| !entry.notif.is_progress()) {
+        shared.conf.with_config_mut(|conf| {
+            if let Some(data) = conf.guild_data.get_mut(&shared.guild) {
+                data.last_notification = Some(last);
+            }
+        })?;
     }
 
-    let cmd = send_packet(&mut client, &aes, ctxt, cmd)?;
+    // The first poll only finds out where the log is, rather than replaying all of it
+    if cursor.is_none() {
+        return Ok(Vec::new());
+    }
+    Ok(entries.into_iter().map(|entry| entry.notif).collect())
+}
 
-    Ok(cmd)
+// For sv_manage versions without the notification log
+async fn poll_subscription(shared: &SharedMin, conn: &AsyncClient) -> Result<Vec<Notification>> {
+    let mut subscriptions = SUBSCRIPTIONS.lock().await;
+    if !subscriptions.contains_key(&shared.guild) {
+        let recv = conn.subscribe(NotificationFilter::all(), TIMEOUT).await?;
+        subscriptions.insert(shared.guild, recv);
+    }
+
+    let mut notifs = Vec::new();
+    let recv = subscriptions.get_mut(&shared.guild).unwrap();
+    loop {
+        match recv.try_recv() {
+            Ok(notif) => notifs.push(notif),
+            Err(TryRecvError::Empty) => break,
+            Err(TryRecvError::Disconnected) => {
+                // The connection was replaced, subscribe again next time
+                subscriptions.remove(&shared.guild);
+                break;
+            }
+        }
+    }
+    Ok(notifs)
 }
@@ -13,6 +13,7 @@ use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serenity::all::{ChannelId, GuildId, MessageId};
 
 use yapper::{base64_decode, base64_encode, ModInfo, ServerStatus};
+use yapper::conf::Schema;
 
 const LOCK: &str = "~/.mcayb.lock";
 pub const CONFIG: &str = "mcayb.json";
@@ -39,8 +40,7 @@ macro_rules! string_serde {
             {
                 let this = String::deserialize(deserializer)?;
                 let this = base64_decode(&this);
-                let this = ende::decode_bytes(&this).unwrap();
-                Ok(this)
+                ende::decode_bytes(&this).map_err(serde::de::Error::custom)
             }
         }
     };
@@ -68,6 +68,8 @@ pub struct MCAYB {
     pub guild_data: HashMap<GuildId, GuildData>,
 }
 
+impl Schema for MCAYB {}
+
 impl Default for MCAYB {
     fn default() -> Self {
         Self {
@@ -83,7 +85,28 @@ pub struct GuildData {
     pub sv_pass: [u8; 32],
     pub last_status: Vec<ServerStatus>,
     pub notifications: ChannelId,
+    #[serde(deserialize_with = "skip_stale_polls")]
     pub polls: HashMap<PollKey, OngoingPoll>,
+    // Id of the last entry read from sv_manage's notification log, `None` before the first poll
+    #[serde(default)]
+    pub last_notification: Option<u64>,
+}
+
+// Polls hold encoded ModInfos, which can't be read back after their layout changes.
+// Those polls are dropped rather than failing the whole config
+fn skip_stale_polls<'de, D>(deserializer: D) -> std::result::Result<HashMap<PollKey, OngoingPoll>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let raw = HashMap::<String, String>::deserialize(deserializer)?;
+    Ok(raw
+        .into_iter()
+        .filter_map(|(key, poll)| {
+            let key = serde_json::from_value(serde_json::Value::String(key)).ok()?;
+            let poll = serde_json::from_value(serde_json::Value::String(poll)).ok()?;
+            Some((key, poll))
+        })
+        .collect())
 }
 
 #[derive(Debug, Encode, Decode, Hash, Clone, Eq, PartialEq)]
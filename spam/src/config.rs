@@ -8,6 +8,7 @@ use expanduser::expanduser;
 use file_guard::{FileGuard, Lock};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
+use yapper::conf::Schema;
 
 pub const CONFIG: &str = "spam.json";
 const LOCK: &str = "~/.spam.lock";
@@ -33,6 +34,8 @@ pub struct SPAM {
 	pub api_tokens: HashMap<Uuid, Token>
 }
 
+impl Schema for SPAM {}
+
 bitflags! {
 	#[derive(Debug, Clone, Serialize, Deserialize)]
 	pub struct Access: u8 {
@@ -8,6 +8,7 @@ use std::net::{SocketAddr, SocketAddrV4};
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Mutex;
+use std::time::Duration;
 use axum::body::{Body, Bytes};
 use axum::extract::{DefaultBodyLimit, Multipart, Path, State};
 use axum::http::{HeaderMap, StatusCode};
@@ -32,6 +33,7 @@ use crate::config::{Access, CONFIG, Scope, SPAM, Token};
 async fn main() {
 	let lock = config::acquire_lock().expect("Failed to acquire lock");
 	let config: Config<SPAM> = Config::init(CONFIG).expect("Failed to load config");
+	config.watch(Duration::from_secs(5));
 
 	// config.with_config_mut(|conf| {
 	// 	let uuid = Uuid::new_v4();
@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use bitflags::bitflags;
+use chrono::{DateTime, Utc};
+use expanduser::expanduser;
+use file_guard::{FileGuard, Lock};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use yapper::conf::Schema;
+
+pub const CONFIG: &str = "sv_gateway.json";
+const LOCK: &str = "~/.sv_gateway.lock";
+
+pub fn acquire_lock() -> anyhow::Result<FileGuard<Box<File>>> {
+	use anyhow::Context;
+
+	let lock = OpenOptions::new()
+		.read(true)
+		.write(true)
+		.create(true)
+		.open(expanduser(LOCK).context("Failed to find home directory")?)
+		.context("Failed to *open/create* the lock file")?;
+
+	Ok(
+		file_guard::try_lock(Box::new(lock), Lock::Exclusive, 0, isize::MAX as _)
+			.context("Failed to *lock* the lock file")?,
+	)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SVGateway {
+	pub listen: String,
+	pub sv_manage: String,
+	// Path to sv_manage's public key
+	pub pub_key: String,
+	pub tls: Option<Tls>,
+	// Jars checked through /deps have to be in here, the gateway opens them itself.
+	// Without it that endpoint refuses everything
+	#[serde(default)]
+	pub uploads: Option<String>,
+	pub api_tokens: HashMap<Uuid, Token>,
+}
+
+impl Schema for SVGateway {}
+
+impl Default for SVGateway {
+	fn default() -> Self {
+		Self {
+			listen: "127.0.0.1:23787".to_owned(),
+			sv_manage: "127.0.0.1:23786".to_owned(),
+			pub_key: "sv_manage.pem".to_owned(),
+			tls: None,
+			uploads: None,
+			api_tokens: HashMap::new(),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tls {
+	pub cert: String,
+	pub key: String,
+}
+
+bitflags! {
+	#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+	pub struct Access: u8 {
+		const Read = 0b00000001;
+		const Write = 0b00000010;
+	}
+}
+
+impl Access {
+	pub const NONE: Self = Access::empty();
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, Hash, Eq, PartialEq)]
+pub enum Scope {
+	// Listing and status are Read, everything that changes a server is Write
+	Servers,
+	Mods,
+	Notifications,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Token {
+	created: DateTime<Utc>,
+	expires: Option<DateTime<Utc>>,
+	// The sv_manage account requests are made as, and its `hash_pw` secret
+	pub account: String,
+	pub secret: [u8; 32],
+	scopes: HashMap<Scope, Access>,
+}
+
+impl Token {
+	pub fn new(account: String, secret: [u8; 32]) -> Self {
+		let mut scopes = HashMap::new();
+		scopes.insert(Scope::Servers, Access::NONE);
+		scopes.insert(Scope::Mods, Access::NONE);
+		scopes.insert(Scope::Notifications, Access::NONE);
+
+		Self {
+			created: Utc::now(),
+			expires: None,
+			account,
+			secret,
+			scopes,
+		}
+	}
+
+	pub fn grant(&mut self, scope: Scope, access: Access) {
+		*self.scopes.entry(scope).or_insert(Access::NONE) |= access;
+	}
+
+	pub fn allows(&self, scope: Scope, access: Access) -> bool {
+		let expired = self.expires.is_some_and(|expires| expires <= Utc::now());
+		!expired && self.scopes.get(&scope).is_some_and(|granted| granted.contains(access))
+	}
+}
@@ -0,0 +1,476 @@
+#![feature(try_blocks)]
+#![feature(let_chains)]
+
+mod config;
+mod openapi;
+
+use std::collections::HashMap;
+use std::env;
+use std::io::BufRead;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::{anyhow, bail, Context, Result};
+use axum::extract::{MatchedPath, Path, Query, Request, State};
+use axum::http::StatusCode;
+use axum::http::header::AUTHORIZATION;
+use axum::middleware::{from_fn_with_state, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post, MethodRouter};
+use axum::{Extension, Json, Router};
+use axum_server::tls_openssl::OpenSSLConfig;
+use serde::Deserialize;
+use tokio::net::TcpListener;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+use yapper::{AsyncClient, DepResolveMode, dispatch_debug, ErrorKind, hash_pw, json, NetCommand, parse_mods, ServerCommand};
+use yapper::conf::Config;
+use crate::config::{Access, CONFIG, Scope, SVGateway, Token};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+// Who a request is made as, put there by `auth`
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+struct Login {
+	account: String,
+	secret: [u8; 32],
+}
+
+#[derive(Clone)]
+struct Gateway {
+	config: Config<SVGateway>,
+	pub_key: Arc<Vec<u8>>,
+	// One session per account, shared by all of its tokens
+	clients: Arc<Mutex<HashMap<Login, Arc<AsyncClient>>>>,
+}
+
+impl Gateway {
+	async fn client(&self, login: &Login) -> Result<Arc<AsyncClient>> {
+		let mut clients = self.clients.lock().await;
+		if let Some(client) = clients.get(login) && !client.is_closed() {
+			return Ok(client.clone());
+		}
+
+		let addr = self.config.with_config(|conf| conf.sv_manage.clone());
+		let mut client = AsyncClient::connect(addr, &self.pub_key, TIMEOUT).await?;
+		client.login(&login.account, login.secret, TIMEOUT).await?;
+		let client = Arc::new(client);
+		clients.insert(login.clone(), client.clone());
+		Ok(client)
+	}
+
+	async fn request(&self, login: &Login, cmd: NetCommand) -> Result<yapper::Response> {
+		let client = self.client(login).await?;
+		match client.request(cmd.clone(), TIMEOUT).await {
+			// One retry with a fresh connection in case the old one went stale
+			Err(err) if client.is_closed() => {
+				dispatch_debug(err.context("Session dropped, reconnecting"));
+				self.client(login).await?.request(cmd, TIMEOUT).await
+			}
+			result => result,
+		}
+	}
+
+	async fn run(&self, login: &Login, cmd: NetCommand) -> Response {
+		match self.request(login, cmd).await {
+			Ok(response) => (status_of(&response), Json(json::response_json(&response))).into_response(),
+			Err(err) => {
+				let response = yapper::Response::error(&err.context("Couldn't talk to sv_manage"));
+				(StatusCode::BAD_GATEWAY, Json(json::response_json(&response))).into_response()
+			}
+		}
+	}
+}
+
+fn status_of(response: &yapper::Response) -> StatusCode {
+	use yapper::Response::*;
+	match response {
+		Err => StatusCode::INTERNAL_SERVER_ERROR,
+		UnknownServer | NoSuchMod => StatusCode::NOT_FOUND,
+		InvalidState | NoBackup | ModConflict | DuplicateMod(_) => StatusCode::CONFLICT,
+		Unsupported(_) => StatusCode::NOT_IMPLEMENTED,
+		Error { kind, .. } => match kind {
+			ErrorKind::NotFound => StatusCode::NOT_FOUND,
+			ErrorKind::InvalidState => StatusCode::CONFLICT,
+			ErrorKind::InvalidInput => StatusCode::BAD_REQUEST,
+			ErrorKind::Timeout => StatusCode::GATEWAY_TIMEOUT,
+			ErrorKind::Unsupported => StatusCode::NOT_IMPLEMENTED,
+			ErrorKind::Io | ErrorKind::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+		},
+		_ => StatusCode::OK,
+	}
+}
+
+fn bad_request(err: anyhow::Error) -> Response {
+	let response = yapper::Response::error_kind(ErrorKind::InvalidInput, &err);
+	(StatusCode::BAD_REQUEST, Json(json::response_json(&response))).into_response()
+}
+
+async fn auth(State(gw): State<Gateway>, matched: Option<MatchedPath>, mut req: Request, next: Next) -> Response {
+	let Some(endpoint) = matched.and_then(|path| openapi::find(req.method().as_str(), path.as_str())) else {
+		return StatusCode::FORBIDDEN.into_response();
+	};
+
+	if let Some((scope, access)) = endpoint.auth {
+		let token: Option<Token> = try {
+			let header = req.headers().get(AUTHORIZATION)?.to_str().ok()?;
+			let ("Bearer", token) = header.split_once(" ")? else { None? };
+			let uuid = Uuid::from_str(token).ok()?;
+			gw.config.with_config(|conf| conf.api_tokens.get(&uuid).cloned())?
+		};
+		let Some(token) = token else { return StatusCode::UNAUTHORIZED.into_response() };
+		if !token.allows(scope, access) {
+			return StatusCode::FORBIDDEN.into_response();
+		}
+
+		req.extensions_mut().insert(Login {
+			account: token.account,
+			secret: token.secret,
+		});
+	}
+
+	next.run(req).await
+}
+
+// Routes that are just a server name and a command
+fn server_action(cmd: ServerCommand) -> MethodRouter<Gateway> {
+	post(move |State(gw): State<Gateway>, Extension(login): Extension<Login>, Path(server): Path<String>| {
+		let cmd = cmd.clone();
+		async move { gw.run(&login, NetCommand::ServerCommand(server, cmd)).await }
+	})
+}
+
+async fn check_updates(State(gw): State<Gateway>, Extension(login): Extension<Login>, Path(server): Path<String>) -> Response {
+	gw.run(&login, NetCommand::ServerCommand(server, ServerCommand::CheckUpdates)).await
+}
+
+async fn list_servers(State(gw): State<Gateway>, Extension(login): Extension<Login>) -> Response {
+	gw.run(&login, NetCommand::ListServers).await
+}
+
+async fn server_status(State(gw): State<Gateway>, Extension(login): Extension<Login>, Path(server): Path<String>) -> Response {
+	gw.run(&login, NetCommand::ServerCommand(server, ServerCommand::Status)).await
+}
+
+#[derive(Debug, Deserialize)]
+struct ConsoleBody {
+	command: String,
+}
+
+async fn console(State(gw): State<Gateway>, Extension(login): Extension<Login>, Path(server): Path<String>, Json(body): Json<ConsoleBody>) -> Response {
+	gw.run(&login, NetCommand::ServerCommand(server, ServerCommand::Console(body.command))).await
+}
+
+#[derive(Debug, Deserialize)]
+struct ModsQuery {
+	per_page: Option<u64>,
+	page: Option<u64>,
+}
+
+async fn list_mods(State(gw): State<Gateway>, Extension(login): Extension<Login>, Path(server): Path<String>, Query(query): Query<ModsQuery>) -> Response {
+	let cmd = ServerCommand::ListMods(query.per_page.unwrap_or(20), query.page.unwrap_or(0));
+	gw.run(&login, NetCommand::ServerCommand(server, cmd)).await
+}
+
+async fn query_mod(State(gw): State<Gateway>, Extension(login): Extension<Login>, Path((server, mod_id)): Path<(String, String)>) -> Response {
+	gw.run(&login, NetCommand::ServerCommand(server, ServerCommand::QueryMod(mod_id))).await
+}
+
+#[derive(Debug, Deserialize)]
+struct ModFileBody {
+	path: String,
+	name: Option<String>,
+}
+
+impl ModFileBody {
+	fn into_parts(self) -> Result<(String, String)> {
+		let name = match self.name {
+			Some(name) => name,
+			None => PathBuf::from(&self.path)
+				.file_name()
+				.context("Path has no file name")?
+				.to_string_lossy()
+				.to_string(),
+		};
+		Ok((self.path, name))
+	}
+}
+
+async fn install_mod(State(gw): State<Gateway>, Extension(login): Extension<Login>, Path(server): Path<String>, Json(body): Json<ModFileBody>) -> Response {
+	match body.into_parts() {
+		Ok((path, name)) => gw.run(&login, NetCommand::ServerCommand(server, ServerCommand::InstallMod(path, name))).await,
+		Err(err) => bad_request(err),
+	}
+}
+
+async fn update_mod(State(gw): State<Gateway>, Extension(login): Extension<Login>, Path(server): Path<String>, Json(body): Json<ModFileBody>) -> Response {
+	match body.into_parts() {
+		Ok((path, name)) => gw.run(&login, NetCommand::ServerCommand(server, ServerCommand::UpdateMod(path, name))).await,
+		Err(err) => bad_request(err),
+	}
+}
+
+async fn uninstall_mod(State(gw): State<Gateway>, Extension(login): Extension<Login>, Path((server, mod_id)): Path<(String, String)>) -> Response {
+	gw.run(&login, NetCommand::ServerCommand(server, ServerCommand::UninstallMod(mod_id))).await
+}
+
+#[derive(Debug, Deserialize)]
+struct DepsBody {
+	mode: String,
+	jars: Vec<String>,
+}
+
+async fn resolve_deps(State(gw): State<Gateway>, Extension(login): Extension<Login>, Path(server): Path<String>, Json(body): Json<DepsBody>) -> Response {
+	let mode = match body.mode.as_str() {
+		"install" => DepResolveMode::Installation,
+		"remove" => DepResolveMode::Removal,
+		"update" => DepResolveMode::Update,
+		any => return bad_request(anyhow!("Unknown mode {any:?}, expected install, remove or update")),
+	};
+
+	// Parsing opens the jars, keep that off the runtime
+	let uploads = gw.config.with_config(|conf| conf.uploads.clone());
+	let mods = tokio::task::spawn_blocking(move || -> Result<_> {
+		let uploads = uploads.context("No upload directory configured")?;
+		let uploads = expanduser::expanduser(&uploads)
+			.and_then(|x| x.canonicalize())
+			.context("Upload directory is unavailable")?;
+
+		let mut mods = Vec::with_capacity(body.jars.len());
+		for jar in body.jars {
+			// Only the client's own name for it goes into errors, never where it resolved to
+			let path = uploads.join(&jar)
+				.canonicalize()
+				.ok()
+				.filter(|path| path.starts_with(&uploads))
+				.with_context(|| format!("No {jar:?} in the upload directory"))?;
+			mods.extend(parse_mods(&path).map_err(|_| anyhow!("Failed to parse {jar:?}"))?);
+		}
+		Ok(mods)
+	}).await;
+
+	match mods {
+		Ok(Ok(mods)) => gw.run(&login, NetCommand::ServerCommand(server, ServerCommand::ResolveDeps(mode, mods))).await,
+		Ok(Err(err)) => bad_request(err),
+		Err(err) => bad_request(anyhow!(err)),
+	}
+}
+
+#[derive(Debug, Deserialize)]
+struct NotificationsQuery {
+	after: Option<u64>,
+}
+
+async fn notifications(State(gw): State<Gateway>, Extension(login): Extension<Login>, Query(query): Query<NotificationsQuery>) -> Response {
+	let cmd = match query.after {
+		Some(id) => NetCommand::NotificationsAfter(id),
+		None => NetCommand::Notifications,
+	};
+	gw.run(&login, cmd).await
+}
+
+async fn openapi_json() -> Json<serde_json::Value> {
+	Json(openapi::spec())
+}
+
+// sv_gateway add-token <account> [scope=rw]...
+// The password comes from $SV_GATEWAY_PASSWORD or the first line of stdin, never argv where `ps` can see it
+fn add_token(config: &Config<SVGateway>, mut args: impl Iterator<Item = String>) -> Result<()> {
+	let account = args.next().context("Missing account")?;
+	let password = match env::var("SV_GATEWAY_PASSWORD") {
+		Ok(password) => password,
+		Err(_) => {
+			let mut line = String::new();
+			std::io::stdin().lock().read_line(&mut line).context("Failed to read the password from stdin")?;
+			line.trim_end_matches(['\r', '\n']).to_owned()
+		}
+	};
+	if password.is_empty() {
+		bail!("Missing password");
+	}
+	let mut token = Token::new(account, hash_pw(&password));
+
+	for grant in args {
+		let (scope, access) = grant.split_once('=').with_context(|| format!("Expected scope=access, got {grant:?}"))?;
+		let scope = match scope {
+			"servers" => Scope::Servers,
+			"mods" => Scope::Mods,
+			"notifications" => Scope::Notifications,
+			any => bail!("Unknown scope {any:?}"),
+		};
+		let mut granted = Access::NONE;
+		for c in access.chars() {
+			match c {
+				'r' => granted |= Access::Read,
+				'w' => granted |= Access::Write,
+				any => bail!("Unknown access {any:?}, expected r and/or w"),
+			}
+		}
+		token.grant(scope, granted);
+	}
+
+	let uuid = Uuid::new_v4();
+	config.with_config_mut(|conf| conf.api_tokens.insert(uuid, token))?;
+	println!("{uuid}");
+	Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+	let _lock = config::acquire_lock()?;
+	let config: Config<SVGateway> = Config::init(CONFIG)
+		.context("Failed to load configuration")?;
+
+	let mut args = env::args().skip(1);
+	if let Some(arg) = args.next() {
+		return match arg.as_str() {
+			"add-token" => add_token(&config, args),
+			any => bail!("Unknown argument {any:?}"),
+		};
+	}
+
+	// Tokens added or revoked by hand take effect without a restart
+	config.watch(Duration::from_secs(5));
+	config.on_reload(|conf| println!("Reloaded configuration, {} tokens", conf.api_tokens.len()));
+
+	let (listen, pub_key, tls) = config.with_config(|conf| (conf.listen.clone(), conf.pub_key.clone(), conf.tls.clone()));
+	let pub_key = std::fs::read(&pub_key)
+		.with_context(|| format!("Failed to read sv_manage's public key {pub_key:?}"))?;
+
+	let gw = Gateway {
+		config,
+		pub_key: Arc::new(pub_key),
+		clients: Arc::new(Mutex::new(HashMap::new())),
+	};
+	let router = router(gw);
+
+	match tls {
+		Some(tls) => {
+			let tls = OpenSSLConfig::from_pem_file(&tls.cert, &tls.key)
+				.context("Failed to load the TLS certificate")?;
+			axum_server::bind_openssl(listen.parse().context("Invalid listen address")?, tls)
+				.serve(router.into_make_service())
+				.await?;
+		}
+		None => {
+			let listener = TcpListener::bind(&listen).await?;
+			axum::serve(listener, router.into_make_service()).await?;
+		}
+	}
+
+	Ok(())
+}
+
+fn router(gw: Gateway) -> Router {
+	Router::new()
+		.route("/openapi.json", get(openapi_json))
+		.route("/servers", get(list_servers))
+		.route("/servers/:server", get(server_status))
+		.route("/servers/:server/start", server_action(ServerCommand::Start))
+		.route("/servers/:server/stop", server_action(ServerCommand::Stop))
+		.route("/servers/:server/reboot", server_action(ServerCommand::Reboot))
+		.route("/servers/:server/backup", server_action(ServerCommand::Backup))
+		.route("/servers/:server/restore", server_action(ServerCommand::Restore))
+		.route("/servers/:server/console", post(console))
+		.route("/servers/:server/mods", get(list_mods).post(install_mod).put(update_mod))
+		.route("/servers/:server/mods/:mod_id", get(query_mod).delete(uninstall_mod))
+		.route("/servers/:server/zip", server_action(ServerCommand::GenerateModsZip))
+		.route("/servers/:server/deps", post(resolve_deps))
+		.route("/servers/:server/updates", get(check_updates))
+		.route("/notifications", get(notifications))
+		.route_layer(from_fn_with_state(gw.clone(), auth))
+		.with_state(gw)
+}
+
+#[cfg(test)]
+mod tests {
+	use axum::body::Body;
+	use axum::http::Request;
+	use tower::ServiceExt;
+	use yapper::testing::TempDir;
+	use super::*;
+
+	// Nothing listens on sv_manage's address, so only requests that never get that far work
+	fn gateway(dir: &TempDir, tokens: &[(Uuid, Token)]) -> Result<Gateway> {
+		let path = dir.path().join(CONFIG);
+		let config: Config<SVGateway> = Config::init(path.to_str().context("Temp dir isn't utf-8")?)?;
+		config.with_config_mut(|conf| {
+			conf.sv_manage = "127.0.0.1:1".to_owned();
+			conf.uploads = Some(dir.path().join("uploads").to_string_lossy().to_string());
+			conf.api_tokens.extend(tokens.iter().cloned());
+		})?;
+		std::fs::create_dir_all(dir.path().join("uploads"))?;
+		Ok(Gateway {
+			config,
+			pub_key: Arc::new(Vec::new()),
+			clients: Arc::new(Mutex::new(HashMap::new())),
+		})
+	}
+
+	fn token(grants: &[(Scope, Access)]) -> (Uuid, Token) {
+		let mut token = Token::new("test".to_owned(), hash_pw("hunter2"));
+		for (scope, access) in grants {
+			token.grant(*scope, *access);
+		}
+		(Uuid::new_v4(), token)
+	}
+
+	async fn send(gw: &Gateway, method: &str, path: &str, token: Option<Uuid>, body: &str) -> StatusCode {
+		let mut req = Request::builder()
+			.method(method)
+			.uri(path)
+			.header("content-type", "application/json");
+		if let Some(token) = token {
+			req = req.header(AUTHORIZATION, format!("Bearer {token}"));
+		}
+		router(gw.clone())
+			.oneshot(req.body(Body::from(body.to_owned())).unwrap())
+			.await
+			.unwrap()
+			.status()
+	}
+
+	#[tokio::test]
+	async fn auth_by_scope() -> Result<()> {
+		let dir = TempDir::new("sv_gateway")?;
+		let (reader, reader_token) = token(&[(Scope::Mods, Access::Read)]);
+		let gw = gateway(&dir, &[(reader, reader_token)])?;
+
+		assert_eq!(send(&gw, "GET", "/openapi.json", None, "").await, StatusCode::OK);
+		assert_eq!(send(&gw, "GET", "/servers", None, "").await, StatusCode::UNAUTHORIZED);
+		assert_eq!(send(&gw, "GET", "/servers", Some(Uuid::new_v4()), "").await, StatusCode::UNAUTHORIZED);
+		// Wrong scope, then the right scope with only read access
+		assert_eq!(send(&gw, "GET", "/servers", Some(reader), "").await, StatusCode::FORBIDDEN);
+		assert_eq!(send(&gw, "POST", "/servers/alpha/deps", Some(reader), "").await, StatusCode::FORBIDDEN);
+		Ok(())
+	}
+
+	#[tokio::test]
+	async fn deps_stay_in_uploads() -> Result<()> {
+		let dir = TempDir::new("sv_gateway")?;
+		let (writer, writer_token) = token(&[(Scope::Mods, Access::Read | Access::Write)]);
+		let gw = gateway(&dir, &[(writer, writer_token)])?;
+		std::fs::write(dir.path().join("outside.jar"), b"not a jar")?;
+
+		let body = |jar: &str| format!(r#"{{ "mode": "install", "jars": [{jar:?}] }}"#);
+		assert_eq!(send(&gw, "POST", "/servers/alpha/deps", Some(writer), &body("../outside.jar")).await, StatusCode::BAD_REQUEST);
+		assert_eq!(send(&gw, "POST", "/servers/alpha/deps", Some(writer), &body("/etc/passwd")).await, StatusCode::BAD_REQUEST);
+		assert_eq!(send(&gw, "POST", "/servers/alpha/deps", Some(writer), &body("missing.jar")).await, StatusCode::BAD_REQUEST);
+		Ok(())
+	}
+
+	#[test]
+	fn statuses() {
+		use yapper::Response;
+
+		assert_eq!(status_of(&Response::Ok), StatusCode::OK);
+		assert_eq!(status_of(&Response::UnknownServer), StatusCode::NOT_FOUND);
+		assert_eq!(status_of(&Response::NoSuchMod), StatusCode::NOT_FOUND);
+		assert_eq!(status_of(&Response::ModConflict), StatusCode::CONFLICT);
+		assert_eq!(status_of(&Response::DuplicateMod("a.jar".to_owned())), StatusCode::CONFLICT);
+		assert_eq!(status_of(&Response::Err), StatusCode::INTERNAL_SERVER_ERROR);
+		assert_eq!(status_of(&Response::error_kind(ErrorKind::InvalidInput, &anyhow!("bad"))), StatusCode::BAD_REQUEST);
+		assert_eq!(status_of(&Response::error_kind(ErrorKind::Timeout, &anyhow!("slow"))), StatusCode::GATEWAY_TIMEOUT);
+	}
+}
@@ -0,0 +1,300 @@
+use serde_json::{json, Map, Value};
+use crate::config::{Access, Scope};
+
+pub struct Endpoint {
+	pub method: &'static str,
+	// In axum syntax, `:param` for path parameters
+	pub path: &'static str,
+	// `None` for endpoints anyone can call
+	pub auth: Option<(Scope, Access)>,
+	pub summary: &'static str,
+	pub query: &'static [Field],
+	pub body: &'static [Field],
+}
+
+pub struct Field {
+	pub name: &'static str,
+	pub ty: &'static str,
+	pub required: bool,
+}
+
+const fn field(name: &'static str, ty: &'static str) -> Field {
+	Field { name, ty, required: true }
+}
+
+const fn optional(name: &'static str, ty: &'static str) -> Field {
+	Field { name, ty, required: false }
+}
+
+const fn endpoint(method: &'static str, path: &'static str, auth: Option<(Scope, Access)>, summary: &'static str) -> Endpoint {
+	Endpoint { method, path, auth, summary, query: &[], body: &[] }
+}
+
+const SERVERS_READ: Option<(Scope, Access)> = Some((Scope::Servers, Access::Read));
+const SERVERS_WRITE: Option<(Scope, Access)> = Some((Scope::Servers, Access::Write));
+const MODS_READ: Option<(Scope, Access)> = Some((Scope::Mods, Access::Read));
+const MODS_WRITE: Option<(Scope, Access)> = Some((Scope::Mods, Access::Write));
+const MOD_FILE: &[Field] = &[field("path", "string"), optional("name", "string")];
+
+// Every route has to be in here: the auth layer refuses anything it can't find
+pub static ENDPOINTS: &[Endpoint] = &[
+	endpoint("get", "/openapi.json", None, "This document"),
+	endpoint("get", "/servers", SERVERS_READ, "List all servers"),
+	endpoint("get", "/servers/:server", SERVERS_READ, "Status of a server"),
+	endpoint("post", "/servers/:server/start", SERVERS_WRITE, "Start a server"),
+	endpoint("post", "/servers/:server/stop", SERVERS_WRITE, "Stop a server"),
+	endpoint("post", "/servers/:server/reboot", SERVERS_WRITE, "Reboot a server"),
+	endpoint("post", "/servers/:server/backup", SERVERS_WRITE, "Back up an idle server"),
+	endpoint("post", "/servers/:server/restore", SERVERS_WRITE, "Restore the last backup of an idle server"),
+	Endpoint {
+		body: &[field("command", "string")],
+		..endpoint("post", "/servers/:server/console", SERVERS_WRITE, "Run a console command")
+	},
+	Endpoint {
+		query: &[optional("per_page", "integer"), optional("page", "integer")],
+		..endpoint("get", "/servers/:server/mods", MODS_READ, "List installed mods")
+	},
+	endpoint("get", "/servers/:server/mods/:mod_id", MODS_READ, "Details of an installed mod"),
+	Endpoint {
+		body: MOD_FILE,
+		..endpoint("post", "/servers/:server/mods", MODS_WRITE, "Install a mod jar from a path sv_manage can read")
+	},
+	Endpoint {
+		body: MOD_FILE,
+		..endpoint("put", "/servers/:server/mods", MODS_WRITE, "Update a mod from a path sv_manage can read")
+	},
+	endpoint("delete", "/servers/:server/mods/:mod_id", MODS_WRITE, "Uninstall a mod"),
+	endpoint("post", "/servers/:server/zip", MODS_WRITE, "Package the mods folder, the link arrives as a notification"),
+	Endpoint {
+		body: &[field("mode", "string"), field("jars", "array")],
+		..endpoint("post", "/servers/:server/deps", MODS_WRITE, "Check the dependencies of mod jars in the gateway's upload directory, `jars` are relative to it. `mode` is install, remove or update")
+	},
+	endpoint("get", "/servers/:server/updates", MODS_READ, "Installed mods with newer versions in their update JSON"),
+	Endpoint {
+		query: &[optional("after", "integer")],
+		..endpoint("get", "/notifications", Some((Scope::Notifications, Access::Read)), "Notifications since the last call, or the persistent log after the id in `after`")
+	},
+];
+
+pub fn find(method: &str, path: &str) -> Option<&'static Endpoint> {
+	ENDPOINTS.iter().find(|x| x.method.eq_ignore_ascii_case(method) && x.path == path)
+}
+
+fn schema(ty: &str) -> Value {
+	match ty {
+		"array" => json!({ "type": "array", "items": { "type": "string" } }),
+		any => json!({ "type": any }),
+	}
+}
+
+fn object(fields: &[Field]) -> Value {
+	let mut properties = Map::new();
+	for field in fields {
+		properties.insert(field.name.to_owned(), schema(field.ty));
+	}
+	let required: Vec<_> = fields.iter().filter(|x| x.required).map(|x| x.name).collect();
+	json!({ "type": "object", "properties": properties, "required": required })
+}
+
+pub fn spec() -> Value {
+	let mut paths = Map::new();
+	for endpoint in ENDPOINTS {
+		let mut parameters = Vec::new();
+		for segment in endpoint.path.split('/') {
+			if let Some(name) = segment.strip_prefix(':') {
+				parameters.push(json!({ "name": name, "in": "path", "required": true, "schema": { "type": "string" } }));
+			}
+		}
+		for field in endpoint.query {
+			parameters.push(json!({ "name": field.name, "in": "query", "required": field.required, "schema": schema(field.ty) }));
+		}
+
+		let mut operation = json!({
+			"summary": endpoint.summary,
+			"parameters": parameters,
+			"responses": {
+				"default": {
+					"description": "What sv_manage answered, the HTTP status follows its meaning",
+					"content": { "application/json": { "schema": { "$ref": "#/components/schemas/Response" } } },
+				},
+			},
+		});
+		if !endpoint.body.is_empty() {
+			operation["requestBody"] = json!({
+				"required": true,
+				"content": { "application/json": { "schema": object(endpoint.body) } },
+			});
+		}
+		if let Some((scope, access)) = endpoint.auth {
+			operation["security"] = json!([{ "token": [] }]);
+			operation["description"] = json!(format!("Needs {access:?} access to {scope:?}"));
+			operation["responses"]["401"] = json!({ "description": "Missing or malformed token" });
+			operation["responses"]["403"] = json!({ "description": "Token lacks the scope" });
+		}
+
+		// OpenAPI wants `{param}` where axum wants `:param`
+		let path = endpoint.path
+			.split('/')
+			.map(|segment| match segment.strip_prefix(':') {
+				Some(name) => format!("{{{name}}}"),
+				None => segment.to_owned(),
+			})
+			.collect::<Vec<_>>()
+			.join("/");
+		let entry = paths.entry(path).or_insert_with(|| json!({}));
+		entry[endpoint.method] = operation;
+	}
+
+	json!({
+		"openapi": "3.0.3",
+		"info": { "title": "sv_gateway", "version": env!("CARGO_PKG_VERSION") },
+		"paths": paths,
+		"components": {
+			"securitySchemes": {
+				"token": { "type": "http", "scheme": "bearer", "bearerFormat": "uuid" },
+			},
+			"schemas": schemas(),
+		},
+	})
+}
+
+// Mirrors yapper::json
+fn schemas() -> Value {
+	json!({
+		"Response": {
+			"type": "object",
+			"required": ["type"],
+			"properties": {
+				"type": {
+					"type": "string",
+					"enum": ["ok", "error", "unknown_server", "invalid_state", "no_backup", "status", "list", "command_output",
+						"notifications", "mod_conflict", "no_such_mod", "mods", "mod", "dep_unsatisfied", "dep_satisfied", "unsupported",
+						"notification_log", "updates", "duplicate_mod", "mods_removed"],
+				},
+				"kind": { "type": "string" },
+				"message": { "type": "string" },
+				"context": { "type": "array", "items": { "type": "string" } },
+				"server": { "$ref": "#/components/schemas/ServerStatus" },
+				"servers": { "type": "array", "items": { "$ref": "#/components/schemas/ServerStatus" } },
+				"output": { "type": "string" },
+				"notifications": { "type": "array", "items": { "$ref": "#/components/schemas/Notification" } },
+				"mods": { "type": "array", "items": { "$ref": "#/components/schemas/ModInfo" } },
+				"more": { "type": "boolean" },
+				"mod": { "$ref": "#/components/schemas/ModInfo" },
+				"deps": { "type": "array", "items": { "type": "object" } },
+				"missing": { "type": "string" },
+				"entries": { "type": "array", "items": { "$ref": "#/components/schemas/NotificationEntry" } },
+				"updates": { "type": "array", "items": { "type": "object" } },
+				"filename": { "type": "string" },
+				"mod_ids": { "type": "array", "items": { "type": "string" }, "description": "Other mods in the same jar that were removed too" },
+			},
+		},
+		"NotificationEntry": {
+			"type": "object",
+			"properties": {
+				"id": { "type": "integer" },
+				"time": { "type": "integer", "description": "Unix seconds, UTC" },
+				"severity": { "type": "string", "enum": ["Info", "Warning", "Error"] },
+				"notification": { "$ref": "#/components/schemas/Notification" },
+			},
+		},
+		"ServerStatus": {
+			"type": "object",
+			"properties": {
+				"name": { "type": "string" },
+				"path": { "type": "string" },
+				"status": { "type": "string" },
+			},
+		},
+		"ModInfo": {
+			"type": "object",
+			"properties": {
+				"mod_id": { "type": "string" },
+				"name": { "type": "string", "nullable": true },
+				"filename": { "type": "string" },
+				"version": { "type": "string" },
+				"description": { "type": "string", "nullable": true },
+				"url": { "type": "string", "nullable": true },
+				"credits": { "type": "string", "nullable": true },
+				"authors": { "type": "array", "items": { "type": "string" }, "nullable": true },
+				"has_logo": { "type": "boolean" },
+				"dependencies": { "type": "array", "items": { "$ref": "#/components/schemas/ModDependency" } },
+				"provides": {
+					"type": "array",
+					"items": {
+						"type": "object",
+						"properties": {
+							"mod_id": { "type": "string" },
+							"version": { "type": "string" },
+						},
+					},
+				},
+				"loader": { "type": "string", "nullable": true },
+				"loader_version": { "type": "string", "nullable": true },
+				"license": { "type": "string", "nullable": true },
+				"issue_tracker": { "type": "string", "nullable": true },
+				"update_json": { "type": "string", "nullable": true },
+				"display_test": { "type": "string", "nullable": true },
+				"client_side_only": { "type": "boolean" },
+				"hashes": {
+					"type": "object",
+					"nullable": true,
+					"properties": {
+						"sha1": { "type": "string" },
+						"sha512": { "type": "string" },
+						"murmur2": { "type": "integer", "description": "CurseForge fingerprint" },
+					},
+				},
+			},
+		},
+		"ModDependency": {
+			"type": "object",
+			"properties": {
+				"mod_id": { "type": "string" },
+				"kind": { "type": "string", "enum": ["Optional", "Required", "Incompatible", "Discouraged", "Recommended"] },
+				"mandatory": { "type": "boolean" },
+				"range": { "type": "string", "description": "Maven version range" },
+				"intervals": {
+					"type": "array",
+					"items": {
+						"type": "object",
+						"properties": {
+							"min_version": { "$ref": "#/components/schemas/Bound" },
+							"max_version": { "$ref": "#/components/schemas/Bound" },
+						},
+					},
+				},
+				"side": { "type": "string" },
+				"ordering": { "type": "string", "nullable": true },
+				"alternatives": {
+					"type": "array",
+					"description": "Other mods that satisfy the dependency just as well",
+					"items": {
+						"type": "object",
+						"properties": {
+							"mod_id": { "type": "string" },
+							"range": { "type": "string" },
+						},
+					},
+				},
+			},
+		},
+		"Bound": {
+			"type": "object",
+			"nullable": true,
+			"description": "Null when unbounded",
+			"properties": {
+				"version": { "type": "string" },
+				"inclusive": { "type": "boolean" },
+			},
+		},
+		"Notification": {
+			"type": "object",
+			"properties": {
+				"kind": { "type": "string" },
+				"server": { "type": "string" },
+				"details": { "type": "object" },
+			},
+		},
+	})
+}
@@ -1,207 +1,401 @@
-use std::mem::{MaybeUninit, replace};
+use std::collections::HashSet;
 use std::net::TcpStream;
-use std::process;
-use std::sync::Arc;
-use std::sync::atomic::Ordering;
+use std::thread;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use anyhow::{anyhow, Result};
-use ende::{BinSettings, BitWidth, Context, Encoder, NumEncoding, SizeRepr, VariantRepr};
-use ende::io::Std;
-use openssl::rsa::{Padding, Rsa};
-use yapper::{LoginPacket, LoginResponse, NetCommand, recv_packet, Response, ServerCommand, ServerStatus, Status};
-use yapper::conf::Config;
-use crate::config::{SVManage};
+use yapper::{Capabilities, Crypt, CryptRecv, CryptSend, dispatch_debug, ErrorKind, HelloPacket, HelloResponse, fake_salt, KDF_ITERATIONS, LoginChallenge, login_nonce, LoginPacket, LoginProof, LoginResponse, NetCommand, NotificationFilter, protocol_context, recv_packet, recv_thing, Response, send_thing, server_handshake, ServerCommand, ServerStatus, SESSION_TIMEOUT, SessionEvent, SessionPacket, Status};
 use crate::ctxt::Ctxt;
-use crate::server_loop::{Command, get_notifs, NOTIFICATIONS, Server};
-
-const KEY_PEM: &[u8] = include_bytes!("../sv_manage_private.pem");
+use crate::server_loop::{Command, get_notifs, notif_cursor, notifs_after, wait_notifs};
+use crate::updates;
 
 pub fn client_loop(client: TcpStream, ctx: Arc<Ctxt>) -> Result<()> {
-	let mut ctxt = Context::new()
-		.settings(BinSettings::new()
-			.variant_repr(VariantRepr::new()
-				.bit_width(BitWidth::Bit8))
-			.size_repr(SizeRepr::new()
-				.num_encoding(NumEncoding::Leb128)));
-	let mut encoder = Encoder::new(Std::new(client), ctxt);
+	let ctxt = protocol_context();
 
 	// Oh boy
 
 	// Expect an aes key encrypted with our public key
-	let buffer: [u8; 256] = encoder.decode_value()?;
-	let key = Rsa::private_key_from_pem(KEY_PEM)?;
-
-	let mut decrypted = [0u8; 256];
-	key.private_decrypt(&buffer, &mut decrypted, Padding::PKCS1)?;
+	let (mut client, mut crypt) = server_handshake(client, &ctx.priv_key, ctxt)?;
 
-	// We got the key and iv
-	let aes = &decrypted[..16];
-	
-	let mut client = encoder.finish().0.into_inner();
+	// Nobody is trusted until they log in
+	let limits = ctx.config.with_config(|conf| conf.limits.clone());
+	crypt.set_limit(limits.pre_login);
 
 	// Packet exchange here
 
-	let mut account_name = None;
-	recv_packet(&mut client, &aes, ctxt, |login: LoginPacket| {
-		ctx.config.with_config(|conf| {
-			if let Some(account) = conf.accounts.get(&login.user) && account.password == login.password {
-				account_name = Some(login.user);
-				Ok(LoginResponse::Ok)
-			} else {
-				Err((
-					anyhow!(r#"Wrong credentials {:?}:{:?}"#, login.user, login.password),
-					LoginResponse::WrongCredentials)
-				)
+	let mut version = 0;
+	let mut capabilities = Capabilities::empty();
+	recv_packet(&mut client, &mut crypt, ctxt, |hello: HelloPacket| {
+		match HelloPacket::ours().negotiate(&hello) {
+			HelloResponse::Ok { version: negotiated_version, capabilities: negotiated } => {
+				version = negotiated_version;
+				capabilities = negotiated;
+				Ok(HelloResponse::Ok { version, capabilities: negotiated })
 			}
-		})
+			incompatible => Err((
+				anyhow!("Client speaks protocol {}..={}, we don't", hello.min_version, hello.version),
+				incompatible,
+			)),
+		}
+	})?;
+	// The answer went out in the hello's fixed layout, everything after is in the negotiated one
+	crypt.set_version(version);
+
+	let mut login = None;
+	recv_packet(&mut client, &mut crypt, ctxt, |packet: LoginPacket| {
+		let credentials = ctx.config.with_config(|conf| {
+			conf.accounts.get(&packet.user).and_then(|account| account.credentials.clone())
+		});
+
+		let result: Result<LoginChallenge> = try {
+			let nonce = login_nonce()?;
+			let challenge = match &credentials {
+				Some(credentials) => LoginChallenge {
+					salt: credentials.salt,
+					iterations: credentials.iterations,
+					nonce,
+				},
+				// Play along so unknown users look the same as wrong passwords,
+				// with a salt that stays the same between attempts like a real one
+				None => LoginChallenge {
+					salt: fake_salt(&ctx.priv_key, &packet.user)?,
+					iterations: KDF_ITERATIONS,
+					nonce,
+				},
+			};
+			login = Some((packet.user, packet.nonce, nonce, credentials));
+			challenge
+		};
+		result.map_err(|err| (err, LoginChallenge {
+			salt: [0; 16],
+			iterations: 0,
+			nonce: [0; 16],
+		}))
+	})?;
+	let (user, client_nonce, server_nonce, credentials) = login.unwrap();
+
+	let mut account_name = None;
+	recv_packet(&mut client, &mut crypt, ctxt, |packet: LoginProof| {
+		let verified = match &credentials {
+			Some(credentials) => credentials.verify(&user, &client_nonce, &server_nonce, &packet.proof)
+				.map_err(|err| (err, LoginResponse::WrongCredentials))?,
+			None => false,
+		};
+
+		if verified {
+			account_name = Some(user.clone());
+			Ok(LoginResponse::Ok)
+		} else {
+			Err((
+				anyhow!(r#"Wrong credentials for {:?}"#, user),
+				LoginResponse::WrongCredentials)
+			)
+		}
 	})?;
 	let account = account_name.unwrap();
-	
-	let ref servers = ctx.servers[&account];
-	
-	recv_packet(&mut client, &aes, ctxt, |command: NetCommand| {
-		match &command {
-			NetCommand::ListServers => {
-				let mut list = Vec::with_capacity(servers.len());
-				for server in servers.iter() {
-					list.push(ServerStatus {
-						name: server.name().to_owned(),
-						path: server.conf().with_config(|x| x.accounts[&account].servers[server.name()].path.clone()),
-						status: server.status(),
-					});
-				}
+	crypt.set_limit(limits.session);
+
+	let mut session = false;
+	recv_packet(&mut client, &mut crypt, ctxt, |command: NetCommand| {
+		check_capabilities(capabilities, &command)?;
+		if let NetCommand::Session = command {
+			session = true;
+			return Ok(Response::Ok);
+		}
+		process_command(&ctx, &account, &command)
+			.map(|resp| resp.downgrade(version, capabilities))
+			.map_err(|(err, resp)| (err, resp.downgrade(version, capabilities)))
+	})?;
+
+	if session {
+		session_loop(client, crypt, ctx, account, capabilities)?;
+	}
+
+	Ok(())
+}
+
+// Commands the client didn't say it understands would have to be answered
+// with something it can't decode, so refuse them up front
+fn check_capabilities(capabilities: Capabilities, command: &NetCommand) -> core::result::Result<(), (anyhow::Error, Response)> {
+	let missing = command.required_capabilities() - capabilities;
+	if !missing.is_empty() {
+		return Err((
+			anyhow!("Command needs capabilities that weren't negotiated: {missing:?}"),
+			Response::Unsupported(missing),
+		))
+	}
+	Ok(())
+}
+
+fn session_loop(client: TcpStream, crypt: Crypt, ctx: Arc<Ctxt>, account: String, capabilities: Capabilities) -> Result<()> {
+	let (send, mut recv) = crypt.split();
+
+	// A silent client (not even keepalives) is considered gone
+	let mut reader = client.try_clone()?;
+	reader.set_read_timeout(Some(SESSION_TIMEOUT))?;
+	let writer = Arc::new(Mutex::new((client, send)));
+	let subscriptions = Arc::new(Mutex::new(HashSet::new()));
+
+	let result = session_packets(&mut reader, &mut recv, &writer, &subscriptions, &ctx, &account, capabilities);
+
+	// Stops the subscriber threads
+	subscriptions.lock().unwrap().clear();
+	result
+}
+
+fn session_packets(reader: &mut TcpStream, recv: &mut CryptRecv, writer: &SessionWriter, subscriptions: &Arc<Mutex<HashSet<u64>>>, ctx: &Arc<Ctxt>, account: &str, capabilities: Capabilities) -> Result<()> {
+	let ctxt = protocol_context();
 
-				Ok(Response::List(list))
+	loop {
+		let packet: SessionPacket = recv_thing(reader, recv, ctxt)?;
+		if let SessionPacket::Request(id, command) = &packet && let Err((err, resp)) = check_capabilities(capabilities, command) {
+			dispatch_debug(err);
+			send_event(writer, SessionEvent::Response(*id, resp))?;
+			continue;
+		}
+
+		match packet {
+			SessionPacket::Request(id, NetCommand::Subscribe(filter)) => {
+				subscriptions.lock().unwrap().insert(id);
+
+				let account = account.to_owned();
+				let writer = writer.clone();
+				let subscriptions = subscriptions.clone();
+				let cursor = notif_cursor(&account);
+				send_event(&writer, SessionEvent::Response(id, Response::Ok))?;
+				thread::spawn(move || subscriber(id, filter, cursor, account, capabilities, writer, subscriptions));
 			}
-			NetCommand::ServerCommand(s, cmd) => {
-				if let Some(server) = servers.iter().find(|x| x.name() == s) {
-					let status = server.status();
-					match cmd {
-						ServerCommand::Start => {
-							server.start();
-							Ok(Response::Ok)
-						}
-						ServerCommand::Stop => {
-							server.stop();
-							Ok(Response::Ok)
-						}
-						ServerCommand::Status => {
-							Ok(Response::Status(ServerStatus {
-								name: server.name().to_owned(),
-								path: server.conf().with_config(|x| x.accounts[&account].servers[server.name()].path.clone()),
-								status: server.status()
-							}))
-						}
-						ServerCommand::Reboot => {
-							server.reboot();
-							Ok(Response::Ok)
-						}
-						ServerCommand::Console(cmd) => {
-							if status != Status::Running {
-								return Err((
-									anyhow!("Server not running, can't run command: {status}"),
-									Response::InvalidState,
-								))
-							}
-							
-							use anyhow::Context;
-							let x = server.send(Command::Console(cmd.clone()), Duration::from_secs(5))
-								.context("Failed to send command")
-								.map_err(|err| (err, Response::Err))?;
-							Ok(x)
-						}
-						ServerCommand::Backup => {
-							if status != Status::Idle {
-								return Err((
-									anyhow!("Server not idle, can't backup: {status}"),
-									Response::InvalidState,
-								))
-							}
-
-							use anyhow::Context;
-							let x = server.send(Command::Backup, Duration::from_secs(5))
-								.context("Failed to send command")
-								.map_err(|err| (err, Response::Err))?;
-							Ok(x)
-						}
-						ServerCommand::Restore => {
-							if status != Status::Idle {
-								return Err((
-									anyhow!("Server not idle, can't restore: {status}"),
-									Response::InvalidState,
-								))
-							}
-
-							use anyhow::Context;
-							let x = server.send(Command::Restore, Duration::from_secs(5))
-								.context("Failed to send command")
-								.map_err(|err| (err, Response::Err))?;
-							Ok(x)
-						}
-						ServerCommand::ListMods(per_page, pages) => {
-							use anyhow::Context;
-							let x = server.send(Command::ListMods(*per_page, *pages), Duration::from_secs(5))
-								.context("Failed to send command")
-								.map_err(|err| (err, Response::Err))?;
-							Ok(x)
-						}
-						ServerCommand::InstallMod(filename, preferred_name) => {
-							use anyhow::Context;
-							let x = server.send(Command::InstallMod(filename.clone(), preferred_name.clone()), Duration::from_secs(5))
-								.context("Failed to send command")
-								.map_err(|err| (err, Response::Err))?;
-							Ok(x)
-						}
-						ServerCommand::UninstallMod(mod_id) => {
-							use anyhow::Context;
-							let x = server.send(Command::UninstallMod(mod_id.clone()), Duration::from_secs(5))
-								.context("Failed to send command")
-								.map_err(|err| (err, Response::Err))?;
-							Ok(x)
-						}
-						ServerCommand::UpdateMod(filename, preferred_name) => {
-							use anyhow::Context;
-							let x = server.send(Command::UpdateMod(filename.clone(), preferred_name.clone()), Duration::from_secs(5))
-								.context("Failed to send command")
-								.map_err(|err| (err, Response::Err))?;
-							Ok(x)
-						}
-						ServerCommand::QueryMod(mod_id) => {
-							use anyhow::Context;
-							let x = server.send(Command::QueryMod(mod_id.clone()), Duration::from_secs(5))
-								.context("Failed to send command")
-								.map_err(|err| (err, Response::Err))?;
-							Ok(x)
+			SessionPacket::Unsubscribe(id) => {
+				subscriptions.lock().unwrap().remove(&id);
+			}
+			SessionPacket::Request(id, command) => {
+				// Every request gets its own thread so a slow command doesn't hold up the others
+				let ctx = ctx.clone();
+				let account = account.to_owned();
+				let writer = writer.clone();
+				let version = recv.version();
+				thread::spawn(move || {
+					let response = process_command(&ctx, &account, &command)
+						.unwrap_or_else(|(err, resp)| {
+							dispatch_debug(err);
+							resp
+						})
+						.downgrade(version, capabilities);
+
+					if let Err(err) = send_event(&writer, SessionEvent::Response(id, response)) {
+						dispatch_debug(err);
+					}
+				});
+			}
+			SessionPacket::Ping(seq) => {
+				send_event(writer, SessionEvent::Pong(seq))?;
+			}
+			SessionPacket::Close => break,
+		}
+	}
+
+	Ok(())
+}
+
+type SessionWriter = Arc<Mutex<(TcpStream, CryptSend)>>;
+
+fn send_event(writer: &SessionWriter, event: SessionEvent) -> Result<()> {
+	let mut writer = writer.lock().unwrap();
+	let (client, send) = &mut *writer;
+	send_thing(client, send, protocol_context(), event)
+}
+
+fn subscriber(id: u64, filter: NotificationFilter, mut cursor: u64, account: String, capabilities: Capabilities, writer: SessionWriter, subscriptions: Arc<Mutex<HashSet<u64>>>) {
+	// Wake up every now and then to notice when we've been unsubscribed
+	while subscriptions.lock().unwrap().contains(&id) {
+		let (notifs, next) = wait_notifs(&account, cursor, Duration::from_secs(1));
+		cursor = next;
+
+		// Leave out what the client couldn't decode
+		let notifs = notifs.into_iter()
+			.filter(|notif| filter.matches(notif))
+			.filter(|notif| capabilities.contains(notif.required_capabilities()));
+		for notif in notifs {
+			if send_event(&writer, SessionEvent::Notification(id, notif)).is_err() {
+				subscriptions.lock().unwrap().remove(&id);
+				return;
+			}
+		}
+	}
+}
+
+fn failed(err: anyhow::Error) -> (anyhow::Error, Response) {
+	let response = Response::error(&err);
+	(err, response)
+}
+
+fn failed_as(kind: ErrorKind, err: anyhow::Error) -> (anyhow::Error, Response) {
+	let response = Response::error_kind(kind, &err);
+	(err, response)
+}
+
+fn process_command(ctx: &Ctxt, account: &str, command: &NetCommand) -> core::result::Result<Response, (anyhow::Error, Response)> {
+	let ref servers = ctx.servers[account];
+
+	match &command {
+		NetCommand::ListServers => {
+			let mut list = Vec::with_capacity(servers.len());
+			for server in servers.iter() {
+				list.push(ServerStatus {
+					name: server.name().to_owned(),
+					path: server.conf().with_config(|x| x.accounts[account].servers[server.name()].path.clone()),
+					status: server.status(),
+				});
+			}
+
+			Ok(Response::List(list))
+		}
+		NetCommand::ServerCommand(s, cmd) => {
+			if let Some(server) = servers.iter().find(|x| x.name() == s) {
+				let status = server.status();
+				match cmd {
+					ServerCommand::Start => {
+						server.start();
+						Ok(Response::Ok)
+					}
+					ServerCommand::Stop => {
+						server.stop();
+						Ok(Response::Ok)
+					}
+					ServerCommand::Status => {
+						Ok(Response::Status(ServerStatus {
+							name: server.name().to_owned(),
+							path: server.conf().with_config(|x| x.accounts[account].servers[server.name()].path.clone()),
+							status: server.status()
+						}))
+					}
+					ServerCommand::Reboot => {
+						server.reboot();
+						Ok(Response::Ok)
+					}
+					ServerCommand::Console(cmd) => {
+						if status != Status::Running {
+							return Err(failed_as(
+								ErrorKind::InvalidState,
+								anyhow!("Server not running, can't run command: {status}"),
+							))
 						}
-						ServerCommand::GenerateModsZip => {
-							use anyhow::Context;
-							let x = server.send(Command::GenerateModsZip, Duration::from_secs(5))
-								.context("Failed to send command")
-								.map_err(|err| (err, Response::Err))?;
-							Ok(x)
+						
+						use anyhow::Context;
+						let x = server.send(Command::Console(cmd.clone()), Duration::from_secs(5))
+							.context("Failed to send command")
+							.map_err(failed)?;
+						Ok(x)
+					}
+					ServerCommand::Backup => {
+						if status != Status::Idle {
+							return Err(failed_as(
+								ErrorKind::InvalidState,
+								anyhow!("Server not idle, can't backup: {status}"),
+							))
 						}
-						ServerCommand::ResolveDeps(mode, new_mods) => {
-							use anyhow::Context;
-							let x = server.send(Command::ResolveDeps(*mode, new_mods.clone()), Duration::from_secs(5))
-								.context("Failed to send command")
-								.map_err(|err| (err, Response::Err))?;
-							Ok(x)
+
+						use anyhow::Context;
+						let x = server.send(Command::Backup, Duration::from_secs(5))
+							.context("Failed to send command")
+							.map_err(failed)?;
+						Ok(x)
+					}
+					ServerCommand::Restore => {
+						if status != Status::Idle {
+							return Err(failed_as(
+								ErrorKind::InvalidState,
+								anyhow!("Server not idle, can't restore: {status}"),
+							))
 						}
+
+						use anyhow::Context;
+						let x = server.send(Command::Restore, Duration::from_secs(5))
+							.context("Failed to send command")
+							.map_err(failed)?;
+						Ok(x)
+					}
+					ServerCommand::ListMods(per_page, pages) => {
+						use anyhow::Context;
+						let x = server.send(Command::ListMods(*per_page, *pages), Duration::from_secs(5))
+							.context("Failed to send command")
+							.map_err(failed)?;
+						Ok(x)
+					}
+					ServerCommand::InstallMod(filename, preferred_name) => {
+						use anyhow::Context;
+						let x = server.send(Command::InstallMod(filename.clone(), preferred_name.clone()), Duration::from_secs(5))
+							.context("Failed to send command")
+							.map_err(failed)?;
+						Ok(x)
+					}
+					ServerCommand::UninstallMod(mod_id) => {
+						use anyhow::Context;
+						let x = server.send(Command::UninstallMod(mod_id.clone()), Duration::from_secs(5))
+							.context("Failed to send command")
+							.map_err(failed)?;
+						Ok(x)
+					}
+					ServerCommand::UpdateMod(filename, preferred_name) => {
+						use anyhow::Context;
+						let x = server.send(Command::UpdateMod(filename.clone(), preferred_name.clone()), Duration::from_secs(5))
+							.context("Failed to send command")
+							.map_err(failed)?;
+						Ok(x)
+					}
+					ServerCommand::QueryMod(mod_id) => {
+						use anyhow::Context;
+						let x = server.send(Command::QueryMod(mod_id.clone()), Duration::from_secs(5))
+							.context("Failed to send command")
+							.map_err(failed)?;
+						Ok(x)
+					}
+					ServerCommand::GenerateModsZip => {
+						use anyhow::Context;
+						let x = server.send(Command::GenerateModsZip, Duration::from_secs(5))
+							.context("Failed to send command")
+							.map_err(failed)?;
+						Ok(x)
+					}
+					ServerCommand::ResolveDeps(mode, new_mods) => {
+						use anyhow::Context;
+						let x = server.send(Command::ResolveDeps(*mode, new_mods.clone()), Duration::from_secs(5))
+							.context("Failed to send command")
+							.map_err(failed)?;
+						Ok(x)
+					}
+					ServerCommand::CheckUpdates => {
+						let updates = server.check_updates(&ctx.fetcher, updates::REQUEST_DEADLINE)
+							.map_err(failed)?;
+						Ok(Response::Updates(updates))
 					}
-				} else {
-					Err((
-						anyhow!(r#"Unknown server {:?}"#, s),
-						Response::UnknownServer)
-					)
 				}
-			}
-			NetCommand::Notifications => {
-				let notifs = get_notifs(&account);
-				
-				Ok(Response::Notifications(notifs))
+			} else {
+				Err((
+					anyhow!(r#"Unknown server {:?}"#, s),
+					Response::UnknownServer)
+				)
 			}
 		}
-	})?;
-
-	Ok(())
-}
\ No newline at end of file
+		NetCommand::Notifications => {
+			let notifs = get_notifs(account);
+			
+			Ok(Response::Notifications(notifs))
+		}
+		NetCommand::NotificationsAfter(id) => {
+			Ok(Response::NotificationLog(notifs_after(account, *id)))
+		}
+		NetCommand::Session => {
+			Err(failed_as(
+				ErrorKind::InvalidState,
+				anyhow!("Already in session mode"),
+			))
+		}
+		NetCommand::Subscribe(_) => {
+			Err(failed_as(
+				ErrorKind::InvalidState,
+				anyhow!("Subscriptions are only available in session mode"),
+			))
+		}
+	}
+}
@@ -1,25 +1,135 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::time::SystemTime;
 use serde::{Deserialize, Serialize};
-use yapper::{hash_pw, Notification, ServerStatus};
+use serde_json::Value;
+use anyhow::Result;
+use yapper::{FrameLimit, hash_pw, NotificationEntry, ServerStatus, StoredCredentials};
+use yapper::conf::{Migration, Schema};
 
 pub const CONFIG: &str = "sv_manage.json";
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct SVManage {
 	pub port: u16,
-	pub accounts: HashMap<String, AccountData>
+	pub accounts: HashMap<String, AccountData>,
+	#[serde(default)]
+	pub limits: Limits,
+	#[serde(default)]
+	pub notifications: Retention,
+	#[serde(default)]
+	pub update_checks: UpdateChecks,
+}
+
+impl Schema for SVManage {
+	const MIGRATIONS: &'static [Migration] = &[
+		salt_passwords,
+		clear_notification_cache,
+	];
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Limits {
+	pub pre_login: FrameLimit,
+	pub session: FrameLimit,
+}
+
+impl Default for Limits {
+	fn default() -> Self {
+		Self {
+			pre_login: FrameLimit::PRE_LOGIN,
+			session: FrameLimit::SESSION,
+		}
+	}
+}
+
+// How much of each account's notification log is kept, in memory and on disk
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Retention {
+	pub max_entries: usize,
+	pub max_age_days: u64,
+}
+
+impl Default for Retention {
+	fn default() -> Self {
+		Self {
+			max_entries: 256,
+			max_age_days: 30,
+		}
+	}
+}
+
+impl Retention {
+	// Entries are oldest first
+	pub fn trim(&self, entries: &mut VecDeque<NotificationEntry>) {
+		let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs();
+		let oldest = now.saturating_sub(self.max_age_days * 24 * 60 * 60);
+		while entries.len() > self.max_entries || entries.front().is_some_and(|entry| entry.time < oldest) {
+			entries.pop_front();
+		}
+	}
+}
+
+// How often every server's mods are checked against their update JSON, 0 turns it off
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct UpdateChecks {
+	pub interval_minutes: u64,
+}
+
+impl Default for UpdateChecks {
+	fn default() -> Self {
+		Self {
+			interval_minutes: 24 * 60,
+		}
+	}
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct AccountData {
 	pub cache: Cache,
-	pub password: [u8; 32],
+	#[serde(default)]
+	pub credentials: Option<StoredCredentials>,
 	pub servers: HashMap<String, ServerConf>,
 }
 
+// 0 -> 1: bare sha256 passwords become salted credentials. The old hash is exactly
+// what clients use as their secret, so it can be fed straight into the KDF
+// without anyone having to pick a new password
+fn salt_passwords(conf: &mut Value) -> Result<()> {
+	use anyhow::Context;
+
+	let Some(accounts) = conf.get_mut("accounts").and_then(Value::as_object_mut) else { return Ok(()) };
+	for (name, account) in accounts.iter_mut() {
+		let Some(account) = account.as_object_mut() else { continue };
+		let Some(password) = account.remove("password") else { continue };
+		let password: [u8; 32] = serde_json::from_value(password)
+			.with_context(|| format!("Invalid password hash for {name:?}"))?;
+		let credentials = StoredCredentials::new(&password)?;
+		account.insert("credentials".to_owned(), serde_json::to_value(credentials)?);
+		println!("Migrated password of {name:?} to salted credentials");
+	}
+	Ok(())
+}
+
+// 1 -> 2: the notification cache used to hold bare notifications, but nothing ever
+// wrote to it. Start the new log from scratch rather than guess ids and times
+fn clear_notification_cache(conf: &mut Value) -> Result<()> {
+	let Some(accounts) = conf.get_mut("accounts").and_then(Value::as_object_mut) else { return Ok(()) };
+	for account in accounts.values_mut() {
+		if let Some(cache) = account.get_mut("cache").and_then(Value::as_object_mut) {
+			cache.insert("notifications".to_owned(), Value::Array(Vec::new()));
+		}
+	}
+	Ok(())
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct Cache {
-	notifications: Vec<Notification>,
+	// Where the notification log used to be persisted, it's moved to notifications/<account>.jsonl on startup
+	#[serde(default)]
+	pub notifications: Vec<NotificationEntry>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -5,12 +5,16 @@ use file_guard::FileGuard;
 use yapper::conf::Config;
 use crate::config::SVManage;
 use crate::server_loop::Server;
+use crate::updates::Fetcher;
 
 #[repr(C)]
 pub struct Ctxt {
 	pub check: usize,
+	// `None` when running in tests
 	#[allow(dead_code)]
-	pub lock: FileGuard<Box<File>>,
+	pub lock: Option<FileGuard<Box<File>>>,
+	pub priv_key: Vec<u8>,
 	pub config: Config<SVManage>,
 	pub servers: HashMap<String, Vec<Server>>,
+	pub fetcher: Arc<dyn Fetcher>,
 }
\ No newline at end of file
@@ -0,0 +1,116 @@
+#![feature(try_blocks)]
+#![feature(gen_blocks)]
+#![feature(let_chains)]
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::thread;
+use std::thread::sleep;
+use std::time::Duration;
+use file_guard::FileGuard;
+use yapper::conf::Config;
+use yapper::{dispatch_debug, Notification};
+use crate::client_loop::client_loop;
+use crate::config::SVManage;
+use crate::ctxt::Ctxt;
+use crate::server_loop::{push_notif, Server};
+use crate::updates::{Fetcher, HttpFetcher};
+
+pub mod config;
+pub mod ctxt;
+pub mod server_loop;
+mod client_loop;
+mod sv_fs;
+pub mod updates;
+#[cfg(feature = "testing")]
+pub mod testing;
+
+// Loads the notification log and starts up each server
+pub fn start(config: Config<SVManage>, priv_key: Vec<u8>, lock: Option<FileGuard<Box<File>>>) -> Arc<Ctxt> {
+	start_with(config, priv_key, lock, Arc::new(HttpFetcher::new()), None)
+}
+
+// Same as `start`, but update JSONs come from `fetcher`, and every `update_interval`
+// instead of `update_checks.interval_minutes` if there is one
+pub fn start_with(config: Config<SVManage>, priv_key: Vec<u8>, lock: Option<FileGuard<Box<File>>>, fetcher: Arc<dyn Fetcher>, update_interval: Option<Duration>) -> Arc<Ctxt> {
+	server_loop::init_notifs(&config);
+
+	let mut ctxt = Ctxt {
+		check: 42,
+		lock,
+		priv_key,
+		config,
+		servers: HashMap::new(),
+		fetcher,
+	};
+
+	ctxt.config.with_config(|x| {
+		for (account_name, account) in x.accounts.iter() {
+			let mut vec = Vec::new();
+			for (server_name, server) in account.servers.iter() {
+				let server = Server::new(account_name.clone(), server_name.clone(), ctxt.config.clone(), server);
+				vec.push(server);
+			}
+			ctxt.servers.insert(account_name.clone(), vec);
+		}
+	});
+
+	let ctxt = Arc::new(ctxt);
+	let ctxt2 = ctxt.clone();
+	thread::spawn(move || check_updates(ctxt2, update_interval));
+	ctxt
+}
+
+// Periodically reports outdated mods, but only when the list changes so it doesn't nag every day
+fn check_updates(ctx: Arc<Ctxt>, update_interval: Option<Duration>) {
+	let mut reported = HashMap::new();
+	loop {
+		let interval = match update_interval {
+			Some(interval) => interval,
+			None => {
+				let minutes = ctx.config.with_config(|x| x.update_checks.interval_minutes);
+				if minutes == 0 {
+					// Turned off, see if that changes
+					sleep(Duration::from_secs(60));
+					continue;
+				}
+				Duration::from_secs(minutes * 60)
+			}
+		};
+		sleep(interval);
+
+		for server in ctx.servers.values().flatten() {
+			let updates = match server.check_updates(&ctx.fetcher, updates::PERIODIC_DEADLINE) {
+				Ok(updates) => updates,
+				Err(err) => {
+					dispatch_debug(err.context(format!("Update check failed for {}", server.name())));
+					continue;
+				}
+			};
+			let key = (server.account().to_owned(), server.name().to_owned());
+			if reported.get(&key) == Some(&updates) {
+				continue;
+			}
+			if !updates.is_empty() {
+				push_notif(server.account(), Notification::ModsOutdated(server.name().to_owned(), updates.clone()));
+			}
+			reported.insert(key, updates);
+		}
+	}
+}
+
+pub fn serve(listener: TcpListener, ctx: Arc<Ctxt>) -> ! {
+	loop {
+		if let Ok((client, addr)) = listener.accept() {
+			let ctx = ctx.clone();
+			thread::spawn(move || {
+				if let Err(err) = client_loop(client, ctx) {
+					// Oversized frames, failed logins etc. all end up here
+					dispatch_debug(err.context(format!("Dropped client {addr}")));
+				}
+			});
+		}
+	}
+}
@@ -1,4 +1,4 @@
-use std::collections::{Bound, HashMap};
+use std::collections::{HashMap, VecDeque};
 use std::fs::{File, FileType};
 use std::{fs, io, process, thread};
 use std::ffi::OsStr;
@@ -8,7 +8,7 @@ use std::mem::replace;
 use std::path::{Path, PathBuf};
 use std::process::{Child, Stdio};
 use std::str::FromStr;
-use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::sync::{Arc, Condvar, Mutex, OnceLock, RwLock};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
 use std::thread::{JoinHandle, sleep};
@@ -30,14 +30,17 @@ use reqwest::blocking::Client;
 use reqwest::blocking::multipart::Form;
 use reqwest::header::AUTHORIZATION;
 use reqwest::StatusCode;
+use serde::Deserialize;
 use zip::{CompressionMethod, ZipArchive, ZipWriter};
 use zip::write::SimpleFileOptions;
 use uuid::Uuid;
-use yapper::{base64_encode, DelOnDrop, DepResolveMode, DepState, dispatch_debug, ModInfo, Notification, parse_mod, parse_mod_ext, reserved_mod_id, Response, Status, ZipProgress};
+use yapper::{base64_encode, DelOnDrop, DepKind, DepResolveMode, DepState, dispatch_debug, ErrorKind, ModInfo, ModUpdate, Notification, NotificationEntry, parse_mod_ext, parse_mods, reserved_mod_id, Response, Status, ZipProgress};
 use yapper::conf::Config;
-use crate::config::{ServerConf, SVManage};
+use crate::config::{Retention, ServerConf, SVManage};
 use crate::sv_fs;
 use crate::sv_fs::Progress;
+use crate::updates;
+use crate::updates::Fetcher;
 
 #[derive(Debug, Clone, Eq, PartialEq, Display, Encode, Decode)]
 pub enum Command {
@@ -71,40 +74,248 @@ struct RequestComm {
 	recv_resp: Receiver<Response>,
 }
 
-pub static NOTIFICATIONS: Lazy<Mutex<HashMap<String, Vec<Notification>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+#[derive(Default)]
+pub struct NotifLog {
+	last_id: u64,
+	entries: VecDeque<NotificationEntry>,
+	// Last id seen by the old drain-style `NetCommand::Notifications` polling
+	poll_cursor: u64,
+}
 
-pub fn get_notifs(account: impl AsRef<str>) -> Vec<Notification> {
+fn unix_time() -> Duration {
+	SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default()
+}
+
+impl NotifLog {
+	fn push(&mut self, notif: Notification, retention: &Retention) -> NotificationEntry {
+		// Millisecond timestamps, bumped on collisions. Progress updates never hit the disk,
+		// going by the clock keeps their ids from being handed out again after a restart
+		let now = unix_time();
+		self.last_id = (self.last_id + 1).max(now.as_millis() as u64);
+		let entry = NotificationEntry {
+			id: self.last_id,
+			time: now.as_secs(),
+			severity: notif.severity(),
+			notif,
+		};
+		self.entries.push_back(entry.clone());
+		retention.trim(&mut self.entries);
+		entry
+	}
+
+	fn after(&self, id: u64) -> Vec<NotificationEntry> {
+		self.entries
+			.iter()
+			.filter(|entry| entry.id > id)
+			.cloned()
+			.collect()
+	}
+}
+
+pub static NOTIFICATIONS: Lazy<Mutex<HashMap<String, NotifLog>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+static NOTIFICATIONS_CHANGED: Condvar = Condvar::new();
+// For the retention settings, see `init_notifs`
+static NOTIFICATIONS_CONF: OnceLock<Config<SVManage>> = OnceLock::new();
+// Each account's log is persisted to its own append-only file, one json entry per line,
+// so notifications don't rewrite the whole config every time
+static NOTIFICATION_FILES: Lazy<Mutex<HashMap<String, LogFile>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+struct LogFile {
+	path: PathBuf,
+	file: Option<File>,
+	// Entries in the file, it gets compacted once that's well past what's kept
+	lines: usize,
+}
+
+impl LogFile {
+	fn append(&mut self, entry: &NotificationEntry) -> Result<()> {
+		if self.file.is_none() {
+			let file = fs::OpenOptions::new()
+				.create(true)
+				.append(true)
+				.open(&self.path)
+				.with_context(|| format!("Failed to open {:?}", self.path))?;
+			self.file = Some(file);
+		}
+		let mut line = serde_json::to_vec(entry)?;
+		line.push(b'\n');
+		self.file.as_mut().unwrap().write_all(&line)?;
+		self.lines += 1;
+		Ok(())
+	}
+
+	// Replaces the file with just `entries`
+	fn rewrite<'a>(&mut self, entries: impl Iterator<Item = &'a NotificationEntry>) -> Result<()> {
+		let tmp = self.path.with_extension("jsonl.tmp");
+		let mut text = Vec::new();
+		let mut lines = 0;
+		for entry in entries {
+			text.extend(serde_json::to_vec(entry)?);
+			text.push(b'\n');
+			lines += 1;
+		}
+		fs::write(&tmp, text).with_context(|| format!("Failed to write {tmp:?}"))?;
+		fs::rename(&tmp, &self.path).with_context(|| format!("Failed to replace {:?}", self.path))?;
+		self.file = None;
+		self.lines = lines;
+		Ok(())
+	}
+}
+
+// Lines that don't parse, like one cut off by a crash, are skipped
+fn read_log(path: &Path) -> Vec<NotificationEntry> {
+	let Ok(text) = fs::read_to_string(path) else { return Vec::new() };
+	text.lines()
+		.filter_map(|line| serde_json::from_str(line).ok())
+		.collect()
+}
+
+// Loads the persisted logs, call before anything can push notifications
+pub fn init_notifs(conf: &Config<SVManage>) {
+	let dir = conf.path().parent().map(Path::to_owned).unwrap_or_default().join("notifications");
+	if let Err(err) = fs::create_dir_all(&dir) {
+		dispatch_debug(anyhow!(err).context("Failed to create the notifications folder"));
+	}
+
+	let retention = conf.with_config(|conf| conf.notifications.clone());
+	// Same order as `persist_notif`
+	let mut files = NOTIFICATION_FILES.lock().unwrap();
 	let mut notifs = NOTIFICATIONS.lock().unwrap();
-	if !notifs.contains_key(account.as_ref()) {
-		return Vec::new();
+	let mut migrated = false;
+	conf.with_config(|conf| {
+		for (account, data) in conf.accounts.iter() {
+			let path = dir.join(format!("{account}.jsonl"));
+			let mut entries: VecDeque<NotificationEntry> = read_log(&path).into();
+			// Logs used to live in the config itself
+			if !data.cache.notifications.is_empty() {
+				migrated = true;
+				if !path.exists() {
+					entries = data.cache.notifications.iter().cloned().collect();
+				}
+			}
+			entries.make_contiguous().sort_by_key(|entry| entry.id);
+			retention.trim(&mut entries);
+
+			let mut file = LogFile { path, file: None, lines: 0 };
+			if let Err(err) = file.rewrite(entries.iter()) {
+				dispatch_debug(err.context("Failed to compact the notification log"));
+			}
+			files.insert(account.clone(), file);
+
+			let log = notifs.entry(account.clone()).or_default();
+			log.entries = entries;
+			log.last_id = log.entries.back().map(|entry| entry.id).unwrap_or(0);
+			// Whatever was left over before the restart counts as already polled
+			log.poll_cursor = log.last_id;
+		}
+	});
+	drop(notifs);
+	drop(files);
+
+	if migrated {
+		let result = conf.with_config_mut(|conf| {
+			for data in conf.accounts.values_mut() {
+				data.cache.notifications.clear();
+			}
+		});
+		if let Err(err) = result {
+			dispatch_debug(err.context("Failed to clear the old notification cache"));
+		}
 	}
+	let _ = NOTIFICATIONS_CONF.set(conf.clone());
+}
 
-	replace(&mut notifs.get_mut(account.as_ref()).unwrap(), Vec::new())
+fn retention() -> Retention {
+	NOTIFICATIONS_CONF.get()
+		.map(|conf| conf.with_config(|conf| conf.notifications.clone()))
+		.unwrap_or_default()
 }
 
-pub fn push_notif(account: impl AsRef<str>, notif: Notification) {
+fn persist_notif(account: &str, entry: NotificationEntry) {
+	if entry.notif.is_progress() {
+		return;
+	}
+
+	let mut files = NOTIFICATION_FILES.lock().unwrap();
+	let Some(file) = files.get_mut(account) else { return };
+	let result: Result<()> = try {
+		file.append(&entry)?;
+		// The file only grows, so every now and then it's cut back down to what's kept in memory
+		if file.lines > retention().max_entries * 2 + 16 {
+			let entries = notifs_after(account, 0);
+			file.rewrite(entries.iter().filter(|entry| !entry.notif.is_progress()))?;
+		}
+	};
+	if let Err(err) = result {
+		dispatch_debug(err.context("Failed to persist a notification"));
+	}
+}
+
+pub fn get_notifs(account: impl AsRef<str>) -> Vec<Notification> {
 	let mut notifs = NOTIFICATIONS.lock().unwrap();
-	if !notifs.contains_key(account.as_ref()) {
-		notifs.insert(account.as_ref().to_owned(), Vec::new());
+	let Some(log) = notifs.get_mut(account.as_ref()) else {
+		return Vec::new();
+	};
+
+	let new = log.after(log.poll_cursor);
+	log.poll_cursor = log.last_id;
+	new.into_iter().map(|entry| entry.notif).collect()
+}
+
+pub fn notifs_after(account: impl AsRef<str>, id: u64) -> Vec<NotificationEntry> {
+	let notifs = NOTIFICATIONS.lock().unwrap();
+	notifs.get(account.as_ref()).map(|log| log.after(id)).unwrap_or_default()
+}
+
+// Where a new subscriber starts reading from
+pub fn notif_cursor(account: impl AsRef<str>) -> u64 {
+	let notifs = NOTIFICATIONS.lock().unwrap();
+	notifs.get(account.as_ref()).map(|log| log.last_id).unwrap_or(0)
+}
+
+// Blocks until there's something past `cursor` or the timeout runs out.
+// Returns the new notifications and the cursor to use next time
+pub fn wait_notifs(account: impl AsRef<str>, cursor: u64, timeout: Duration) -> (Vec<Notification>, u64) {
+	let notifs = NOTIFICATIONS.lock().unwrap();
+	let (notifs, _) = NOTIFICATIONS_CHANGED.wait_timeout_while(notifs, timeout, |notifs| {
+		notifs.get(account.as_ref()).is_none_or(|log| log.last_id <= cursor)
+	}).unwrap();
+
+	match notifs.get(account.as_ref()) {
+		Some(log) => (log.after(cursor).into_iter().map(|entry| entry.notif).collect(), log.last_id),
+		None => (Vec::new(), cursor),
 	}
+}
 
-	notifs.get_mut(account.as_ref()).unwrap().push(notif);
+pub fn push_notif(account: impl AsRef<str>, notif: Notification) {
+	let retention = retention();
+	let entry = {
+		let mut notifs = NOTIFICATIONS.lock().unwrap();
+		let entry = notifs.entry(account.as_ref().to_owned()).or_default().push(notif, &retention);
+		NOTIFICATIONS_CHANGED.notify_all();
+		entry
+	};
+	persist_notif(account.as_ref(), entry);
 }
 
+// Used for progress updates: the old entry is dropped and the new one gets a fresh id,
+// so every subscriber sees it exactly once no matter how far along it was
 pub fn replace_notif_if<F>(account: impl AsRef<str>, notif: Notification, f: F)
 where F: Fn(&Notification) -> bool
 {
-	let mut notifs = NOTIFICATIONS.lock().unwrap();
-	if !notifs.contains_key(account.as_ref()) {
-		notifs.insert(account.as_ref().to_owned(), Vec::new());
-	}
+	let retention = retention();
+	let entry = {
+		let mut notifs = NOTIFICATIONS.lock().unwrap();
+		let log = notifs.entry(account.as_ref().to_owned()).or_default();
 
-	let vec = notifs.get_mut(account.as_ref()).unwrap();
-	if let Some(other) = vec.iter_mut().find(|x| f(x)) {
-		*other = notif;
-		return;
-	}
-	vec.push(notif);
+		if let Some(idx) = log.entries.iter().position(|other| f(&other.notif)) {
+			log.entries.remove(idx);
+		}
+		let entry = log.push(notif, &retention);
+		NOTIFICATIONS_CHANGED.notify_all();
+		entry
+	};
+	persist_notif(account.as_ref(), entry);
 }
 
 struct Shared {
@@ -248,6 +459,19 @@ impl Server {
 	pub fn conf(&self) -> &Config<SVManage> {
 		&self.shared.conf
 	}
+
+	// Runs on the caller's thread for up to `deadline`
+	pub fn check_updates(&self, fetcher: &Arc<dyn Fetcher>, deadline: Duration) -> Result<Vec<ModUpdate>> {
+		let path = self.conf().with_config(|x| x.accounts[self.account()].servers[self.name()].path.clone());
+		let mods_path = expanduser(&path).context("Failed to get home directory")?.join("mods");
+		let mods = list_mods(&mods_path, &self.shared).context("Failed to list mods")?;
+		let mc_version = mods.iter()
+			.find(|x| x.mod_id == "minecraft")
+			.ok_or(anyhow!("Couldn't find the minecraft version"))?
+			.version
+			.to_string();
+		Ok(updates::check(&mods, &mc_version, fetcher, deadline))
+	}
 }
 
 fn dispatch_err<T: Debug>(err: &T) { dispatch_debug(err) }
@@ -298,7 +522,7 @@ fn server_main(account: String, server: String, comm: ProcessComm, shared: Arc<S
 			if let Some(command) = command {
 				comm.send_resp.send(process_command_idle(command, &mut deferred, &shared, &get_path, &get_backup_path, &get_mods_path)
 					.inspect_err(dispatch_err)
-					.unwrap_or(Response::Err)).unwrap();
+					.unwrap_or_else(|err| Response::error(&err))).unwrap();
 			}
 
 			
@@ -346,7 +570,6 @@ fn server_main(account: String, server: String, comm: ProcessComm, shared: Arc<S
 						fs::create_dir(&working).context("Couldn't create working server directory")?;
 						// This will take a long time!
 						sv_fs::copy_dir_all(&backup, &working, |progress| {
-							let mut notifications = NOTIFICATIONS.lock().unwrap();
 							let new = Notification::RestoreProgress(server.clone(), progress.copied, progress.total);
 							replace_notif_if(&account, new, |other| other.is_restore_progress());
 						}).context("Error copying data")?;
@@ -430,18 +653,34 @@ fn list_mods(path: &Path, shared: &Shared) -> Result<Vec<ModInfo>> {
 			continue;
 		}
 
-		// Parse mod
-		let info = match parse_mod(&path).context(format!("Error while parsing {path:?}")) {
-			Ok(info) => info,
+		// Parse mods, there can be several in one jar
+		let infos = match parse_mods(&path).context(format!("Error while parsing {path:?}")) {
+			Ok(infos) => infos,
 			Err(_) => continue,
 		};
-		vec.push(info);
+		vec.extend(infos);
 	}
 
-	// Add forge as a mod
+	// Add the loader and minecraft as mods
 	let server_path = expanduser(shared.conf.with_config(|conf| {
 		conf.accounts[&shared.account].servers[&shared.server].path.clone()
 	}))?;
+	match fabric_mods(&server_path)? {
+		Some(loader) => vec.extend(loader),
+		None => vec.extend(forge_mods(&server_path)?),
+	}
+
+	vec.sort_by(|mod1, mod2| mod1.mod_id.cmp(&mod2.mod_id));
+	
+	*shared.mods_cache.lock().unwrap() = Some(vec.clone());
+	shared.mod_cache_up_to_date.store(true, Ordering::Release);
+	Ok(vec)
+}
+
+// Forge and NeoForge servers start from the args file of a version folder in /libraries
+fn forge_mods(server_path: &Path) -> Result<Vec<ModInfo>> {
+	let mut vec = Vec::new();
+
 	let runner = server_path.join("run.sh");
 	let runner = fs::read_to_string(&runner).context(format!("Failed to read run script: {runner:?}"))?;
 
@@ -457,7 +696,7 @@ fn list_mods(path: &Path, shared: &Shared) -> Result<Vec<ModInfo>> {
 		forge_dir.pop();
 
 		let mut forge_path_ = None;
-		for item in fs::read_dir(&forge_dir).context(format!("Failed to read a directory in /libraries: {path:?}"))? {
+		for item in fs::read_dir(&forge_dir).context(format!("Failed to read a directory in /libraries: {forge_dir:?}"))? {
 			let Ok(item) = item else { continue };
 			let Ok(ftype) = item.file_type() else { continue };
 			if !ftype.is_file() { continue };
@@ -470,22 +709,41 @@ fn list_mods(path: &Path, shared: &Shared) -> Result<Vec<ModInfo>> {
 		}
 		forge_path = forge_path_.ok_or(anyhow!("Couldn't find forge jar file"))?;
 
-		let (mc_version_, forge_version) = forge_dir
+		let dir_name = forge_dir
 			.components()
 			.last()
 			.ok_or(anyhow!("Failed to get path component"))?
 			.as_os_str()
 			.to_str()
-			.ok_or(anyhow!("Path name is not utf-8"))?
-			.split_once("-")
-			.ok_or(anyhow!("Failed to parse minecraft and forge versions"))?;
+			.ok_or(anyhow!("Path name is not utf-8"))?;
+		let loader_dir = forge_dir.parent().ok_or(anyhow!("Failed to get path component"))?;
+		let (mc_version_, forge_version) = if loader_dir.ends_with("net/neoforged/neoforge") {
+			// NeoForge only has its own version in the path, suffix and all (20.4.80-beta)
+			(neoforge_mc_version(dir_name)?, dir_name)
+		} else if loader_dir.ends_with("net/minecraftforge/forge") || loader_dir.ends_with("net/neoforged/forge") {
+			// `<mc>-<forge>`, NeoForge for 1.20.1 still did it forge's way
+			let (mc_version, forge_version) = dir_name
+				.split_once("-")
+				.ok_or(anyhow!("Failed to parse the forge version {dir_name:?}"))?;
+			(mc_version.to_owned(), forge_version)
+		} else {
+			bail!("Unknown loader in {loader_dir:?}")
+		};
 
-		let info = parse_mod_ext(&forge_path, Some(forge_version.to_owned())).context(format!(r#"Couldn't parse forge "{path:?}". WHAT!"#))?;
+		let info = parse_mod_ext(&forge_path, Some(forge_version.to_owned())).context(format!(r#"Couldn't parse forge "{forge_path:?}". WHAT!"#))?;
 		vec.push(info);
 
-		mc_version = mc_version_.to_owned();
+		mc_version = mc_version_;
 	} else {
-		bail!("Could't parse run.sh script");
+		// Before 1.17 it's `java -jar forge-<mc>-<forge>.jar`, no args file
+		let (path, mc_version_, forge_version) = legacy_forge(server_path)?.ok_or(anyhow!("Could't parse run.sh script"))?;
+		// Forge's own metadata lived in code back then
+		let info = parse_mod_ext(&path, Some(forge_version.clone()))
+			.unwrap_or_else(|_| builtin_mod("forge", "Forge", &forge_version, &path));
+		vec.push(info);
+
+		forge_path = path;
+		mc_version = mc_version_;
 	}
 
 	// I tried to find a way to parse the mod info for the `minecraft` modid for basically 2 days.
@@ -496,30 +754,132 @@ fn list_mods(path: &Path, shared: &Shared) -> Result<Vec<ModInfo>> {
 	let mut forge_zip = ZipArchive::new(
 		File::open(&forge_path).context("Failed to open forge jar file")?
 	).context("Failed to open forge jar file")?;
-	let mut mcp_logo = forge_zip.by_name("mcplogo.png").context("Failed to read mcplogo")?;
-	let mut data = Vec::new();
-	mcp_logo.read_to_end(&mut data)?;
-
-	let info = ModInfo {
-		filename: "minecraft".to_owned(),
-		path: forge_path.clone(),
-		mod_id: "minecraft".to_owned(),
-		name: Some("Minecraft".to_owned()),
+	// NeoForge doesn't ship it
+	let logo: Result<Vec<u8>> = try {
+		let mut mcp_logo = forge_zip.by_name("mcplogo.png").context("Failed to read mcplogo")?;
+		let mut data = Vec::new();
+		mcp_logo.read_to_end(&mut data)?;
+		data
+	};
+
+	let mut info = builtin_mod("minecraft", "Minecraft", &mc_version, &forge_path);
+	info.logo = logo.ok();
+	vec.push(info);
+	Ok(vec)
+}
+
+// The forge jar in the server folder with its minecraft and forge versions, from its name if it has them
+fn legacy_forge(server_path: &Path) -> Result<Option<(PathBuf, String, String)>> {
+	for item in fs::read_dir(server_path).context(format!("Failed to read the server directory: {server_path:?}"))? {
+		let Ok(item) = item else { continue };
+		let path = item.path();
+		let Some(name) = path.file_name().and_then(OsStr::to_str) else { continue };
+		let Some(name) = name.strip_prefix("forge-").and_then(|x| x.strip_suffix(".jar")) else { continue };
+		if name.ends_with("-installer") { continue };
+
+		let name = name.strip_suffix("-universal").unwrap_or(name);
+		let (mc_version, forge_version) = match name.split_once("-") {
+			Some((mc_version, forge_version)) => (mc_version.to_owned(), forge_version.to_owned()),
+			None => version_json(&path).context(format!("Failed to get the versions of {path:?}"))?,
+		};
+		return Ok(Some((path, mc_version, forge_version)));
+	}
+	Ok(None)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VersionJson {
+	// `1.12.2-forge-14.23.5.2859`, sometimes `1.12.2-forge1.12.2-14.23.5.2847`
+	id: String,
+	inherits_from: String,
+}
+
+// The launcher profile inside forge's universal jar
+fn version_json(path: &Path) -> Result<(String, String)> {
+	let mut zip = ZipArchive::new(File::open(path)?)?;
+	let mut json = String::new();
+	zip.by_name("version.json")?.read_to_string(&mut json)?;
+	let json: VersionJson = serde_json::from_str(&json)?;
+
+	let (_, forge_version) = json.id.rsplit_once("-").ok_or(anyhow!("Failed to parse the version id {:?}", json.id))?;
+	Ok((json.inherits_from, forge_version.to_owned()))
+}
+
+// Fabric and Quilt servers start from a launcher jar, the versions are in /libraries
+fn fabric_mods(server_path: &Path) -> Result<Option<Vec<ModInfo>>> {
+	let (mod_id, name, launcher, loader_dir) = if server_path.join("quilt-server-launch.jar").is_file() {
+		("quilt_loader", "Quilt Loader", "quilt-server-launch.jar", "libraries/org/quiltmc/quilt-loader")
+	} else if server_path.join("fabric-server-launch.jar").is_file() || server_path.join(".fabric").is_dir() {
+		("fabricloader", "Fabric Loader", "fabric-server-launch.jar", "libraries/net/fabricmc/fabric-loader")
+	} else {
+		return Ok(None);
+	};
+
+	let loader_version = library_version(&server_path.join(loader_dir))?;
+	// Intermediary mappings are versioned like minecraft itself
+	let mc_version = library_version(&server_path.join("libraries/net/fabricmc/intermediary"))?;
+
+	let launcher = server_path.join(launcher);
+	Ok(Some(vec![
+		builtin_mod(mod_id, name, &loader_version, &launcher),
+		builtin_mod("minecraft", "Minecraft", &mc_version, &launcher),
+	]))
+}
+
+// The newest version folder of a library, older ones stay around after updating
+fn library_version(dir: &Path) -> Result<String> {
+	let mut newest: Option<String> = None;
+	for item in fs::read_dir(dir).context(format!("Failed to read a directory in /libraries: {dir:?}"))? {
+		let Ok(item) = item else { continue };
+		let Ok(ftype) = item.file_type() else { continue };
+		if !ftype.is_dir() { continue };
+		let Ok(version) = item.file_name().into_string() else { continue };
+
+		if newest.as_ref().is_none_or(|x| ComparableVersion::new(x) < ComparableVersion::new(&version)) {
+			newest = Some(version);
+		}
+	}
+	newest.ok_or(anyhow!("No versions in {dir:?}"))
+}
+
+// Loaders and minecraft itself, which have no metadata of their own to parse
+fn builtin_mod(mod_id: &str, name: &str, version: &str, path: &Path) -> ModInfo {
+	ModInfo {
+		filename: mod_id.to_owned(),
+		path: path.to_owned(),
+		mod_id: mod_id.to_owned(),
+		name: Some(name.to_owned()),
 		description: None,
-		version: ComparableVersion::new(&mc_version),
-		logo: Some(data),
+		version: ComparableVersion::new(version),
+		logo: None,
 		url: None,
 		credits: None,
 		authors: None,
 		dependencies: Vec::new(),
-	};
-	vec.push(info);
+		provides: Vec::new(),
+		loader: None,
+		loader_version: None,
+		license: None,
+		issue_tracker: None,
+		update_json: None,
+		display_test: None,
+		client_side_only: false,
+		hashes: None,
+	}
+}
 
-	vec.sort_by(|mod1, mod2| mod1.mod_id.cmp(&mod2.mod_id));
-	
-	*shared.mods_cache.lock().unwrap() = Some(vec.clone());
-	shared.mod_cache_up_to_date.store(true, Ordering::Release);
-	Ok(vec)
+// NeoForge versions are minecraft's without the leading 1, so 20.4.x is for 1.20.4 and 21.0.x for 1.21
+fn neoforge_mc_version(version: &str) -> Result<String> {
+	let mut parts = version.split('.');
+	let (Some(major), Some(minor)) = (parts.next(), parts.next()) else {
+		bail!("Failed to parse the NeoForge version {version:?}")
+	};
+	Ok(if minor == "0" {
+		format!("1.{major}")
+	} else {
+		format!("1.{major}.{minor}")
+	})
 }
 
 fn query_mod(path: &Path, mod_id: &str, shared: &Shared) -> Result<ModInfo> {
@@ -683,13 +1043,15 @@ where F: Fn() -> Result<PathBuf>
 		let mods_folder = mods()?;
 		ensure_dir(&mods_folder).context("Error creating mods folder")?;
 
-		let to_install = parse_mod(&mod_path)?;
-		if reserved_mod_id(&to_install.mod_id) {
+		let to_install = parse_mods(&mod_path)?;
+		if to_install.iter().any(|modd| reserved_mod_id(&modd.mod_id)) {
 			bail!("Reserved mod id!")
 		}
 		let all = list_mods(&mods_folder, shared)?;
 
-		if all.iter().any(|modd| modd.mod_id == to_install.mod_id) {
+		if let Some(same) = all.iter().find(|modd| modd.hashes.is_some() && modd.hashes == to_install[0].hashes) {
+			Response::DuplicateMod(same.filename.clone())
+		} else if all.iter().any(|modd| to_install.iter().any(|new| new.mod_id == modd.mod_id)) {
 			Response::ModConflict
 		} else {
 			shared.update_status(Status::Modding);
@@ -728,13 +1090,30 @@ where F: Fn() -> Result<PathBuf>
 		let mods_folder = mods()?;
 		ensure_dir(&mods_folder).context("Error creating mods folder")?;
 
-		let to_install = parse_mod(&mod_path)?;
-		if reserved_mod_id(&to_install.mod_id) {
+		let to_install = parse_mods(&mod_path)?;
+		if to_install.iter().any(|modd| reserved_mod_id(&modd.mod_id)) {
 			bail!("Reserved mod id!")
 		}
 		let all = list_mods(&mods_folder, shared)?;
 
-		if let Some(modd) = all.iter().find(|modd| modd.mod_id == to_install.mod_id) {
+		// Every jar that has one of the new mods gets replaced, along with whatever else it bundles
+		let mut replaced: Vec<&Path> = all
+			.iter()
+			.filter(|modd| to_install.iter().any(|new| new.mod_id == modd.mod_id))
+			.map(|modd| modd.path.as_path())
+			.collect();
+		replaced.sort();
+		replaced.dedup();
+
+		let asked: Vec<&str> = to_install.iter().map(|x| x.mod_id.as_str()).collect();
+		let (along, broken) = removed_along(&all, &replaced, &to_install, &asked);
+
+		if replaced.is_empty() {
+			Response::NoSuchMod
+		} else if !broken.is_empty() {
+			// Something still needs the other mods in the old jar
+			Response::DepUnsatisfied(broken)
+		} else {
 			shared.update_status(Status::Modding);
 			let mut destination_path = mods_folder.join(&filename);
 			loop {
@@ -744,14 +1123,14 @@ where F: Fn() -> Result<PathBuf>
 				destination_path.set_file_name(format!("{filename}-2.jar"));
 			};
 
-			fs::remove_file(&modd.path).context("Failed to remove old mod file")?;
+			for old in replaced {
+				fs::remove_file(old).context("Failed to remove old mod file")?;
+			}
 			fs::rename(&mod_path, &destination_path).context("Failed to move mod")?;
 			del.forgive();
 
 			shared.invalidate_mod_cache();
-			Response::Ok
-		} else {
-			Response::NoSuchMod
+			if along.is_empty() { Response::Ok } else { Response::ModsRemoved(along) }
 		}
 	};
 	shared.update_status(Status::Idle);
@@ -794,16 +1173,24 @@ where F: Fn() -> Result<PathBuf>
 			}
 		}
 		DepResolveMode::Update => {
-			for the_mod in the_mods.iter() {
-				if let Some(installed_mod) = all.iter().find(|other| other.mod_id == the_mod.mod_id) {
-					if the_mod.version <= installed_mod.version {
-						// You tried to update a mod to an equal or lower version
-						// AlreadyInstalled here means "A newer or equal version is already installed"
-						unsat.push((the_mod.mod_id.clone(), DepState::AlreadyInstalled));
-					}
-				} else {
+			// Judged per jar, a bundle can add new libraries or keep some at the same version
+			// as long as something in it replaces an older installed mod
+			let mut jars: Vec<&Path> = the_mods.iter().map(|x| x.path.as_path()).collect();
+			jars.sort();
+			jars.dedup();
+			for jar in jars {
+				let bundle: Vec<&ModInfo> = the_mods.iter().filter(|x| x.path == jar).collect();
+				let installed: Vec<(&ModInfo, &ModInfo)> = bundle
+					.iter()
+					.filter_map(|the_mod| Some((*the_mod, all.iter().find(|other| other.mod_id == the_mod.mod_id)?)))
+					.collect();
+				if installed.is_empty() {
 					// You tried to update a mod that is not installed
-					unsat.push((the_mod.mod_id.clone(), DepState::NotInstalled));
+					unsat.push((bundle[0].mod_id.clone(), DepState::NotInstalled));
+				} else if installed.iter().all(|(the_mod, installed_mod)| the_mod.version <= installed_mod.version) {
+					// You tried to update a mod to an equal or lower version
+					// AlreadyInstalled here means "A newer or equal version is already installed"
+					unsat.push((installed[0].0.mod_id.clone(), DepState::AlreadyInstalled));
 				}
 			}
 		}
@@ -816,64 +1203,122 @@ where F: Fn() -> Result<PathBuf>
 	
 	// Create hypothetical scenario where the changes have been made
 	// then try to resolve all dependencies
+	// Removing or replacing a mod takes the rest of its jar along
+	let jars_of = |all: &[ModInfo], the_mods: &[ModInfo]| -> Vec<PathBuf> {
+		all
+			.iter()
+			.filter(|ours| the_mods.iter().any(|other| other.mod_id == ours.mod_id))
+			.map(|ours| ours.path.clone())
+			.collect()
+	};
 	match mode {
 		DepResolveMode::Installation => all.append(&mut the_mods),
-		DepResolveMode::Removal => all.retain(|ours| !the_mods.iter().any(|other| other.mod_id == ours.mod_id)),
+		DepResolveMode::Removal => {
+			let removed = jars_of(&all, &the_mods);
+			all.retain(|ours| !removed.contains(&ours.path));
+		}
 		DepResolveMode::Update => {
-			for ours in all.iter_mut() {
-				if let Some(other) = the_mods.iter().find(|other| other.mod_id == ours.mod_id) {
-					*ours = other.clone();
-				}
-			}
+			let replaced = jars_of(&all, &the_mods);
+			all.retain(|ours| !replaced.contains(&ours.path));
+			all.append(&mut the_mods);
 		}
 	}
 	
 	drop(the_mods);
-	let hypothetical = all;
+	let unsat = unsatisfied(&all);
+	if unsat.is_empty() {
+		Ok(Response::DepSatisfied)
+	} else {
+		Ok(Response::DepUnsatisfied(unsat))
+	}
+}
+
+// Every dependency of `hypothetical` that isn't met by the rest of it
+fn unsatisfied(hypothetical: &[ModInfo]) -> Vec<(String, DepState)> {
+	let mut unsat = Vec::new();
+
+	// Forge's language providers are versioned along with forge itself, NeoForge's FML isn't
+	let forge = hypothetical.iter().find(|x| x.mod_id == "forge").map(|x| &x.version);
+
+	// Bundled copies count too, when several mods bundle one the newest wins like in forge
+	let installed = |mod_id: &str| hypothetical
+		.iter()
+		.find(|other| other.mod_id == mod_id)
+		.map(|other| &other.version)
+		.or_else(|| hypothetical
+			.iter()
+			.flat_map(|other| other.provides.iter())
+			.filter(|provided| provided.mod_id == mod_id)
+			.map(|provided| &provided.version)
+			.max());
 	
 	for the_mod in hypothetical.iter() {
+		if let Some(forge) = forge &&
+			let Some(loader) = &the_mod.loader &&
+			matches!(loader.as_str(), "javafml" | "lowcodefml") &&
+			let Some(range) = &the_mod.loader_version &&
+			!range.contains(forge) {
+			unsat.push((the_mod.mod_id.clone(), DepState::LoaderMismatch(range.clone(), forge.clone())))
+		}
+
 		// Try to resolve dependencies, pretending the changes have been made
 		for the_dep in the_mod.dependencies.iter() {
-			let find = hypothetical
-				.iter()
-				.find(|other| other.mod_id == the_dep.mod_id);
+			// Any of the alternatives will do just as well
+			if the_dep.alternatives.iter().any(|(mod_id, range)| installed(mod_id).is_some_and(|x| range.contains(x))) {
+				continue;
+			}
 
-			if let Some(found_dep) = find {
+			if let Some(version) = installed(&the_dep.mod_id) {
 				// Dependency is installed, but does the version match?
-				let version = &found_dep.version;
-
-				// Check low bound
-				let low_sat = match &the_dep.min_version {
-					Bound::Included(v) => version >= v,
-					Bound::Excluded(v) => version > v,
-					Bound::Unbounded => true,
-				};
-
-				// Check high bound
-				let high_sat = match &the_dep.max_version {
-					Bound::Included(v) => version <= v,
-					Bound::Excluded(v) => version < v,
-					Bound::Unbounded => true,
-				};
-
-				if low_sat && high_sat {
-					// Yay!
-				} else {
-					// :3c
-					unsat.push((the_dep.mod_id.clone(), DepState::VersionMismatch(version.clone())))
+				match (the_dep.kind, the_dep.range.contains(version)) {
+					(DepKind::Incompatible, true) => {
+						unsat.push((the_dep.mod_id.clone(), DepState::Incompatible(version.clone())))
+					}
+					(DepKind::Discouraged, true) => {
+						unsat.push((the_dep.mod_id.clone(), DepState::Discouraged(version.clone())))
+					}
+					(DepKind::Incompatible | DepKind::Discouraged, false) => {}
+					(DepKind::Recommended, false) => {
+						// Like fabric, only a warning
+						unsat.push((the_dep.mod_id.clone(), DepState::Discouraged(version.clone())))
+					}
+					(_, true) => {
+						// Yay!
+					}
+					(_, false) => {
+						// :3c
+						unsat.push((the_dep.mod_id.clone(), DepState::VersionMismatch(version.clone())))
+					}
 				}
-			} else {
+			} else if the_dep.kind.mandatory() {
 				// Dependency is not installed, error
 				unsat.push((the_dep.mod_id.clone(), DepState::NotInstalled))
 			}
 		}
 	}
-	
-	if unsat.is_empty() {
-		Ok(Response::DepSatisfied)
-	} else {
-		Ok(Response::DepUnsatisfied(unsat))
-	}
+	unsat
+}
+
+// Removing a jar removes everything in it, not just the mods that were `asked` for (`added` ones
+// take their place). Those other mods, and what they alone were keeping satisfied
+fn removed_along(all: &[ModInfo], removed: &[&Path], added: &[ModInfo], asked: &[&str]) -> (Vec<String>, Vec<(String, DepState)>) {
+	let gone = |x: &ModInfo| removed.contains(&x.path.as_path());
+	let along: Vec<String> = all
+		.iter()
+		.filter(|x| gone(x) && !asked.contains(&x.mod_id.as_str()))
+		.map(|x| x.mod_id.clone())
+		.collect();
+
+	let with_along: Vec<ModInfo> = all
+		.iter()
+		.filter(|x| !gone(x) || !asked.contains(&x.mod_id.as_str()))
+		.chain(added)
+		.cloned()
+		.collect();
+	let without: Vec<ModInfo> = all.iter().filter(|x| !gone(x)).chain(added).cloned().collect();
+	let before = unsatisfied(&with_along);
+	let broken = unsatisfied(&without).into_iter().filter(|x| !before.contains(x)).collect();
+	(along, broken)
 }
 
 fn uninstall_mod<F>(mod_id: String, mods: &F, shared: &Shared) -> Result<Response>
@@ -890,11 +1335,17 @@ where F: Fn() -> Result<PathBuf>
 				bail!("Reserved mod id!")
 			}
 
-			shared.update_status(Status::Modding);
-			fs::remove_file(&modd.path).context("Error deleting mod file")?;
+			// Anything else in the same jar goes with it, unless something needs it
+			let (along, broken) = removed_along(&all, &[modd.path.as_path()], &[], &[mod_id.as_str()]);
+			if !broken.is_empty() {
+				Response::DepUnsatisfied(broken)
+			} else {
+				shared.update_status(Status::Modding);
+				fs::remove_file(&modd.path).context("Error deleting mod file")?;
 
-			shared.invalidate_mod_cache();
-			Response::Ok
+				shared.invalidate_mod_cache();
+				if along.is_empty() { Response::Ok } else { Response::ModsRemoved(along) }
+			}
 		} else {
 			Response::NoSuchMod
 		}
@@ -1034,7 +1485,10 @@ fn start_server<F: Fn() -> Result<PathBuf>, G: Fn() -> Result<PathBuf>>(path: &F
 				}?;
 			
 				if let Some(command) = command {
-					comm.send_resp.send(process_command_inloop(command, &mut child, rcon_client.as_ref(), shared, path, mods)?)?;
+					// A failed command shouldn't take the whole server down with it
+					comm.send_resp.send(process_command_inloop(command, &mut child, rcon_client.as_ref(), shared, path, mods)
+						.inspect_err(dispatch_err)
+						.unwrap_or_else(|err| Response::error(&err)))?;
 				}
 			
 				if child.try_wait()?.is_some() { break 'server };
@@ -1122,7 +1576,7 @@ where F: Fn() -> Result<PathBuf>,
 		}
 		Command::GenerateModsZip => {
 			// shared.update_status(Status::Packaging);
-			Ok(Response::Err)
+			Ok(Response::error_kind(ErrorKind::InvalidState, &anyhow!("Can't generate the mods zip while the server is running")))
 		}
 		Command::ResolveDeps(mode, new_mods) => {
 			resolve_deps(mods, shared, new_mods, mode)
@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use anyhow::{bail, Context, Result};
+use yapper::{Credentials, hash_pw, NetCommand, Response, ServerCommand, Session, Status, StoredCredentials};
+use yapper::conf::Config;
+use yapper::testing::{fake_server_ext, keypair, Loader, TempDir};
+use crate::config::{AccountData, CONFIG, ServerConf, SVManage};
+use crate::updates::{Fetcher, HttpFetcher};
+
+pub const PASSWORD: &str = "hunter2";
+pub const TIMEOUT: Duration = Duration::from_secs(30);
+
+// The notification log is global, so every manager in a process gets its own account
+static NEXT_ACCOUNT: AtomicU64 = AtomicU64::new(0);
+
+/// A whole sv_manage in this process, listening on a loopback port.
+/// Config, servers and backups all live in a temp dir that goes away on drop,
+/// and the servers are `yapper::testing::fake_server_ext`s
+pub struct TestManager {
+	dir: TempDir,
+	servers: Vec<String>,
+	pub account: String,
+	pub session: Session,
+}
+
+impl TestManager {
+	pub fn start(servers: &[&str]) -> Result<Self> {
+		Self::start_with_fetcher(servers, Arc::new(HttpFetcher::new()))
+	}
+
+	pub fn start_with_fetcher(servers: &[&str], fetcher: Arc<dyn Fetcher>) -> Result<Self> {
+		Self::start_ext(servers, Loader::Forge, fetcher, None)
+	}
+
+	/// Every server runs `loader`, and mods are checked for updates every `update_interval` if there is one
+	pub fn start_ext(servers: &[&str], loader: Loader, fetcher: Arc<dyn Fetcher>, update_interval: Option<Duration>) -> Result<Self> {
+		let dir = TempDir::new("sv_manage")?;
+		let (priv_key, pub_key) = keypair()?;
+		let account = format!("test{}", NEXT_ACCOUNT.fetch_add(1, Ordering::Relaxed));
+
+		let mut data = AccountData::default();
+		data.credentials = Some(StoredCredentials::new(&hash_pw(PASSWORD))?);
+		for name in servers {
+			let path = fake_server_ext(dir.path(), name, loader)?;
+			data.servers.insert(name.to_string(), ServerConf {
+				running: false,
+				path: path.to_string_lossy().to_string(),
+			});
+		}
+
+		let path = dir.path().join(CONFIG);
+		let config: Config<SVManage> = Config::init(path.to_str().context("Temp dir isn't utf-8")?)?;
+		config.with_config_mut(|conf| {
+			conf.accounts.insert(account.clone(), data);
+		})?;
+
+		let listener = TcpListener::bind("127.0.0.1:0")?;
+		let addr = listener.local_addr()?;
+		let ctx = crate::start_with(config, priv_key, None, fetcher, update_interval);
+		thread::spawn(move || crate::serve(listener, ctx));
+
+		let session = Session::new(Credentials {
+			addr: addr.to_string(),
+			pub_key,
+			user: account.clone(),
+			password: hash_pw(PASSWORD),
+		});
+
+		Ok(Self {
+			dir,
+			servers: servers.iter().map(|x| x.to_string()).collect(),
+			account,
+			session,
+		})
+	}
+
+	pub fn dir(&self) -> &Path {
+		self.dir.path()
+	}
+
+	pub fn server_path(&self, server: &str) -> PathBuf {
+		self.dir.path().join(server)
+	}
+
+	pub fn request(&self, cmd: NetCommand) -> Result<Response> {
+		self.session.request(cmd, TIMEOUT)
+	}
+
+	pub fn server(&self, server: &str, cmd: ServerCommand) -> Result<Response> {
+		self.request(NetCommand::ServerCommand(server.to_owned(), cmd))
+	}
+
+	pub fn status(&self, server: &str) -> Result<Status> {
+		match self.server(server, ServerCommand::Status)? {
+			Response::Status(status) => Ok(status.status),
+			other => bail!("Expected a status, got {other}"),
+		}
+	}
+
+	// Servers start, stop and back up in the background
+	pub fn wait_for(&self, server: &str, status: Status) -> Result<()> {
+		let start = Instant::now();
+		loop {
+			let current = self.status(server)?;
+			if current == status {
+				return Ok(());
+			}
+			if start.elapsed() > TIMEOUT {
+				bail!("{server} stayed {current} instead of becoming {status}");
+			}
+			sleep(Duration::from_millis(50));
+		}
+	}
+}
+
+impl Drop for TestManager {
+	// Don't leave stub servers running in a directory that's about to be deleted
+	fn drop(&mut self) {
+		for server in self.servers.iter() {
+			let _ = self.server(server, ServerCommand::Stop);
+			let _ = self.wait_for(server, Status::Idle);
+		}
+	}
+}
+
+/// A tiny HTTP server on a loopback port that answers GETs with whatever was `put` at that path
+/// and 404s everything else. Stands in for the sites hosting update JSONs
+pub struct HttpStandIn {
+	addr: String,
+	pages: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl HttpStandIn {
+	pub fn start() -> Result<Self> {
+		let listener = TcpListener::bind("127.0.0.1:0")?;
+		let addr = listener.local_addr()?.to_string();
+		let pages: Arc<Mutex<HashMap<String, String>>> = Arc::default();
+
+		let pages2 = pages.clone();
+		thread::spawn(move || {
+			for client in listener.incoming() {
+				let Ok(mut client) = client else { continue };
+				let result: Result<()> = try {
+					let mut reader = BufReader::new(&client);
+					let mut request = String::new();
+					reader.read_line(&mut request)?;
+					// The headers don't matter, but closing with them unread can reset the connection
+					let mut header = String::new();
+					while reader.read_line(&mut header)? > 2 {
+						header.clear();
+					}
+					// GET /path HTTP/1.1
+					let path = request.split_whitespace().nth(1).unwrap_or_default();
+					let response = match pages2.lock().unwrap().get(path) {
+						Some(body) => format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}", body.len()),
+						None => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_owned(),
+					};
+					client.write_all(response.as_bytes())?;
+				};
+				let _ = result;
+			}
+		});
+
+		Ok(Self { addr, pages })
+	}
+
+	pub fn put(&self, path: &str, body: impl Into<String>) {
+		self.pages.lock().unwrap().insert(path.to_owned(), body.into());
+	}
+
+	pub fn url(&self, path: &str) -> String {
+		format!("http://{}{path}", self.addr)
+	}
+}
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+use anyhow::{Context, Result};
+use mvn_version::ComparableVersion;
+use reqwest::blocking::Client;
+use serde::Deserialize;
+use yapper::{dispatch_debug, ModInfo, ModUpdate};
+
+// Forge's update checker format, https://docs.minecraftforge.net/en/latest/misc/updatechecker/
+
+/// How long a client asking for updates waits at most, mcayb gives up after 10 seconds
+pub const REQUEST_DEADLINE: Duration = Duration::from_secs(8);
+/// Nobody is waiting on the periodic check
+pub const PERIODIC_DEADLINE: Duration = Duration::from_secs(120);
+// Update JSONs fetched at once
+const FETCHERS: usize = 8;
+
+/// Gets the body of an update JSON. Tests plug in a local stand-in instead of the internet
+pub trait Fetcher: Send + Sync {
+	fn fetch(&self, url: &str) -> Result<String>;
+}
+
+impl<F> Fetcher for F
+where F: Fn(&str) -> Result<String> + Send + Sync
+{
+	fn fetch(&self, url: &str) -> Result<String> {
+		self(url)
+	}
+}
+
+pub struct HttpFetcher {
+	client: Client,
+}
+
+impl HttpFetcher {
+	pub fn new() -> Self {
+		let client = Client::builder()
+			.timeout(Duration::from_secs(10))
+			.build()
+			.unwrap();
+		Self { client }
+	}
+}
+
+impl Default for HttpFetcher {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Fetcher for HttpFetcher {
+	fn fetch(&self, url: &str) -> Result<String> {
+		Ok(self.client.get(url)
+			.send()
+			.context("Failed to send request")?
+			.error_for_status()?
+			.text()
+			.context("Failed to read response")?)
+	}
+}
+
+#[derive(Debug, Deserialize)]
+struct UpdateJson {
+	homepage: Option<String>,
+	// `<mc>-latest` and `<mc>-recommended` to a mod version
+	#[serde(default)]
+	promos: HashMap<String, String>,
+}
+
+/// Every mod in `mods` with a newer version for `mc_version` in its update JSON.
+/// Mods whose update JSON can't be fetched or parsed, or isn't back before `deadline`, are left out
+pub fn check(mods: &[ModInfo], mc_version: &str, fetcher: &Arc<dyn Fetcher>, deadline: Duration) -> Vec<ModUpdate> {
+	let mut bodies = fetch_all(mods, fetcher, deadline);
+	let mut updates = Vec::new();
+	for (i, info) in mods.iter().enumerate() {
+		let Some(url) = &info.update_json else { continue };
+		let Some(body) = bodies.remove(&i) else { continue };
+		let json: Result<UpdateJson> = try {
+			serde_json::from_str(&body?).with_context(|| format!("Failed to parse {url}"))?
+		};
+		let json = match json {
+			Ok(json) => json,
+			Err(err) => {
+				dispatch_debug(err.context(format!("Couldn't check {} for updates", info.mod_id)));
+				continue;
+			}
+		};
+
+		let newer = |promo: &str| json.promos
+			.get(&format!("{mc_version}-{promo}"))
+			.filter(|x| ComparableVersion::new(x.trim()) > info.version)
+			.cloned();
+		let latest = newer("latest");
+		let recommended = newer("recommended");
+		if latest.is_none() && recommended.is_none() {
+			continue;
+		}
+
+		updates.push(ModUpdate {
+			mod_id: info.mod_id.clone(),
+			current: info.version.to_string(),
+			latest,
+			recommended,
+			homepage: json.homepage.filter(|x| !x.is_empty()).or_else(|| info.url.clone()),
+		});
+	}
+	updates
+}
+
+// Update JSON bodies by index into `mods`, a few at a time. The fetchers aren't waited for,
+// one still going at the deadline finishes on its own and is ignored
+fn fetch_all(mods: &[ModInfo], fetcher: &Arc<dyn Fetcher>, deadline: Duration) -> HashMap<usize, Result<String>> {
+	let urls: Vec<(usize, String)> = mods
+		.iter()
+		.enumerate()
+		.filter_map(|(i, x)| Some((i, x.update_json.clone()?)))
+		.collect();
+	let total = urls.len();
+	let queue = Arc::new(Mutex::new(urls.into_iter()));
+	let (send, recv) = channel();
+	for _ in 0..FETCHERS.min(total) {
+		let queue = queue.clone();
+		let send = send.clone();
+		let fetcher = fetcher.clone();
+		thread::spawn(move || loop {
+			let Some((i, url)) = queue.lock().unwrap().next() else { break };
+			let body = fetcher.fetch(&url).with_context(|| format!("Failed to fetch {url}"));
+			if send.send((i, body)).is_err() { break }
+		});
+	}
+	drop(send);
+
+	let end = Instant::now() + deadline;
+	let mut bodies = HashMap::new();
+	while let Some(left) = end.checked_duration_since(Instant::now()) {
+		match recv.recv_timeout(left) {
+			Ok((i, body)) => {
+				bodies.insert(i, body);
+			}
+			Err(RecvTimeoutError::Timeout) => break,
+			Err(RecvTimeoutError::Disconnected) => return bodies,
+		}
+	}
+	// What's left isn't worth fetching anymore
+	queue.lock().unwrap().by_ref().for_each(drop);
+	dispatch_debug(format!("Ran out of time checking for updates, {} mods weren't checked", total - bodies.len()));
+	bodies
+}
+
@@ -0,0 +1,505 @@
+use std::path::Path;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use anyhow::{bail, Result};
+use mvn_version::ComparableVersion;
+use std::sync::Arc;
+use sv_manage::testing::{HttpStandIn, TestManager, TIMEOUT};
+use sv_manage::updates::HttpFetcher;
+use yapper::{DepResolveMode, DepState, ErrorKind, ModUpdate, NetCommand, Notification, parse_mod, parse_mods, Response, ServerCommand, Status};
+use yapper::testing::{FABRIC_LOADER_VERSION, LEGACY_FORGE_VERSION, LEGACY_MC_VERSION, Loader, MC_VERSION, mod_jar, NEOFORGE_VERSION, TempDir, write_jar};
+
+// GenerateModsZip isn't covered, it uploads to spam
+
+fn until(mut f: impl FnMut() -> Result<bool>) -> Result<()> {
+	let start = Instant::now();
+	while !f()? {
+		assert!(start.elapsed() < TIMEOUT, "Timed out");
+		sleep(Duration::from_millis(50));
+	}
+	Ok(())
+}
+
+#[test]
+fn list_and_status() -> Result<()> {
+	let sv = TestManager::start(&["alpha", "beta"])?;
+
+	let Response::List(mut list) = sv.request(NetCommand::ListServers)? else { panic!() };
+	list.sort_by(|a, b| a.name.cmp(&b.name));
+	assert_eq!(list.iter().map(|x| x.name.as_str()).collect::<Vec<_>>(), ["alpha", "beta"]);
+	assert!(list.iter().all(|x| x.status == Status::Idle));
+
+	assert_eq!(sv.status("alpha")?, Status::Idle);
+	assert_eq!(sv.server("gamma", ServerCommand::Status)?, Response::UnknownServer);
+	Ok(())
+}
+
+#[test]
+fn start_console_reboot_stop() -> Result<()> {
+	let sv = TestManager::start(&["alpha"])?;
+
+	let response = sv.server("alpha", ServerCommand::Console("say hi".to_owned()))?;
+	assert!(matches!(response, Response::Error { kind: ErrorKind::InvalidState, .. }), "{response}");
+
+	assert_eq!(sv.server("alpha", ServerCommand::Start)?, Response::Ok);
+	sv.wait_for("alpha", Status::Running)?;
+
+	let response = sv.server("alpha", ServerCommand::Console("say hi".to_owned()))?;
+	assert!(matches!(response, Response::CommandOutput(_)), "{response}");
+
+	// Can't back up a running server
+	let response = sv.server("alpha", ServerCommand::Backup)?;
+	assert!(matches!(response, Response::Error { kind: ErrorKind::InvalidState, .. }), "{response}");
+
+	assert_eq!(sv.server("alpha", ServerCommand::Reboot)?, Response::Ok);
+	sv.wait_for("alpha", Status::Running)?;
+
+	assert_eq!(sv.server("alpha", ServerCommand::Stop)?, Response::Ok);
+	sv.wait_for("alpha", Status::Idle)?;
+	Ok(())
+}
+
+#[test]
+fn backup_and_restore() -> Result<()> {
+	let sv = TestManager::start(&["alpha"])?;
+	let backup = sv.dir().join("alpha.bak");
+
+	assert_eq!(sv.server("alpha", ServerCommand::Restore)?, Response::NoBackup);
+
+	assert_eq!(sv.server("alpha", ServerCommand::Backup)?, Response::Ok);
+	until(|| Ok(backup.join("run.sh").is_file() && sv.status("alpha")? == Status::Idle))?;
+
+	std::fs::write(sv.server_path("alpha").join("world.dat"), "changed")?;
+	assert_eq!(sv.server("alpha", ServerCommand::Restore)?, Response::Ok);
+	until(|| Ok(!sv.server_path("alpha").join("world.dat").exists() && sv.status("alpha")? == Status::Idle))?;
+
+	// Both show up in the persistent log, in order
+	let Response::NotificationLog(entries) = sv.request(NetCommand::NotificationsAfter(0))? else { panic!() };
+	assert!(entries.windows(2).all(|x| x[0].id < x[1].id));
+	let became = |status| entries.iter().any(|x| matches!(x.notif, Notification::StatusChanged(_, _, new) if new == status));
+	assert!(became(Status::BackingUp));
+	assert!(became(Status::Restoring));
+
+	let last = entries.last().unwrap().id;
+	assert_eq!(sv.request(NetCommand::NotificationsAfter(last))?, Response::NotificationLog(Vec::new()));
+
+	// Persisted next to the config rather than in it
+	let persisted = std::fs::read_to_string(sv.dir().join("notifications").join(format!("{}.jsonl", sv.account)))?;
+	assert_eq!(persisted.lines().count(), entries.iter().filter(|x| !x.notif.is_progress()).count());
+	Ok(())
+}
+
+#[test]
+fn mods() -> Result<()> {
+	let sv = TestManager::start(&["alpha"])?;
+	let jars = TempDir::new("jars")?;
+	let jar = |name: &str, mod_id: &str, version: &str, deps: &[(&str, &str)]| -> Result<String> {
+		Ok(mod_jar(&jars.path().join(name), mod_id, version, deps)?.to_string_lossy().to_string())
+	};
+
+	let install = ServerCommand::InstallMod(jar("example.jar", "example", "1.0", &[])?, "example.jar".to_owned());
+	assert_eq!(sv.server("alpha", install)?, Response::Ok);
+
+	let Response::Mod(info) = sv.server("alpha", ServerCommand::QueryMod("example".to_owned()))? else { panic!() };
+	assert_eq!(info.version, ComparableVersion::new("1.0"));
+
+	let Response::Mods(list, _) = sv.server("alpha", ServerCommand::ListMods(0, 0))? else { panic!() };
+	for mod_id in ["example", "forge", "minecraft"] {
+		assert!(list.iter().any(|x| x.mod_id == mod_id), "{mod_id} missing");
+	}
+
+	let install = ServerCommand::InstallMod(jar("example2.jar", "example", "2.0", &[])?, "example2.jar".to_owned());
+	assert_eq!(sv.server("alpha", install)?, Response::ModConflict);
+
+	// The very same jar again
+	let copy = jars.path().join("copy.jar");
+	std::fs::copy(sv.server_path("alpha").join("mods/example.jar"), &copy)?;
+	let install = ServerCommand::InstallMod(copy.to_string_lossy().to_string(), "copy.jar".to_owned());
+	assert_eq!(sv.server("alpha", install)?, Response::DuplicateMod("example.jar".to_owned()));
+
+	let update = ServerCommand::UpdateMod(jar("example-1.1.jar", "example", "1.1", &[])?, "example.jar".to_owned());
+	assert_eq!(sv.server("alpha", update)?, Response::Ok);
+	let Response::Mod(info) = sv.server("alpha", ServerCommand::QueryMod("example".to_owned()))? else { panic!() };
+	assert_eq!(info.version, ComparableVersion::new("1.1"));
+
+	let needs = |name: &str, range: &str| -> Result<Response> {
+		let addon = parse_mod(Path::new(&jar(name, "addon", "1.0", &[("example", range)])?))?;
+		sv.server("alpha", ServerCommand::ResolveDeps(DepResolveMode::Installation, vec![addon]))
+	};
+	assert_eq!(needs("addon.jar", "[1.0,)")?, Response::DepSatisfied);
+	let Response::DepUnsatisfied(unsat) = needs("addon-next.jar", "[2.0,)")? else { panic!() };
+	assert!(matches!(&unsat[..], [(mod_id, DepState::VersionMismatch(_))] if mod_id == "example"), "{unsat:?}");
+
+	assert_eq!(sv.server("alpha", ServerCommand::UninstallMod("example".to_owned()))?, Response::Ok);
+	assert_eq!(sv.server("alpha", ServerCommand::UninstallMod("example".to_owned()))?, Response::NoSuchMod);
+	Ok(())
+}
+
+#[test]
+fn loader_version() -> Result<()> {
+	let sv = TestManager::start(&["alpha"])?;
+	let jars = TempDir::new("jars")?;
+
+	// The fake server runs forge 47.2.0
+	let needs = |loader: &str, range: &str| -> Result<Response> {
+		let path = jars.path().join(format!("needs-{loader}.jar"));
+		let toml = format!(
+			"modLoader = \"{loader}\"\nloaderVersion = \"{range}\"\nlicense = \"MIT\"\n\n\
+			[[mods]]\nmodId = \"needy\"\nversion = \"1.0\"\n"
+		);
+		write_jar(&path, &[("META-INF/mods.toml", toml.as_bytes())])?;
+		sv.server("alpha", ServerCommand::ResolveDeps(DepResolveMode::Installation, vec![parse_mod(&path)?]))
+	};
+
+	assert_eq!(needs("javafml", "[47,)")?, Response::DepSatisfied);
+	let Response::DepUnsatisfied(unsat) = needs("javafml", "[49,)")? else { panic!() };
+	assert!(matches!(&unsat[..], [(mod_id, DepState::LoaderMismatch(..))] if mod_id == "needy"), "{unsat:?}");
+	// Versioned on its own
+	assert_eq!(needs("kotlinforforge", "[4,)")?, Response::DepSatisfied);
+
+	// NeoForge's library folder is just its version, suffix included
+	let sv = TestManager::start_ext(&["beta"], Loader::NeoForge, Arc::new(HttpFetcher::new()), None)?;
+	let Response::Mod(neoforge) = sv.server("beta", ServerCommand::QueryMod("neoforge".to_owned()))? else { panic!() };
+	assert_eq!(neoforge.version, ComparableVersion::new(NEOFORGE_VERSION));
+	let Response::Mod(minecraft) = sv.server("beta", ServerCommand::QueryMod("minecraft".to_owned()))? else { panic!() };
+	assert_eq!(minecraft.version, ComparableVersion::new("1.20.4"));
+	Ok(())
+}
+
+#[test]
+fn fabric_server() -> Result<()> {
+	let sv = TestManager::start_ext(&["alpha"], Loader::Fabric, Arc::new(HttpFetcher::new()), None)?;
+	let jars = TempDir::new("jars")?;
+
+	// Nothing in run.sh to go by, the versions come from /libraries
+	let Response::Mod(loader) = sv.server("alpha", ServerCommand::QueryMod("fabricloader".to_owned()))? else { panic!() };
+	assert_eq!(loader.version, ComparableVersion::new(FABRIC_LOADER_VERSION));
+	let Response::Mod(minecraft) = sv.server("alpha", ServerCommand::QueryMod("minecraft".to_owned()))? else { panic!() };
+	assert_eq!(minecraft.version, ComparableVersion::new(MC_VERSION));
+
+	let needs = |loader: &str, minecraft: &str| -> Result<Response> {
+		let path = jars.path().join("needy.jar");
+		let json = format!(
+			r#"{{ "schemaVersion": 1, "id": "needy", "version": "1.0", "depends": {{ "fabricloader": "{loader}", "minecraft": "{minecraft}" }} }}"#
+		);
+		write_jar(&path, &[("fabric.mod.json", json.as_bytes())])?;
+		sv.server("alpha", ServerCommand::ResolveDeps(DepResolveMode::Installation, vec![parse_mod(&path)?]))
+	};
+
+	assert_eq!(needs(">=0.15.0", "~1.20.1")?, Response::DepSatisfied);
+	let Response::DepUnsatisfied(unsat) = needs(">=0.16.0", "~1.20.1")? else { panic!() };
+	assert!(matches!(&unsat[..], [(mod_id, DepState::VersionMismatch(_))] if mod_id == "fabricloader"), "{unsat:?}");
+	let Response::DepUnsatisfied(unsat) = needs(">=0.15.0", "<1.20")? else { panic!() };
+	assert!(matches!(&unsat[..], [(mod_id, DepState::VersionMismatch(_))] if mod_id == "minecraft"), "{unsat:?}");
+
+	// Recommendations are only a warning, even for the wrong version
+	let example = mod_jar(&jars.path().join("example.jar"), "example", "1.5", &[])?;
+	let install = ServerCommand::InstallMod(example.to_string_lossy().to_string(), "example.jar".to_owned());
+	assert_eq!(sv.server("alpha", install)?, Response::Ok);
+	let recommends = |range: &str| -> Result<Response> {
+		let path = jars.path().join("fond.jar");
+		let json = format!(r#"{{ "schemaVersion": 1, "id": "fond", "version": "1.0", "recommends": {{ "example": "{range}" }} }}"#);
+		write_jar(&path, &[("fabric.mod.json", json.as_bytes())])?;
+		sv.server("alpha", ServerCommand::ResolveDeps(DepResolveMode::Installation, vec![parse_mod(&path)?]))
+	};
+	assert_eq!(recommends(">=1.0")?, Response::DepSatisfied);
+	let Response::DepUnsatisfied(unsat) = recommends(">=2.0")? else { panic!() };
+	assert!(matches!(&unsat[..], [(mod_id, DepState::Discouraged(_))] if mod_id == "example"), "{unsat:?}");
+	Ok(())
+}
+
+#[test]
+fn legacy_forge_server() -> Result<()> {
+	let sv = TestManager::start_ext(&["alpha", "renamed"], Loader::LegacyForge, Arc::new(HttpFetcher::new()), None)?;
+	let jars = TempDir::new("jars")?;
+
+	let version = |server: &str, mod_id: &str| -> Result<ComparableVersion> {
+		let Response::Mod(info) = sv.server(server, ServerCommand::QueryMod(mod_id.to_owned()))? else { panic!() };
+		Ok(info.version)
+	};
+
+	// Started from the forge jar itself, which has both versions in its name
+	assert_eq!(version("alpha", "forge")?, ComparableVersion::new(LEGACY_FORGE_VERSION));
+	assert_eq!(version("alpha", "minecraft")?, ComparableVersion::new(LEGACY_MC_VERSION));
+
+	// Or in its version.json when it's been renamed
+	let renamed = sv.server_path("renamed");
+	std::fs::rename(
+		renamed.join(format!("forge-{LEGACY_MC_VERSION}-{LEGACY_FORGE_VERSION}.jar")),
+		renamed.join("forge-universal.jar"),
+	)?;
+	assert_eq!(version("renamed", "forge")?, ComparableVersion::new(LEGACY_FORGE_VERSION));
+	assert_eq!(version("renamed", "minecraft")?, ComparableVersion::new(LEGACY_MC_VERSION));
+
+	let needs = |range: &str| -> Result<Response> {
+		let path = jars.path().join("needy.jar");
+		let json = format!(
+			r#"{{ "modListVersion": 2, "modList": [{{ "modid": "needy", "version": "1.0", "mcversion": "{LEGACY_MC_VERSION}", "requiredMods": ["required-after:forge@{range}"] }}] }}"#
+		);
+		write_jar(&path, &[("mcmod.info", json.as_bytes())])?;
+		sv.server("alpha", ServerCommand::ResolveDeps(DepResolveMode::Installation, vec![parse_mod(&path)?]))
+	};
+
+	assert_eq!(needs("[14.23,)")?, Response::DepSatisfied);
+	let Response::DepUnsatisfied(unsat) = needs("[15.0,)")? else { panic!() };
+	assert!(matches!(&unsat[..], [(mod_id, DepState::VersionMismatch(_))] if mod_id == "forge"), "{unsat:?}");
+	Ok(())
+}
+
+#[test]
+fn dependency_kinds() -> Result<()> {
+	let sv = TestManager::start(&["alpha"])?;
+	let jars = TempDir::new("jars")?;
+
+	let example = mod_jar(&jars.path().join("example.jar"), "example", "1.5", &[])?;
+	let install = ServerCommand::InstallMod(example.to_string_lossy().to_string(), "example.jar".to_owned());
+	assert_eq!(sv.server("alpha", install)?, Response::Ok);
+
+	let needs = |kind: &str, range: &str| -> Result<Response> {
+		let path = jars.path().join(format!("addon-{kind}.jar"));
+		let toml = format!(
+			"modLoader = \"javafml\"\nloaderVersion = \"[4,)\"\nlicense = \"MIT\"\n\n\
+			[[mods]]\nmodId = \"addon\"\nversion = \"1.0\"\n\n\
+			[[dependencies.addon]]\nmodId = \"example\"\ntype = \"{kind}\"\nversionRange = \"{range}\"\nside = \"BOTH\"\n\n\
+			[[dependencies.addon]]\nmodId = \"missing\"\ntype = \"optional\"\nside = \"BOTH\"\n"
+		);
+		write_jar(&path, &[("META-INF/neoforge.mods.toml", toml.as_bytes())])?;
+		sv.server("alpha", ServerCommand::ResolveDeps(DepResolveMode::Installation, vec![parse_mod(&path)?]))
+	};
+
+	// A missing optional dependency is fine, and so is one outside the range of a conflict
+	assert_eq!(needs("optional", "[1.0,)")?, Response::DepSatisfied);
+	assert_eq!(needs("incompatible", "[2.0,)")?, Response::DepSatisfied);
+
+	let Response::DepUnsatisfied(unsat) = needs("optional", "[2.0,)")? else { panic!() };
+	assert!(matches!(&unsat[..], [(_, DepState::VersionMismatch(_))]), "{unsat:?}");
+	let Response::DepUnsatisfied(unsat) = needs("incompatible", "[1.0,2.0)")? else { panic!() };
+	assert!(matches!(&unsat[..], [(_, DepState::Incompatible(_))]), "{unsat:?}");
+	let Response::DepUnsatisfied(unsat) = needs("discouraged", "[1.0,2.0)")? else { panic!() };
+	assert!(matches!(&unsat[..], [(_, DepState::Discouraged(_))]), "{unsat:?}");
+	Ok(())
+}
+
+#[test]
+fn alternatives() -> Result<()> {
+	let sv = TestManager::start(&["alpha"])?;
+	let jars = TempDir::new("jars")?;
+
+	let example = mod_jar(&jars.path().join("example.jar"), "example", "1.5", &[])?;
+	let install = ServerCommand::InstallMod(example.to_string_lossy().to_string(), "example.jar".to_owned());
+	assert_eq!(sv.server("alpha", install)?, Response::Ok);
+
+	let needs = |range: &str| -> Result<Response> {
+		let path = jars.path().join("quilted.jar");
+		let json = format!(
+			r#"{{ "schema_version": 1, "quilt_loader": {{ "id": "quilted", "version": "1.0", "depends": [[{{ "id": "missing" }}, {{ "id": "example", "versions": "{range}" }}]] }} }}"#
+		);
+		write_jar(&path, &[("quilt.mod.json", json.as_bytes())])?;
+		sv.server("alpha", ServerCommand::ResolveDeps(DepResolveMode::Installation, vec![parse_mod(&path)?]))
+	};
+
+	// Either one will do
+	assert_eq!(needs(">=1.0")?, Response::DepSatisfied);
+	let Response::DepUnsatisfied(unsat) = needs(">=2.0")? else { panic!() };
+	assert!(matches!(&unsat[..], [(mod_id, DepState::NotInstalled)] if mod_id == "missing"), "{unsat:?}");
+	Ok(())
+}
+
+#[test]
+fn bundles() -> Result<()> {
+	let sv = TestManager::start(&["alpha"])?;
+	let jars = TempDir::new("jars")?;
+	let bundle = |name: &str, version: &str| -> Result<String> {
+		let path = jars.path().join(name);
+		let toml = format!(
+			"modLoader = \"javafml\"\nloaderVersion = \"[47,)\"\nlicense = \"MIT\"\n\n\
+			[[mods]]\nmodId = \"bundle\"\nversion = \"{version}\"\n\n\
+			[[mods]]\nmodId = \"bundle_lib\"\nversion = \"0.3\"\n"
+		);
+		write_jar(&path, &[("META-INF/mods.toml", toml.as_bytes())])?;
+		Ok(path.to_string_lossy().to_string())
+	};
+	let mod_ids = || -> Result<Vec<String>> {
+		let Response::Mods(list, _) = sv.server("alpha", ServerCommand::ListMods(0, 0))? else { panic!() };
+		Ok(list.into_iter().map(|x| x.mod_id).filter(|x| x.starts_with("bundle")).collect())
+	};
+
+	let install = ServerCommand::InstallMod(bundle("bundle.jar", "1.0")?, "bundle.jar".to_owned());
+	assert_eq!(sv.server("alpha", install)?, Response::Ok);
+	assert_eq!(mod_ids()?, ["bundle", "bundle_lib"]);
+
+	// Either of them clashes
+	let lib = mod_jar(&jars.path().join("lib.jar"), "bundle_lib", "0.4", &[])?;
+	let install = ServerCommand::InstallMod(lib.to_string_lossy().to_string(), "lib.jar".to_owned());
+	assert_eq!(sv.server("alpha", install)?, Response::ModConflict);
+
+	// Only the library didn't change, that's still an update
+	let next = parse_mods(Path::new(&bundle("bundle-next.jar", "1.1")?))?;
+	assert_eq!(sv.server("alpha", ServerCommand::ResolveDeps(DepResolveMode::Update, next))?, Response::DepSatisfied);
+	let update = ServerCommand::UpdateMod(bundle("bundle-1.1.jar", "1.1")?, "bundle-1.1.jar".to_owned());
+	assert_eq!(sv.server("alpha", update)?, Response::Ok);
+	let Response::Mod(info) = sv.server("alpha", ServerCommand::QueryMod("bundle_lib".to_owned()))? else { panic!() };
+	assert_eq!(info.filename, "bundle-1.1.jar");
+
+	// Not while something needs the rest of the jar
+	let needy = mod_jar(&jars.path().join("needy.jar"), "needy", "1.0", &[("bundle_lib", "[0.1,)")])?;
+	let install = ServerCommand::InstallMod(needy.to_string_lossy().to_string(), "needy.jar".to_owned());
+	assert_eq!(sv.server("alpha", install)?, Response::Ok);
+	let Response::DepUnsatisfied(unsat) = sv.server("alpha", ServerCommand::UninstallMod("bundle".to_owned()))? else { panic!() };
+	assert!(matches!(&unsat[..], [(mod_id, DepState::NotInstalled)] if mod_id == "bundle_lib"), "{unsat:?}");
+	assert_eq!(mod_ids()?, ["bundle", "bundle_lib"]);
+	assert_eq!(sv.server("alpha", ServerCommand::UninstallMod("needy".to_owned()))?, Response::Ok);
+
+	// Removing one takes the whole jar, and says what else went
+	let removed = sv.server("alpha", ServerCommand::UninstallMod("bundle_lib".to_owned()))?;
+	assert_eq!(removed, Response::ModsRemoved(vec!["bundle".to_owned()]));
+	assert!(mod_ids()?.is_empty());
+
+	// Same for updating to a jar without the library
+	let install = ServerCommand::InstallMod(bundle("bundle-again.jar", "1.0")?, "bundle.jar".to_owned());
+	assert_eq!(sv.server("alpha", install)?, Response::Ok);
+	let alone = mod_jar(&jars.path().join("alone.jar"), "bundle", "1.2", &[])?;
+	let update = ServerCommand::UpdateMod(alone.to_string_lossy().to_string(), "bundle-1.2.jar".to_owned());
+	assert_eq!(sv.server("alpha", update)?, Response::ModsRemoved(vec!["bundle_lib".to_owned()]));
+	assert_eq!(mod_ids()?, ["bundle"]);
+	Ok(())
+}
+
+#[test]
+fn bundled_dependencies() -> Result<()> {
+	let sv = TestManager::start(&["alpha"])?;
+	let jars = TempDir::new("jars")?;
+
+	let lib = std::fs::read(mod_jar(&jars.path().join("lib.jar"), "some_lib", "2.3.1", &[])?)?;
+	let outer = jars.path().join("outer.jar");
+	let metadata = r#"{ "jars": [{
+		"identifier": { "group": "com.example", "artifact": "some_lib" },
+		"version": { "range": "[2.3,3)", "artifactVersion": "2.3.1" },
+		"path": "META-INF/jarjar/lib.jar"
+	}] }"#;
+	let toml = "modLoader = \"javafml\"\nloaderVersion = \"[47,)\"\nlicense = \"MIT\"\n\n[[mods]]\nmodId = \"outer\"\nversion = \"1.0\"\n";
+	write_jar(&outer, &[
+		("META-INF/mods.toml", toml.as_bytes()),
+		("META-INF/jarjar/metadata.json", metadata.as_bytes()),
+		("META-INF/jarjar/lib.jar", &lib),
+	])?;
+	let install = ServerCommand::InstallMod(outer.to_string_lossy().to_string(), "outer.jar".to_owned());
+	assert_eq!(sv.server("alpha", install)?, Response::Ok);
+
+	let needs = |name: &str, range: &str| -> Result<Response> {
+		let addon = parse_mod(&mod_jar(&jars.path().join(name), "addon", "1.0", &[("some_lib", range)])?)?;
+		sv.server("alpha", ServerCommand::ResolveDeps(DepResolveMode::Installation, vec![addon]))
+	};
+	assert_eq!(needs("addon.jar", "[2.0,)")?, Response::DepSatisfied);
+	let Response::DepUnsatisfied(unsat) = needs("addon-next.jar", "[3.0,)")? else { panic!() };
+	assert!(matches!(&unsat[..], [(mod_id, DepState::VersionMismatch(_))] if mod_id == "some_lib"), "{unsat:?}");
+	Ok(())
+}
+
+#[test]
+fn update_check() -> Result<()> {
+	let http = HttpStandIn::start()?;
+	let sv = TestManager::start_with_fetcher(&["alpha"], Arc::new(HttpFetcher::new()))?;
+	let jars = TempDir::new("jars")?;
+	let install = |mod_id: &str, version: &str, update_json: &str| -> Result<Response> {
+		let path = jars.path().join(format!("{mod_id}.jar"));
+		let toml = format!(
+			"modLoader = \"javafml\"\nloaderVersion = \"[47,)\"\nlicense = \"MIT\"\n\n\
+			[[mods]]\nmodId = \"{mod_id}\"\nversion = \"{version}\"\nupdateJSONURL = \"{}\"\n",
+			http.url(update_json)
+		);
+		write_jar(&path, &[("META-INF/mods.toml", toml.as_bytes())])?;
+		sv.server("alpha", ServerCommand::InstallMod(path.to_string_lossy().to_string(), format!("{mod_id}.jar")))
+	};
+
+	http.put("/stale.json", r#"{
+		"homepage": "https://example.com/stale",
+		"1.20.1": { "1.2": "Fixes" },
+		"promos": { "1.20.1-latest": "1.2", "1.20.1-recommended": "1.1", "1.19.2-latest": "9.0" }
+	}"#);
+	http.put("/fresh.json", r#"{ "promos": { "1.20.1-latest": "2.0", "1.20.1-recommended": "1.5" } }"#);
+	assert_eq!(install("stale", "1.0", "/stale.json")?, Response::Ok);
+	assert_eq!(install("fresh", "2.0", "/fresh.json")?, Response::Ok);
+	// 404s, it just gets skipped
+	assert_eq!(install("gone", "1.0", "/gone.json")?, Response::Ok);
+
+	let response = sv.server("alpha", ServerCommand::CheckUpdates)?;
+	assert_eq!(response, Response::Updates(vec![ModUpdate {
+		mod_id: "stale".to_owned(),
+		current: "1.0".to_owned(),
+		latest: Some("1.2".to_owned()),
+		recommended: Some("1.1".to_owned()),
+		homepage: Some("https://example.com/stale".to_owned()),
+	}]));
+	Ok(())
+}
+
+#[test]
+fn slow_update_check() -> Result<()> {
+	let fetcher = |url: &str| -> Result<String> {
+		match url {
+			"https://example.com/stale.json" => Ok(r#"{ "promos": { "1.20.1-latest": "1.2" } }"#.to_owned()),
+			_ => {
+				sleep(Duration::from_secs(60));
+				bail!("No {url}")
+			}
+		}
+	};
+	let sv = TestManager::start_with_fetcher(&["alpha"], Arc::new(fetcher))?;
+	let jars = TempDir::new("jars")?;
+	for mod_id in ["slow", "stale", "slower"] {
+		let path = jars.path().join(format!("{mod_id}.jar"));
+		let toml = format!(
+			"modLoader = \"javafml\"\nloaderVersion = \"[47,)\"\nlicense = \"MIT\"\n\n\
+			[[mods]]\nmodId = \"{mod_id}\"\nversion = \"1.0\"\nupdateJSONURL = \"https://example.com/{mod_id}.json\"\n"
+		);
+		write_jar(&path, &[("META-INF/mods.toml", toml.as_bytes())])?;
+		let install = ServerCommand::InstallMod(path.to_string_lossy().to_string(), format!("{mod_id}.jar"));
+		assert_eq!(sv.server("alpha", install)?, Response::Ok);
+	}
+
+	// Well within mcayb's timeout, with whatever made it in time
+	let start = Instant::now();
+	let Response::Updates(updates) = sv.server("alpha", ServerCommand::CheckUpdates)? else { panic!() };
+	assert!(start.elapsed() < Duration::from_secs(10), "took {:?}", start.elapsed());
+	let mod_ids: Vec<&str> = updates.iter().map(|x| x.mod_id.as_str()).collect();
+	assert_eq!(mod_ids, ["stale"]);
+	Ok(())
+}
+
+#[test]
+fn periodic_update_check() -> Result<()> {
+	let fetcher = |url: &str| -> Result<String> {
+		match url {
+			"https://example.com/stale.json" => Ok(r#"{ "promos": { "1.20.1-latest": "1.2" } }"#.to_owned()),
+			_ => bail!("No {url}"),
+		}
+	};
+	let sv = TestManager::start_ext(&["alpha"], Loader::Forge, Arc::new(fetcher), Some(Duration::from_millis(100)))?;
+	let jars = TempDir::new("jars")?;
+
+	let path = jars.path().join("stale.jar");
+	let toml = "modLoader = \"javafml\"\nloaderVersion = \"[47,)\"\nlicense = \"MIT\"\n\n\
+		[[mods]]\nmodId = \"stale\"\nversion = \"1.0\"\nupdateJSONURL = \"https://example.com/stale.json\"\n";
+	write_jar(&path, &[("META-INF/mods.toml", toml.as_bytes())])?;
+	let install = ServerCommand::InstallMod(path.to_string_lossy().to_string(), "stale.jar".to_owned());
+	assert_eq!(sv.server("alpha", install)?, Response::Ok);
+
+	let outdated = || -> Result<Vec<Notification>> {
+		let Response::NotificationLog(entries) = sv.request(NetCommand::NotificationsAfter(0))? else { panic!() };
+		Ok(entries.into_iter().map(|x| x.notif).filter(|x| matches!(x, Notification::ModsOutdated(..))).collect())
+	};
+	until(|| Ok(!outdated()?.is_empty()))?;
+
+	// Nothing changed since, so the next checks stay quiet
+	sleep(Duration::from_millis(500));
+	let outdated = outdated()?;
+	assert_eq!(outdated, [Notification::ModsOutdated("alpha".to_owned(), vec![ModUpdate {
+		mod_id: "stale".to_owned(),
+		current: "1.0".to_owned(),
+		latest: Some("1.2".to_owned()),
+		recommended: None,
+		homepage: None,
+	}])]);
+	Ok(())
+}
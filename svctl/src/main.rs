@@ -0,0 +1,255 @@
+mod output;
+
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use yapper::{Credentials, DepResolveMode, hash_pw, NetCommand, NotificationFilter, NotificationKind, parse_mods, Response, ServerCommand, Session};
+use crate::output::Output;
+
+/// Drives sv_manage from a terminal
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Cli {
+	/// Address sv_manage listens on
+	#[arg(long, env = "SVCTL_ADDR", default_value = "127.0.0.1:23786")]
+	addr: String,
+	/// sv_manage's public key
+	#[arg(long, env = "SVCTL_KEY", default_value = "sv_manage.pem")]
+	key: PathBuf,
+	#[arg(long, short, env = "SVCTL_USER")]
+	user: String,
+	#[arg(long, env = "SVCTL_PASSWORD", hide_env_values = true)]
+	password: String,
+	/// Seconds to wait for each response
+	#[arg(long, default_value_t = 30)]
+	timeout: u64,
+	/// Print JSON instead of tables
+	#[arg(long, global = true)]
+	json: bool,
+	#[command(subcommand)]
+	command: Cmd,
+}
+
+#[derive(Debug, Subcommand)]
+enum Cmd {
+	/// List all servers
+	List,
+	Status { server: String },
+	Start { server: String },
+	Stop { server: String },
+	Reboot { server: String },
+	/// Run a command in the server console
+	Console {
+		server: String,
+		#[arg(trailing_var_arg = true, required = true)]
+		command: Vec<String>,
+	},
+	Backup { server: String },
+	Restore { server: String },
+	#[command(subcommand)]
+	Mod(ModCmd),
+	/// Package the mods folder into a zip, the link shows up as a notification
+	Zip { server: String },
+	/// List installed mods with newer versions in their update JSON
+	Updates { server: String },
+	/// Check whether the dependencies of local mod jars are satisfied
+	Deps {
+		server: String,
+		#[arg(value_enum)]
+		mode: DepMode,
+		#[arg(required = true)]
+		jars: Vec<PathBuf>,
+	},
+	/// Print the notifications that piled up since last time
+	Notifications {
+		/// Print the persistent log from after this id instead, without consuming anything
+		#[arg(long)]
+		after: Option<u64>,
+	},
+	/// Follow notifications as they happen
+	Tail {
+		#[arg(long)]
+		server: Vec<String>,
+		#[arg(long)]
+		kind: Vec<String>,
+	},
+}
+
+#[derive(Debug, Subcommand)]
+enum ModCmd {
+	List {
+		server: String,
+		#[arg(long, default_value_t = 20)]
+		per_page: u64,
+		#[arg(long, default_value_t = 0)]
+		page: u64,
+	},
+	Query { server: String, mod_id: String },
+	/// Install a jar that sv_manage can read from its own filesystem
+	Install {
+		server: String,
+		path: PathBuf,
+		/// File name in the mods folder, defaults to the jar's own
+		#[arg(long)]
+		name: Option<String>,
+	},
+	Uninstall { server: String, mod_id: String },
+	Update {
+		server: String,
+		path: PathBuf,
+		#[arg(long)]
+		name: Option<String>,
+	},
+}
+
+#[derive(Debug, Copy, Clone, ValueEnum)]
+enum DepMode {
+	Install,
+	Remove,
+	Update,
+}
+
+impl From<DepMode> for DepResolveMode {
+	fn from(value: DepMode) -> Self {
+		match value {
+			DepMode::Install => DepResolveMode::Installation,
+			DepMode::Remove => DepResolveMode::Removal,
+			DepMode::Update => DepResolveMode::Update,
+		}
+	}
+}
+
+fn server_cmd(server: String, cmd: ServerCommand) -> NetCommand {
+	NetCommand::ServerCommand(server, cmd)
+}
+
+// The server opens the file itself, so hand it something it can find
+fn mod_file(path: PathBuf, name: Option<String>) -> Result<(String, String)> {
+	let path = fs::canonicalize(&path).with_context(|| format!("Can't find {path:?}"))?;
+	let name = match name {
+		Some(name) => name,
+		None => path.file_name()
+			.context("Path has no file name")?
+			.to_string_lossy()
+			.to_string(),
+	};
+	Ok((path.to_string_lossy().to_string(), name))
+}
+
+fn command(cmd: Cmd) -> Result<NetCommand> {
+	Ok(match cmd {
+		Cmd::List => NetCommand::ListServers,
+		Cmd::Status { server } => server_cmd(server, ServerCommand::Status),
+		Cmd::Start { server } => server_cmd(server, ServerCommand::Start),
+		Cmd::Stop { server } => server_cmd(server, ServerCommand::Stop),
+		Cmd::Reboot { server } => server_cmd(server, ServerCommand::Reboot),
+		Cmd::Console { server, command } => server_cmd(server, ServerCommand::Console(command.join(" "))),
+		Cmd::Backup { server } => server_cmd(server, ServerCommand::Backup),
+		Cmd::Restore { server } => server_cmd(server, ServerCommand::Restore),
+		Cmd::Mod(ModCmd::List { server, per_page, page }) => server_cmd(server, ServerCommand::ListMods(per_page, page)),
+		Cmd::Mod(ModCmd::Query { server, mod_id }) => server_cmd(server, ServerCommand::QueryMod(mod_id)),
+		Cmd::Mod(ModCmd::Install { server, path, name }) => {
+			let (path, name) = mod_file(path, name)?;
+			server_cmd(server, ServerCommand::InstallMod(path, name))
+		}
+		Cmd::Mod(ModCmd::Uninstall { server, mod_id }) => server_cmd(server, ServerCommand::UninstallMod(mod_id)),
+		Cmd::Mod(ModCmd::Update { server, path, name }) => {
+			let (path, name) = mod_file(path, name)?;
+			server_cmd(server, ServerCommand::UpdateMod(path, name))
+		}
+		Cmd::Zip { server } => server_cmd(server, ServerCommand::GenerateModsZip),
+		Cmd::Updates { server } => server_cmd(server, ServerCommand::CheckUpdates),
+		Cmd::Deps { server, mode, jars } => {
+			let mut mods = Vec::with_capacity(jars.len());
+			for jar in jars {
+				mods.extend(parse_mods(&jar).with_context(|| format!("Failed to parse {jar:?}"))?);
+			}
+			server_cmd(server, ServerCommand::ResolveDeps(mode.into(), mods))
+		}
+		Cmd::Notifications { after: None } => NetCommand::Notifications,
+		Cmd::Notifications { after: Some(id) } => NetCommand::NotificationsAfter(id),
+		Cmd::Tail { .. } => unreachable!(),
+	})
+}
+
+fn tail(session: &Session, out: &Output, timeout: Duration, servers: Vec<String>, kinds: Vec<String>) -> Result<()> {
+	let mut filter = NotificationFilter::all();
+	if !servers.is_empty() {
+		filter.servers = Some(servers);
+	}
+	if !kinds.is_empty() {
+		let mut parsed = Vec::with_capacity(kinds.len());
+		for kind in kinds {
+			parsed.push(parse_kind(&kind)?);
+		}
+		filter.kinds = Some(parsed);
+	}
+
+	let notifs = session.subscribe(filter, timeout)?;
+	loop {
+		// Wakes up now and then so a dropped connection gets noticed and re-established
+		match notifs.recv_timeout(Duration::from_secs(5)) {
+			Ok(notif) => out.notification(&notif),
+			Err(_) => session.ensure_connected()?,
+		}
+	}
+}
+
+fn parse_kind(kind: &str) -> Result<NotificationKind> {
+	use NotificationKind::*;
+	[BackupFailed, RestoreFailed, StatusChanged, BackupProgress, RestoreProgress, ZipProgress, ZipFailed, ZipFile, ModsOutdated]
+		.into_iter()
+		.find(|x| x.to_string().eq_ignore_ascii_case(kind))
+		.with_context(|| format!("Unknown notification kind {kind:?}"))
+}
+
+fn main_wrapper() -> Result<bool> {
+	let cli = Cli::parse();
+	let out = Output::new(cli.json);
+	let timeout = Duration::from_secs(cli.timeout);
+
+	let pub_key = fs::read(&cli.key)
+		.with_context(|| format!("Failed to read public key {:?}", cli.key))?;
+	let session = Session::new(Credentials {
+		addr: cli.addr,
+		pub_key,
+		user: cli.user,
+		password: hash_pw(&cli.password),
+	});
+
+	if let Cmd::Tail { server, kind } = cli.command {
+		tail(&session, &out, timeout, server, kind)?;
+		return Ok(true);
+	}
+
+	let response = session.request(command(cli.command)?, timeout)?;
+	out.response(&response);
+
+	// Non zero exit status for anything that looks like a failure
+	Ok(!matches!(
+		response,
+		Response::Err
+			| Response::Error { .. }
+			| Response::UnknownServer
+			| Response::InvalidState
+			| Response::NoBackup
+			| Response::ModConflict
+			| Response::DuplicateMod(_)
+			| Response::NoSuchMod
+			| Response::DepUnsatisfied(_)
+			| Response::Unsupported(_)
+	))
+}
+
+fn main() -> core::result::Result<(), ()> {
+	match main_wrapper() {
+		Ok(true) => Ok(()),
+		Ok(false) => Err(()),
+		Err(err) => {
+			eprintln!("{:?}", err);
+			Err(())
+		}
+	}
+}
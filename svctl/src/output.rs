@@ -0,0 +1,202 @@
+use yapper::{DepState, json, ModInfo, ModUpdate, Notification, NotificationEntry, Response, ServerStatus};
+
+pub struct Output {
+	json: bool,
+}
+
+impl Output {
+	pub fn new(json: bool) -> Self {
+		Self { json }
+	}
+
+	pub fn response(&self, response: &Response) {
+		if self.json {
+			println!("{}", json::response_json(response));
+			return;
+		}
+
+		match response {
+			Response::Ok => println!("Ok"),
+			Response::Err => println!("Error"),
+			Response::UnknownServer => println!("Unknown server"),
+			Response::InvalidState => println!("The server can't do that right now"),
+			Response::NoBackup => println!("There is no backup to restore"),
+			Response::Status(status) => servers(std::slice::from_ref(status)),
+			Response::List(list) => servers(list),
+			Response::CommandOutput(output) => println!("{output}"),
+			Response::Notifications(notifs) => {
+				for notif in notifs {
+					self.notification(notif);
+				}
+			}
+			Response::ModConflict => println!("A mod with the same id is already installed"),
+			Response::DuplicateMod(filename) => println!("The exact same jar is already installed as {filename}"),
+			Response::ModsRemoved(mod_ids) => println!("Ok, {} were in the same jar and got removed too", mod_ids.join(", ")),
+			Response::NoSuchMod => println!("No such mod"),
+			Response::Mods(list, more) => {
+				mods(list);
+				if *more {
+					println!("(more on the next page)");
+				}
+			}
+			Response::Mod(info) => single_mod(info),
+			Response::DepUnsatisfied(deps) => {
+				println!("Dependencies not satisfied:");
+				table(&["MOD", "PROBLEM"], deps.iter().map(|(mod_id, state)| vec![mod_id.clone(), dep_state(state)]).collect());
+			}
+			Response::DepSatisfied => println!("Dependencies satisfied"),
+			Response::Unsupported(missing) => println!("The server doesn't support this ({missing:?})"),
+			Response::Error { kind, message, context } => {
+				println!("Error ({kind}): {message}");
+				for cause in context {
+					println!("  caused by: {cause}");
+				}
+			}
+			Response::NotificationLog(entries) => {
+				for entry in entries {
+					self.entry(entry);
+				}
+			}
+			Response::Updates(list) if list.is_empty() => println!("Everything is up to date"),
+			Response::Updates(list) => updates(list),
+		}
+	}
+
+	pub fn entry(&self, entry: &NotificationEntry) {
+		if self.json {
+			println!("{}", json::entry_json(entry));
+		} else {
+			let notif = &entry.notif;
+			println!("#{} {} {} [{}] {}: {notif}", entry.id, entry.time, entry.severity, notif.server(), notif.kind());
+		}
+	}
+
+	pub fn notification(&self, notif: &Notification) {
+		if self.json {
+			println!("{}", json::notification_json(notif));
+		} else {
+			println!("[{}] {}: {notif}", notif.server(), notif.kind());
+		}
+	}
+}
+
+fn servers(list: &[ServerStatus]) {
+	table(&["NAME", "STATUS", "PATH"], list.iter().map(|x| vec![x.name.clone(), x.status.to_string(), x.path.clone()]).collect());
+}
+
+fn mods(list: &[ModInfo]) {
+	table(&["ID", "NAME", "VERSION", "FILE"], list.iter().map(|x| vec![
+		x.mod_id.clone(),
+		x.name().to_owned(),
+		x.version.to_string(),
+		x.filename.clone(),
+	]).collect());
+}
+
+fn updates(list: &[ModUpdate]) {
+	table(&["ID", "CURRENT", "LATEST", "RECOMMENDED", "HOMEPAGE"], list.iter().map(|x| vec![
+		x.mod_id.clone(),
+		x.current.clone(),
+		x.latest.clone().unwrap_or_default(),
+		x.recommended.clone().unwrap_or_default(),
+		x.homepage.clone().unwrap_or_default(),
+	]).collect());
+}
+
+fn single_mod(info: &ModInfo) {
+	let mut rows = vec![
+		vec!["id".to_owned(), info.mod_id.clone()],
+		vec!["name".to_owned(), info.name().to_owned()],
+		vec!["version".to_owned(), info.version.to_string()],
+		vec!["file".to_owned(), info.filename.clone()],
+	];
+	if let Some(url) = &info.url {
+		rows.push(vec!["url".to_owned(), url.clone()]);
+	}
+	if let Some(authors) = &info.authors {
+		rows.push(vec!["authors".to_owned(), authors.join(", ")]);
+	}
+	if let Some(credits) = &info.credits {
+		rows.push(vec!["credits".to_owned(), credits.clone()]);
+	}
+	if let Some(license) = &info.license {
+		rows.push(vec!["license".to_owned(), license.clone()]);
+	}
+	if let Some(loader) = &info.loader {
+		let range = info.loader_version.as_ref().map(|x| format!(" {x}")).unwrap_or_default();
+		rows.push(vec!["loader".to_owned(), format!("{loader}{range}")]);
+	}
+	if let Some(issues) = &info.issue_tracker {
+		rows.push(vec!["issues".to_owned(), issues.clone()]);
+	}
+	if info.client_side_only {
+		rows.push(vec!["side".to_owned(), "client only".to_owned()]);
+	}
+	if let Some(hashes) = &info.hashes {
+		rows.push(vec!["sha1".to_owned(), hashes.sha1.clone()]);
+		rows.push(vec!["fingerprint".to_owned(), hashes.murmur2.to_string()]);
+	}
+	if let Some(description) = &info.description {
+		rows.push(vec!["description".to_owned(), description.trim().replace('\n', " ")]);
+	}
+	table(&["", ""], rows);
+
+	if !info.dependencies.is_empty() {
+		println!();
+		// Any of the alternatives does, so they share a row
+		table(&["DEPENDENCY", "KIND", "RANGE", "SIDE"], info.dependencies.iter().map(|dep| vec![
+			dep.alternatives.iter().fold(dep.mod_id.clone(), |all, (mod_id, _)| format!("{all} or {mod_id}")),
+			format!("{:?}", dep.kind),
+			dep.alternatives.iter().fold(dep.range.to_string(), |all, (_, range)| format!("{all} or {range}")),
+			format!("{:?}", dep.side),
+		]).collect());
+	}
+
+	if !info.provides.is_empty() {
+		println!();
+		table(&["BUNDLED", "VERSION"], info.provides.iter().map(|x| vec![
+			x.mod_id.clone(),
+			x.version.to_string(),
+		]).collect());
+	}
+}
+
+fn dep_state(state: &DepState) -> String {
+	match state {
+		DepState::NotInstalled => "not installed".to_owned(),
+		DepState::VersionMismatch(got) => format!("version mismatch, got {got}"),
+		DepState::AlreadyInstalled => "already installed".to_owned(),
+		DepState::InvalidInput => "invalid input".to_owned(),
+		DepState::Incompatible(got) => format!("incompatible, got {got}"),
+		DepState::Discouraged(got) => format!("discouraged, got {got}"),
+		DepState::LoaderMismatch(needs, got) => format!("needs forge {needs}, got {got}"),
+	}
+}
+
+fn table(headers: &[&str], rows: Vec<Vec<String>>) {
+	let mut widths: Vec<usize> = headers.iter().map(|x| x.chars().count()).collect();
+	for row in rows.iter() {
+		for (i, cell) in row.iter().enumerate() {
+			widths[i] = widths[i].max(cell.chars().count());
+		}
+	}
+
+	let print_row = |cells: Vec<&str>| {
+		let mut line = String::new();
+		for (i, cell) in cells.iter().enumerate() {
+			if i + 1 == cells.len() {
+				line.push_str(cell);
+			} else {
+				line.push_str(&format!("{cell:<width$}  ", width = widths[i]));
+			}
+		}
+		println!("{}", line.trim_end());
+	};
+
+	if headers.iter().any(|x| !x.is_empty()) {
+		print_row(headers.to_vec());
+	}
+	for row in rows.iter() {
+		print_row(row.iter().map(String::as_str).collect());
+	}
+}
@@ -1,5 +1,4 @@
 use std::env;
-use std::env::args;
 
 fn main() {
 	let mut out = String::new();
@@ -13,5 +12,9 @@ fn main() {
 		}
 	}
 	let hash = yapper::hash_pw(&out);
-	println!("pw: {out:?}, hash: {hash:?}");
-}
\ No newline at end of file
+	println!("pw: {out:?}, hash (client side, e.g. mcayb's sv_pass): {hash:?}");
+
+	// What goes in the account's "credentials" field in sv_manage.json
+	let credentials = yapper::StoredCredentials::new(&hash).unwrap();
+	println!("credentials (server side): {}", serde_json::to_string(&credentials).unwrap());
+}
@@ -0,0 +1,320 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+use anyhow::{anyhow, bail, Context, Result};
+use ende::{Decode, Encode};
+use ende::io::VecStream;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use crate::{Capabilities, Crypt, CryptRecv, CryptSend, decode_frame, dispatch_debug, encode_frame, FrameLimit, handshake_key, HelloPacket, HelloResponse, KEEPALIVE_INTERVAL, LoginChallenge, login_nonce, login_proof, LoginPacket, LoginProof, LoginResponse, NetCommand, Notification, NotificationFilter, protocol_context, Response, SESSION_TIMEOUT, SessionEvent, SessionPacket};
+
+async fn send_frame<T: Encode<VecStream>, W: AsyncWrite + Unpin>(writer: &mut W, crypt: &mut CryptSend, t: T) -> Result<()> {
+	let frame = encode_frame(crypt, protocol_context(), t)?;
+	writer.write_all(&frame).await?;
+	writer.flush().await?;
+	Ok(())
+}
+
+async fn recv_frame<T: Decode<VecStream>, R: AsyncRead + Unpin>(reader: &mut R, crypt: &mut CryptRecv) -> Result<T> {
+	let len = reader.read_u32().await? as usize;
+	crypt.check_len(len)?;
+	let mut body = vec![0u8; len];
+	reader.read_exact(&mut body).await?;
+	decode_frame(crypt, protocol_context(), &body)
+}
+
+async fn timed<T>(timeout: Duration, what: &str, fut: impl Future<Output = Result<T>>) -> Result<T> {
+	tokio::time::timeout(timeout, fut)
+		.await
+		.map_err(|_| anyhow!("Timed out waiting for {what}"))?
+}
+
+struct Shared {
+	writer: tokio::sync::Mutex<(OwnedWriteHalf, CryptSend)>,
+	pending: Mutex<HashMap<u64, oneshot::Sender<Response>>>,
+	subscriptions: Mutex<HashMap<u64, mpsc::UnboundedSender<Notification>>>,
+	next_id: AtomicU64,
+	alive: AtomicBool,
+}
+
+impl Shared {
+	async fn send(&self, packet: SessionPacket) -> Result<()> {
+		let mut writer = self.writer.lock().await;
+		let (client, crypt) = &mut *writer;
+		let result = send_frame(client, crypt, packet).await;
+		if result.is_err() {
+			self.kill();
+		}
+		result
+	}
+
+	fn kill(&self) {
+		self.alive.store(false, Ordering::Release);
+		// Dropping the senders wakes up everyone still waiting
+		self.pending.lock().unwrap().clear();
+		self.subscriptions.lock().unwrap().clear();
+	}
+
+	async fn request(&self, id: u64, cmd: NetCommand) -> Result<Response> {
+		let (send, recv) = oneshot::channel();
+		self.pending.lock().unwrap().insert(id, send);
+
+		if let Err(err) = self.send(SessionPacket::Request(id, cmd)).await {
+			self.pending.lock().unwrap().remove(&id);
+			return Err(err.context("Failed to send request"));
+		}
+
+		// Clean up after ourselves if the caller gives up on us
+		let _guard = PendingGuard(self, id);
+		recv.await.map_err(|_| anyhow!("Connection lost while waiting for response to request {id}"))
+	}
+}
+
+struct PendingGuard<'a>(&'a Shared, u64);
+
+impl Drop for PendingGuard<'_> {
+	fn drop(&mut self) {
+		self.0.pending.lock().unwrap().remove(&self.1);
+	}
+}
+
+enum State {
+	// Handshake and hello done, not logged in yet
+	Connected(TcpStream, Crypt),
+	LoggedIn {
+		shared: Arc<Shared>,
+		tasks: [JoinHandle<()>; 2],
+	},
+	Closed,
+}
+
+/// Tokio counterpart of [`Session`](crate::Session), for callers that can't block.
+/// Unlike `Session` it doesn't reconnect by itself: once [`is_closed`](Self::is_closed)
+/// returns true, make a new one.
+pub struct AsyncClient {
+	state: State,
+	capabilities: Capabilities,
+}
+
+impl AsyncClient {
+	/// Opens the connection, exchanges keys and negotiates the protocol
+	pub async fn connect(addr: impl ToSocketAddrs, pub_key: &[u8], timeout: Duration) -> Result<Self> {
+		timed(timeout, "connection", async {
+			let ctxt = protocol_context();
+			let mut client = TcpStream::connect(addr).await.context("Failed to connect")?;
+
+			let (mut crypt, encrypted) = handshake_key(pub_key, ctxt)?;
+			client.write_all(&encrypted).await?;
+			client.flush().await?;
+
+			let ours = HelloPacket::ours();
+			send_frame(&mut client, crypt.send_half(), ours.clone()).await?;
+			let hello: HelloResponse = recv_frame(&mut client, crypt.recv_half()).await?;
+			let (version, capabilities) = hello.accept(&ours)?;
+			crypt.set_version(version);
+
+			if !capabilities.contains(Capabilities::Session) {
+				bail!("Server doesn't support sessions")
+			}
+
+			Ok(Self {
+				state: State::Connected(client, crypt),
+				capabilities,
+			})
+		}).await
+	}
+
+	pub fn capabilities(&self) -> Capabilities {
+		self.capabilities
+	}
+
+	/// Logs in and switches the connection to session mode, after which requests can be made
+	pub async fn login(&mut self, user: &str, password: [u8; 32], timeout: Duration) -> Result<()> {
+		let State::Connected(mut client, mut crypt) = std::mem::replace(&mut self.state, State::Closed) else {
+			bail!("Already logged in or closed")
+		};
+
+		timed(timeout, "login", async {
+			let nonce = login_nonce()?;
+			let login = LoginPacket {
+				user: user.to_owned(),
+				nonce,
+			};
+			send_frame(&mut client, crypt.send_half(), login).await?;
+			let challenge: LoginChallenge = recv_frame(&mut client, crypt.recv_half()).await?;
+
+			// The KDF is deliberately slow, keep it off the runtime
+			let owned_user = user.to_owned();
+			let proof = tokio::task::spawn_blocking(move || login_proof(&password, &owned_user, &nonce, &challenge))
+				.await
+				.context("KDF task failed")??;
+			send_frame(&mut client, crypt.send_half(), LoginProof { proof }).await?;
+			let response: LoginResponse = recv_frame(&mut client, crypt.recv_half()).await?;
+			match response {
+				LoginResponse::Ok => {}
+				LoginResponse::WrongCredentials => bail!("Wrong credentials"),
+			}
+
+			send_frame(&mut client, crypt.send_half(), NetCommand::Session).await?;
+			let response: Response = recv_frame(&mut client, crypt.recv_half()).await?;
+			match response {
+				Response::Ok => Ok(()),
+				any => bail!("Server refused session mode: {any}"),
+			}
+		}).await?;
+
+		crypt.set_limit(FrameLimit::SESSION);
+		let (mut reader, writer) = client.into_split();
+		let (send, mut recv) = crypt.split();
+
+		let shared = Arc::new(Shared {
+			writer: tokio::sync::Mutex::new((writer, send)),
+			pending: Mutex::new(HashMap::new()),
+			subscriptions: Mutex::new(HashMap::new()),
+			next_id: AtomicU64::new(0),
+			alive: AtomicBool::new(true),
+		});
+
+		// Reader: routes responses back to whoever is waiting for them
+		let reader_shared = shared.clone();
+		let reader_task = tokio::spawn(async move {
+			let result: Result<()> = async {
+				loop {
+					let event: SessionEvent = timed(SESSION_TIMEOUT, "server", recv_frame(&mut reader, &mut recv)).await?;
+					match event {
+						SessionEvent::Response(id, response) => {
+							let waiting = reader_shared.pending.lock().unwrap().remove(&id);
+							if let Some(waiting) = waiting {
+								let _ = waiting.send(response);
+							}
+						}
+						SessionEvent::Pong(_) => {}
+						SessionEvent::Notification(id, notif) => {
+							let sink = reader_shared.subscriptions.lock().unwrap().get(&id).cloned();
+							if let Some(sink) = sink && sink.send(notif).is_err() {
+								// Nobody is listening anymore
+								reader_shared.subscriptions.lock().unwrap().remove(&id);
+								reader_shared.send(SessionPacket::Unsubscribe(id)).await?;
+							}
+						}
+					}
+				}
+			}.await;
+			if reader_shared.alive.load(Ordering::Acquire) && let Err(err) = result {
+				dispatch_debug(err);
+			}
+			reader_shared.kill();
+		});
+
+		// Keepalive: the server drops sessions that stay silent for too long
+		let ping_shared = Arc::downgrade(&shared);
+		let ping_task = tokio::spawn(async move {
+			let mut seq = 0;
+			loop {
+				tokio::time::sleep(KEEPALIVE_INTERVAL).await;
+				let Some(shared) = ping_shared.upgrade() else { break };
+				if !shared.alive.load(Ordering::Acquire) { break }
+				if shared.send(SessionPacket::Ping(seq)).await.is_err() { break }
+				seq += 1;
+			}
+		});
+
+		self.state = State::LoggedIn {
+			shared,
+			tasks: [reader_task, ping_task],
+		};
+		Ok(())
+	}
+
+	pub async fn request(&self, cmd: NetCommand, timeout: Duration) -> Result<Response> {
+		let shared = self.shared()?;
+
+		let missing = cmd.required_capabilities() - self.capabilities;
+		if !missing.is_empty() {
+			bail!("Server doesn't support {missing:?}")
+		}
+
+		let id = shared.next_id.fetch_add(1, Ordering::Relaxed);
+		timed(timeout, "response", shared.request(id, cmd)).await
+	}
+
+	/// Notifications matching `filter` are pushed into the returned channel as they happen,
+	/// until the receiver is dropped or the connection closes
+	pub async fn subscribe(&self, filter: NotificationFilter, timeout: Duration) -> Result<mpsc::UnboundedReceiver<Notification>> {
+		let shared = self.shared()?;
+		if !self.capabilities.contains(Capabilities::Subscribe) {
+			bail!("Server doesn't support subscriptions")
+		}
+		let (send, recv) = mpsc::unbounded_channel();
+
+		// Registered before asking, the first notification may beat the response
+		let id = shared.next_id.fetch_add(1, Ordering::Relaxed);
+		shared.subscriptions.lock().unwrap().insert(id, send);
+
+		match timed(timeout, "response", shared.request(id, NetCommand::Subscribe(filter))).await {
+			Ok(Response::Ok) => Ok(recv),
+			Ok(any) => {
+				shared.subscriptions.lock().unwrap().remove(&id);
+				bail!("Server refused subscription: {any}")
+			}
+			Err(err) => {
+				shared.subscriptions.lock().unwrap().remove(&id);
+				Err(err)
+			}
+		}
+	}
+
+	pub fn is_closed(&self) -> bool {
+		match &self.state {
+			State::Connected(..) => false,
+			State::LoggedIn { shared, .. } => !shared.alive.load(Ordering::Acquire),
+			State::Closed => true,
+		}
+	}
+
+	pub async fn close(&mut self) {
+		if let State::LoggedIn { shared, tasks } = std::mem::replace(&mut self.state, State::Closed) {
+			if shared.alive.load(Ordering::Acquire) {
+				let _ = shared.send(SessionPacket::Close).await;
+			}
+			shared.kill();
+			for task in tasks {
+				task.abort();
+			}
+		}
+	}
+
+	fn shared(&self) -> Result<&Shared> {
+		match &self.state {
+			State::LoggedIn { shared, .. } if shared.alive.load(Ordering::Acquire) => Ok(shared),
+			State::LoggedIn { .. } | State::Closed => bail!("Connection closed"),
+			State::Connected(..) => bail!("Not logged in"),
+		}
+	}
+}
+
+impl Drop for AsyncClient {
+	fn drop(&mut self) {
+		if let State::LoggedIn { shared, tasks } = &self.state {
+			shared.kill();
+			// The socket goes away with the tasks
+			for task in tasks {
+				task.abort();
+			}
+		}
+	}
+}
+
+impl core::fmt::Debug for AsyncClient {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		f.debug_struct("AsyncClient")
+			.field("capabilities", &self.capabilities)
+			.field("closed", &self.is_closed())
+			.finish()
+	}
+}
@@ -0,0 +1,192 @@
+use std::io::{Read, Write};
+use anyhow::{bail, Result};
+use ende::Context;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::{Crypt, LoginChallenge, LoginPacket, LoginProof, LoginResponse, send_packet};
+
+// Login is SCRAM-like: the server only stores H(HMAC(KDF(secret), "Client Key")),
+// which is enough to check a proof but not to produce one.
+// `secret` is what the client holds, the output of `hash_pw`.
+
+pub const KDF_ITERATIONS: u32 = 100_000;
+
+/// What sv_manage keeps for each account
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct StoredCredentials {
+	pub salt: [u8; 16],
+	pub iterations: u32,
+	pub stored_key: [u8; 32],
+}
+
+impl StoredCredentials {
+	/// Derives fresh credentials with a random salt
+	pub fn new(secret: &[u8; 32]) -> Result<Self> {
+		let mut salt = [0u8; 16];
+		openssl::rand::rand_bytes(&mut salt)?;
+
+		let client_key = client_key(secret, &salt, KDF_ITERATIONS)?;
+		Ok(Self {
+			salt,
+			iterations: KDF_ITERATIONS,
+			stored_key: Sha256::digest(client_key).into(),
+		})
+	}
+
+	pub fn verify(&self, user: &str, client_nonce: &[u8; 16], server_nonce: &[u8; 16], proof: &[u8; 32]) -> Result<bool> {
+		let message = auth_message(user, client_nonce, server_nonce, &self.salt);
+		let signature = hmac(&self.stored_key, &message)?;
+
+		// Undo the xor to get the client key back, then check it against what we stored
+		let mut client_key = [0u8; 32];
+		for i in 0..32 {
+			client_key[i] = proof[i] ^ signature[i];
+		}
+		let stored_key: [u8; 32] = Sha256::digest(client_key).into();
+
+		Ok(openssl::memcmp::eq(&stored_key, &self.stored_key))
+	}
+}
+
+fn client_key(secret: &[u8; 32], salt: &[u8; 16], iterations: u32) -> Result<[u8; 32]> {
+	let mut salted = [0u8; 32];
+	openssl::pkcs5::pbkdf2_hmac(secret, salt, iterations as usize, MessageDigest::sha256(), &mut salted)?;
+	hmac(&salted, b"Client Key")
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Result<[u8; 32]> {
+	let key = PKey::hmac(key)?;
+	let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+	signer.update(data)?;
+
+	let mut out = [0u8; 32];
+	signer.sign(&mut out)?;
+	Ok(out)
+}
+
+// Everything both sides agree on, so a proof can't be replayed under different conditions
+fn auth_message(user: &str, client_nonce: &[u8; 16], server_nonce: &[u8; 16], salt: &[u8; 16]) -> Vec<u8> {
+	let mut message = Vec::with_capacity(user.len() + 48);
+	message.extend_from_slice(user.as_bytes());
+	message.extend_from_slice(client_nonce);
+	message.extend_from_slice(server_nonce);
+	message.extend_from_slice(salt);
+	message
+}
+
+/// Answers the server's challenge
+pub fn login_proof(secret: &[u8; 32], user: &str, client_nonce: &[u8; 16], challenge: &LoginChallenge) -> Result<[u8; 32]> {
+	if challenge.iterations > 10 * KDF_ITERATIONS {
+		// Don't let a confused server make us spin forever
+		bail!("Server asked for an unreasonable amount of KDF iterations: {}", challenge.iterations)
+	}
+
+	let client_key = client_key(secret, &challenge.salt, challenge.iterations)?;
+	let stored_key: [u8; 32] = Sha256::digest(client_key).into();
+
+	let message = auth_message(user, client_nonce, &challenge.nonce, &challenge.salt);
+	let signature = hmac(&stored_key, &message)?;
+
+	let mut proof = [0u8; 32];
+	for i in 0..32 {
+		proof[i] = client_key[i] ^ signature[i];
+	}
+	Ok(proof)
+}
+
+/// The salt to hand out for accounts that don't exist. Derived from a server secret,
+/// so asking twice gives the same answer just like for an account that does
+pub fn fake_salt(server_secret: &[u8], user: &str) -> Result<[u8; 16]> {
+	let mut message = b"Fake Salt".to_vec();
+	message.extend_from_slice(user.as_bytes());
+	let mac = hmac(server_secret, &message)?;
+	Ok(mac[..16].try_into().unwrap())
+}
+
+pub fn login_nonce() -> Result<[u8; 16]> {
+	let mut nonce = [0u8; 16];
+	openssl::rand::rand_bytes(&mut nonce)?;
+	Ok(nonce)
+}
+
+pub fn client_login<S: Read + Write>(client: &mut S, crypt: &mut Crypt, ctxt: Context, user: &str, secret: &[u8; 32]) -> Result<()> {
+	let nonce = login_nonce()?;
+	let login = LoginPacket {
+		user: user.to_owned(),
+		nonce,
+	};
+	let challenge = send_packet(client, crypt, ctxt, login)?;
+
+	let proof = login_proof(secret, user, &nonce, &challenge)?;
+	match send_packet(client, crypt, ctxt, LoginProof { proof })? {
+		LoginResponse::Ok => Ok(()),
+		LoginResponse::WrongCredentials => bail!("Wrong credentials"),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::hash_pw;
+
+	fn challenge(credentials: &StoredCredentials, nonce: [u8; 16]) -> LoginChallenge {
+		LoginChallenge {
+			salt: credentials.salt,
+			iterations: credentials.iterations,
+			nonce,
+		}
+	}
+
+	#[test]
+	fn proof_verifies() -> Result<()> {
+		let secret = hash_pw("hunter2");
+		let credentials = StoredCredentials::new(&secret)?;
+		let (client_nonce, server_nonce) = (login_nonce()?, login_nonce()?);
+
+		let proof = login_proof(&secret, "alice", &client_nonce, &challenge(&credentials, server_nonce))?;
+		assert!(credentials.verify("alice", &client_nonce, &server_nonce, &proof)?);
+
+		// Bound to the user and both nonces
+		assert!(!credentials.verify("bob", &client_nonce, &server_nonce, &proof)?);
+		assert!(!credentials.verify("alice", &login_nonce()?, &server_nonce, &proof)?);
+		assert!(!credentials.verify("alice", &client_nonce, &login_nonce()?, &proof)?);
+		Ok(())
+	}
+
+	#[test]
+	fn wrong_password() -> Result<()> {
+		let credentials = StoredCredentials::new(&hash_pw("hunter2"))?;
+		let (client_nonce, server_nonce) = (login_nonce()?, login_nonce()?);
+
+		let proof = login_proof(&hash_pw("hunter3"), "alice", &client_nonce, &challenge(&credentials, server_nonce))?;
+		assert!(!credentials.verify("alice", &client_nonce, &server_nonce, &proof)?);
+		Ok(())
+	}
+
+	#[test]
+	fn salts_differ() -> Result<()> {
+		let secret = hash_pw("hunter2");
+		assert_ne!(StoredCredentials::new(&secret)?.salt, StoredCredentials::new(&secret)?.salt);
+		Ok(())
+	}
+
+	#[test]
+	fn fake_salt_is_stable() -> Result<()> {
+		assert_eq!(fake_salt(b"secret", "nobody")?, fake_salt(b"secret", "nobody")?);
+		assert_ne!(fake_salt(b"secret", "nobody")?, fake_salt(b"secret", "somebody")?);
+		assert_ne!(fake_salt(b"secret", "nobody")?, fake_salt(b"other", "nobody")?);
+		Ok(())
+	}
+
+	#[test]
+	fn absurd_iterations() -> Result<()> {
+		let credentials = StoredCredentials::new(&hash_pw("hunter2"))?;
+		let mut challenge = challenge(&credentials, login_nonce()?);
+		challenge.iterations = u32::MAX;
+		assert!(login_proof(&hash_pw("hunter2"), "alice", &login_nonce()?, &challenge).is_err());
+		Ok(())
+	}
+}
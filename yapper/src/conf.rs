@@ -1,49 +1,250 @@
-use std::fs::{File, OpenOptions};
-use std::io;
-use std::io::{Seek, SeekFrom, Write};
-use std::path::Path;
-use std::sync::{Arc, RwLock};
+use std::ffi::OsString;
+use std::fs;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock, Weak};
+use std::thread;
+use std::time::{Duration, SystemTime};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use toml_edit::{DocumentMut, Item, Table};
 use anyhow::Result;
 
-#[derive(Debug)]
-struct ConfigInner<T: Sync + Send + Default + Serialize + for<'a> Deserialize<'a>> {
+type Callback<T> = Box<dyn Fn(&T) + Send + Sync>;
+
+/// Upgrades the raw JSON of a config by one schema version
+pub type Migration = fn(&mut Value) -> Result<()>;
+
+/// Versioning for persisted configs. The schema version is the number of migrations,
+/// so changing a struct in a way old files can't parse means appending one here
+pub trait Schema {
+	const MIGRATIONS: &'static [Migration] = &[];
+}
+
+const SCHEMA_KEY: &str = "schema";
+
+/// On-disk format of a config, picked from the file extension. Anything unknown is JSON
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Format {
+	Json,
+	Toml,
+	Yaml,
+}
+
+impl Format {
+	pub fn of(path: &Path) -> Self {
+		match path.extension().and_then(|x| x.to_str()) {
+			Some("toml") => Self::Toml,
+			Some("yaml" | "yml") => Self::Yaml,
+			_ => Self::Json,
+		}
+	}
+
+	fn parse(self, text: &str) -> Result<Value> {
+		Ok(match self {
+			Self::Json => serde_json::from_str(text)?,
+			Self::Toml => toml::from_str(text)?,
+			Self::Yaml => serde_yaml::from_str(text)?,
+		})
+	}
+
+	// `old` is the current file, TOML keeps its comments and layout where the keys still exist
+	fn render(self, mut value: Value, old: Option<&str>) -> Result<String> {
+		Ok(match self {
+			Self::Json => serde_json::to_string_pretty(&value)?,
+			Self::Toml => {
+				// TOML has no null, a missing key reads back as None just the same
+				strip_nulls(&mut value);
+				let new: DocumentMut = toml::to_string_pretty(&value)?.parse()?;
+				match old.and_then(|old| old.parse::<DocumentMut>().ok()) {
+					Some(mut old) => {
+						merge_toml(old.as_table_mut(), new.as_table().clone());
+						old.to_string()
+					}
+					None => new.to_string(),
+				}
+			}
+			Self::Yaml => serde_yaml::to_string(&value)?,
+		})
+	}
+}
+
+// Operators can switch formats by renaming `x.json` to `x.toml` or `x.yaml`,
+// whichever exists wins over the name the program asked for
+fn locate(path: &Path) -> PathBuf {
+	if path.is_file() {
+		return path.to_owned();
+	}
+	["json", "toml", "yaml", "yml"]
+		.into_iter()
+		.map(|ext| path.with_extension(ext))
+		.find(|x| x.is_file())
+		.unwrap_or_else(|| path.to_owned())
+}
+
+fn strip_nulls(value: &mut Value) {
+	match value {
+		Value::Object(object) => {
+			object.retain(|_, x| !x.is_null());
+			object.values_mut().for_each(strip_nulls);
+		}
+		Value::Array(array) => array.iter_mut().for_each(strip_nulls),
+		_ => {}
+	}
+}
+
+// Bring `old` in line with `new` while keeping the keys, comments and formatting it already has
+fn merge_toml(old: &mut Table, new: Table) {
+	old.retain(|key, _| new.contains_key(key));
+	for (key, item) in new {
+		match (old.get_mut(&key), item) {
+			(Some(Item::Table(old)), Item::Table(new)) => merge_toml(old, new),
+			(Some(Item::Value(old)), Item::Value(mut new)) => {
+				*new.decor_mut() = old.decor().clone();
+				*old = new;
+			}
+			(Some(old), new) => *old = new,
+			(None, new) => {
+				old.insert(&key, new);
+			}
+		}
+	}
+}
+
+struct ConfigInner<T: Sync + Send + Default + Serialize + for<'a> Deserialize<'a> + Schema> {
 	config: T,
-	file: File,
+	path: PathBuf,
+	// Modification time of the last version we read or wrote ourselves,
+	// anything else showing up on disk is an external edit
+	modified: Option<SystemTime>,
+	callbacks: Vec<Callback<T>>,
+}
+
+#[derive(Clone)]
+pub struct Config<T: Sync + Send + Default + Serialize + for<'a> Deserialize<'a> + Schema>(Arc<RwLock<ConfigInner<T>>>);
+
+impl<T: Sync + Send + Default + Serialize + for<'a> Deserialize<'a> + Schema + core::fmt::Debug> core::fmt::Debug for Config<T> {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		let this = self.0.read().unwrap();
+		f.debug_struct("Config")
+			.field("config", &this.config)
+			.field("path", &this.path)
+			.finish()
+	}
+}
+
+fn sibling(path: &Path, ext: &str) -> PathBuf {
+	let mut name = OsString::from(path.as_os_str());
+	name.push(".");
+	name.push(ext);
+	PathBuf::from(name)
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+	fs::metadata(path).and_then(|x| x.modified()).ok()
+}
+
+// Write to a temp file, fsync it, keep the old file as .bak and rename over it.
+// A crash at any point leaves either the old or the new version in place
+fn write_atomic<T: Serialize + Schema>(path: &Path, config: &T) -> Result<Option<SystemTime>> {
+	use anyhow::Context;
+
+	let mut value = serde_json::to_value(config).context("Failed to serialize")?;
+	if let Some(object) = value.as_object_mut() {
+		object.insert(SCHEMA_KEY.to_owned(), Value::from(T::MIGRATIONS.len()));
+	}
+	let old = fs::read_to_string(path).ok();
+	let text = Format::of(path).render(value, old.as_deref()).context("Failed to serialize")?;
+
+	let tmp = sibling(path, "tmp");
+	let mut file = File::create(&tmp).context("Failed to create the temp file")?;
+	// Keep whatever permissions the old file had, it may hold credentials
+	if let Ok(meta) = fs::metadata(path) {
+		file.set_permissions(meta.permissions()).context("Failed to copy permissions to the temp file")?;
+	}
+	file.write_all(text.as_bytes()).context("Failed to write the temp file")?;
+	file.sync_all().context("Failed to sync the temp file")?;
+	drop(file);
+
+	if path.is_file() {
+		fs::copy(path, sibling(path, "bak")).context("Failed to back up the old file")?;
+	}
+	fs::rename(&tmp, path).context("Failed to replace the old file")?;
+
+	// The rename only sticks once the directory is synced too
+	let dir = match path.parent() {
+		Some(dir) if !dir.as_os_str().is_empty() => dir,
+		_ => Path::new("."),
+	};
+	File::open(dir)
+		.and_then(|dir| dir.sync_all())
+		.context("Failed to sync the config directory")?;
+
+	Ok(modified(path))
 }
 
-#[derive(Debug, Clone)]
-pub struct Config<T: Sync + Send + Default + Serialize + for<'a> Deserialize<'a>>(Arc<RwLock<ConfigInner<T>>>);
+fn read<T: for<'a> Deserialize<'a> + Schema>(path: &Path, backup: bool) -> Result<(T, bool)> {
+	use anyhow::{bail, Context};
+
+	let text = fs::read_to_string(path).context("Failed to open the config file")?;
+	let mut value = Format::of(path).parse(&text)
+		.with_context(|| format!("Failed to parse config file, the previous version may be in {:?}", sibling(path, "bak")))?;
+
+	// Files from before versioning have no schema at all
+	let schema = value.get(SCHEMA_KEY).and_then(Value::as_u64).unwrap_or(0) as usize;
+	let latest = T::MIGRATIONS.len();
+	if schema > latest {
+		bail!("Config file has schema {schema} but this build only knows up to {latest}");
+	}
+
+	let migrated = schema < latest;
+	if migrated && backup {
+		fs::copy(path, sibling(path, &format!("v{schema}.bak")))
+			.context("Failed to back up the config before migrating")?;
+	}
+	for (version, migration) in T::MIGRATIONS.iter().enumerate().skip(schema) {
+		migration(&mut value)
+			.with_context(|| format!("Failed to migrate config from schema {version} to {}", version + 1))?;
+	}
+
+	if let Some(object) = value.as_object_mut() {
+		object.remove(SCHEMA_KEY);
+	}
+	let config = serde_json::from_value(value)
+		.with_context(|| format!("Failed to parse config file, the previous version may be in {:?}", sibling(path, "bak")))?;
+	Ok((config, migrated))
+}
 
-impl<T: Sync + Send + Default + Serialize + for<'a> Deserialize<'a>> Config<T> {
+impl<T: Sync + Send + Default + Serialize + for<'a> Deserialize<'a> + Schema> Config<T> {
 	pub fn init(path: &str) -> Result<Self> {
 		use anyhow::Context;
 
-		if !Path::new(path).is_file() {
-			let mut config_file = File::create(path)?;
+		let path = locate(Path::new(path));
+		let config = if !path.is_file() {
 			let default = T::default();
-			serde_json::to_writer_pretty(&mut config_file, &default)
+			write_atomic(&path, &default)
 				.context("Failed to write the default config")?;
-			Ok(Self(Arc::new(RwLock::new(ConfigInner {
-				config: default,
-				file: config_file,
-			}))))
+			default
 		} else {
-			let mut config_file = OpenOptions::new()
-				.write(true)
-				.read(true)
-				.create(true)
-				.open(path)
-				.context("Failed to open the config file")?;
-
-			let config: T =
-				serde_json::from_reader(&mut config_file).context("Failed to parse config file")?;
-
-			Ok(Self(Arc::new(RwLock::new(ConfigInner {
-				config,
-				file: config_file,
-			}))))
-		}
+			let (config, migrated) = read(&path, true)?;
+			if migrated {
+				write_atomic(&path, &config)
+					.context("Failed to write the migrated config")?;
+			}
+			config
+		};
+
+		Ok(Self(Arc::new(RwLock::new(ConfigInner {
+			config,
+			modified: modified(&path),
+			path,
+			callbacks: Vec::new(),
+		}))))
+	}
+
+	pub fn path(&self) -> PathBuf {
+		self.0.read().unwrap().path.clone()
 	}
 
 	pub fn with_config<F, R>(&self, f: F) -> R
@@ -62,16 +263,51 @@ impl<T: Sync + Send + Default + Serialize + for<'a> Deserialize<'a>> Config<T> {
 
 		let mut this = self.0.write().unwrap();
 		let r = f(&mut this.config);
-		let flush: io::Result<()> = try {
-			this.file.set_len(0)?;
-			this.file.seek(SeekFrom::Start(0))?;
-			let mut vec: Vec<u8> = Vec::new();
-			serde_json::to_writer_pretty(&mut vec, &this.config)?;
-			this.file.write_all(&vec)?;
-			this.file.flush()?;
-		};
-		flush.context("Failed to flush configuration changes")?;
+		this.modified = write_atomic(&this.path, &this.config)
+			.context("Failed to flush configuration changes")?;
 
 		Ok(r)
 	}
-}
\ No newline at end of file
+
+	/// Called with the new config every time it's reloaded after an external edit.
+	/// The config is locked while it runs, so use the argument rather than `with_config`
+	pub fn on_reload(&self, callback: impl Fn(&T) + Send + Sync + 'static) {
+		self.0.write().unwrap().callbacks.push(Box::new(callback));
+	}
+
+	/// Re-read the file if something other than us changed it.
+	/// Returns whether it was reloaded
+	pub fn reload(&self) -> Result<bool> {
+		let mut this = self.0.write().unwrap();
+		let modified = modified(&this.path);
+		if modified.is_none() || modified == this.modified {
+			return Ok(false);
+		}
+
+		// Don't retry a broken edit until the file changes again
+		this.modified = modified;
+		// Hand edits are migrated in memory only, the next write brings the file up to date
+		this.config = read(&this.path, false)?.0;
+		for callback in this.callbacks.iter() {
+			callback(&this.config);
+		}
+		Ok(true)
+	}
+}
+
+impl<T: Sync + Send + Default + Serialize + for<'a> Deserialize<'a> + Schema + 'static> Config<T> {
+	/// Check the file for external edits every `interval` and reload it.
+	/// The thread exits once every handle to the config is dropped
+	pub fn watch(&self, interval: Duration) {
+		let weak: Weak<RwLock<ConfigInner<T>>> = Arc::downgrade(&self.0);
+		thread::spawn(move || {
+			loop {
+				thread::sleep(interval);
+				let Some(inner) = weak.upgrade() else { return };
+				if let Err(err) = Config(inner).reload() {
+					crate::dispatch_debug(err.context("Failed to reload the config, keeping the old one"));
+				}
+			}
+		});
+	}
+}
@@ -0,0 +1,215 @@
+use anyhow::{anyhow, bail, Context, Result};
+use openssl::symm;
+use openssl::symm::Cipher;
+use crate::{FrameLimit, PROTOCOL_VERSION};
+
+pub const TAG_LEN: usize = 16;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Role {
+	Client,
+	Server,
+}
+
+impl Role {
+	pub fn peer(self) -> Self {
+		match self {
+			Role::Client => Role::Server,
+			Role::Server => Role::Client,
+		}
+	}
+
+	// Each direction gets its own nonce space so the two sides
+	// never encrypt under the same (key, nonce) pair
+	fn nonce_prefix(self) -> [u8; 4] {
+		match self {
+			Role::Client => *b"clnt",
+			Role::Server => *b"srvr",
+		}
+	}
+}
+
+fn nonce(role: Role, seq: u64) -> [u8; 12] {
+	let mut nonce = [0u8; 12];
+	nonce[..4].copy_from_slice(&role.nonce_prefix());
+	nonce[4..].copy_from_slice(&seq.to_be_bytes());
+	nonce
+}
+
+/// AES-128-GCM state for one end of a connection.
+/// The nonce is derived from a per-direction sequence number that is never sent,
+/// so any modified, truncated, replayed or reordered frame fails to authenticate.
+pub struct Crypt {
+	send: CryptSend,
+	recv: CryptRecv,
+}
+
+impl Crypt {
+	pub fn new(key: &[u8], role: Role) -> Result<Self> {
+		let key: [u8; 16] = key
+			.try_into()
+			.map_err(|_| anyhow!("Expected a 16 byte key, got {} bytes", key.len()))?;
+
+		Ok(Self {
+			send: CryptSend {
+				key,
+				role,
+				seq: 0,
+				version: PROTOCOL_VERSION,
+			},
+			recv: CryptRecv {
+				key,
+				role: role.peer(),
+				seq: 0,
+				// Nobody has logged in yet, `set_limit` raises it after
+				limit: FrameLimit::PRE_LOGIN,
+				version: PROTOCOL_VERSION,
+			},
+		})
+	}
+
+	pub fn role(&self) -> Role {
+		self.send.role
+	}
+
+	pub fn seal(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+		self.send.seal(data)
+	}
+
+	pub fn open(&mut self, sealed: &[u8]) -> Result<Vec<u8>> {
+		self.recv.open(sealed)
+	}
+
+	pub fn send_half(&mut self) -> &mut CryptSend {
+		&mut self.send
+	}
+
+	pub fn recv_half(&mut self) -> &mut CryptRecv {
+		&mut self.recv
+	}
+
+	pub fn set_limit(&mut self, limit: FrameLimit) {
+		self.recv.set_limit(limit);
+	}
+
+	/// The version the hello settled on, everything after it is encoded for that
+	pub fn set_version(&mut self, version: u16) {
+		self.send.version = version;
+		self.recv.version = version;
+	}
+
+	/// Splits the state so one thread can write while another reads
+	pub fn split(self) -> (CryptSend, CryptRecv) {
+		(self.send, self.recv)
+	}
+}
+
+pub struct CryptSend {
+	key: [u8; 16],
+	role: Role,
+	seq: u64,
+	// Negotiated protocol version, see `wire_version`
+	version: u16,
+}
+
+impl CryptSend {
+	pub fn version(&self) -> u16 {
+		self.version
+	}
+
+	/// Encrypts `data` into a frame body (ciphertext followed by the tag).
+	/// The big-endian length of the body is authenticated as associated data.
+	pub fn seal(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+		if self.seq == u64::MAX {
+			bail!("Send sequence number exhausted")
+		}
+
+		let nonce = nonce(self.role, self.seq);
+		let len = ((data.len() + TAG_LEN) as u32).to_be_bytes();
+
+		let mut tag = [0u8; TAG_LEN];
+		let mut sealed = symm::encrypt_aead(Cipher::aes_128_gcm(), &self.key, Some(&nonce), &len, data, &mut tag)
+			.context("Failed to encrypt packet")?;
+		sealed.extend_from_slice(&tag);
+
+		self.seq += 1;
+		Ok(sealed)
+	}
+}
+
+pub struct CryptRecv {
+	key: [u8; 16],
+	role: Role,
+	seq: u64,
+	// Lives here since it's per connection receive state too
+	limit: FrameLimit,
+	version: u16,
+}
+
+impl CryptRecv {
+	pub fn version(&self) -> u16 {
+		self.version
+	}
+
+	pub fn limit(&self) -> FrameLimit {
+		self.limit
+	}
+
+	pub fn set_limit(&mut self, limit: FrameLimit) {
+		self.limit = limit;
+	}
+
+	/// Call with the length prefix before allocating anything for the frame
+	pub fn check_len(&self, len: usize) -> Result<()> {
+		if len > self.limit.max_frame {
+			bail!("Frame of {len} bytes is over the {} byte limit", self.limit.max_frame)
+		}
+		if len < TAG_LEN {
+			bail!("Frame too short to contain an authentication tag: {len} bytes")
+		}
+		Ok(())
+	}
+
+	/// Decrypts and authenticates a frame body produced by the peer's [`CryptSend::seal`].
+	pub fn open(&mut self, sealed: &[u8]) -> Result<Vec<u8>> {
+		if sealed.len() < TAG_LEN {
+			bail!("Packet too short to contain an authentication tag: {} bytes", sealed.len())
+		}
+		if self.seq == u64::MAX {
+			bail!("Receive sequence number exhausted")
+		}
+
+		let nonce = nonce(self.role, self.seq);
+		let len = (sealed.len() as u32).to_be_bytes();
+		let (data, tag) = sealed.split_at(sealed.len() - TAG_LEN);
+
+		let opened = symm::decrypt_aead(Cipher::aes_128_gcm(), &self.key, Some(&nonce), &len, data, tag)
+			.map_err(|_| anyhow!("Packet failed authentication (tampered, truncated or replayed)"))?;
+
+		self.seq += 1;
+		Ok(opened)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn frame_over_limit() {
+		let key = [7u8; 16];
+		let mut client = Crypt::new(&key, Role::Client).unwrap();
+		let mut server = Crypt::new(&key, Role::Server).unwrap();
+
+		let small = client.seal(b"hello").unwrap();
+		assert!(server.recv_half().check_len(small.len()).is_ok());
+		assert_eq!(server.open(&small).unwrap(), b"hello");
+
+		// Fine once logged in, too big before
+		let big = client.seal(&vec![0u8; FrameLimit::PRE_LOGIN.max_frame]).unwrap();
+		assert!(server.recv_half().check_len(big.len()).is_err());
+		server.set_limit(FrameLimit::SESSION);
+		assert!(server.recv_half().check_len(big.len()).is_ok());
+		assert!(server.open(&big).is_ok());
+	}
+}
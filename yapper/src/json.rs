@@ -0,0 +1,143 @@
+use std::ops::Bound;
+use mvn_version::ComparableVersion;
+use serde_json::{json, Value};
+use crate::{DepState, ModDependency, ModInfo, ModUpdate, Notification, NotificationEntry, Response, ServerStatus, ZipProgress};
+
+// Presentation JSON for tools and gateways. Not the same as the serde impls,
+// which are for storage (ModInfo serializes to an ende blob for example)
+
+pub fn response_json(response: &Response) -> Value {
+	match response {
+		Response::Ok => json!({ "type": "ok" }),
+		Response::Err => json!({ "type": "error", "kind": "Internal", "message": "Unspecified error", "context": [] }),
+		Response::UnknownServer => json!({ "type": "unknown_server" }),
+		Response::InvalidState => json!({ "type": "invalid_state" }),
+		Response::NoBackup => json!({ "type": "no_backup" }),
+		Response::Status(status) => json!({ "type": "status", "server": status_json(status) }),
+		Response::List(list) => json!({ "type": "list", "servers": list.iter().map(status_json).collect::<Vec<_>>() }),
+		Response::CommandOutput(output) => json!({ "type": "command_output", "output": output }),
+		Response::Notifications(notifs) => json!({ "type": "notifications", "notifications": notifs.iter().map(notification_json).collect::<Vec<_>>() }),
+		Response::ModConflict => json!({ "type": "mod_conflict" }),
+		Response::NoSuchMod => json!({ "type": "no_such_mod" }),
+		Response::Mods(mods, more) => json!({ "type": "mods", "mods": mods.iter().map(mod_json).collect::<Vec<_>>(), "more": more }),
+		Response::Mod(info) => json!({ "type": "mod", "mod": mod_json(info) }),
+		Response::DepUnsatisfied(deps) => json!({
+			"type": "dep_unsatisfied",
+			"deps": deps.iter().map(|(mod_id, state)| json!({ "mod_id": mod_id, "state": dep_state_json(state) })).collect::<Vec<_>>(),
+		}),
+		Response::DepSatisfied => json!({ "type": "dep_satisfied" }),
+		Response::Unsupported(missing) => json!({ "type": "unsupported", "missing": format!("{missing:?}") }),
+		Response::Error { kind, message, context } => json!({ "type": "error", "kind": kind.to_string(), "message": message, "context": context }),
+		Response::NotificationLog(entries) => json!({ "type": "notification_log", "entries": entries.iter().map(entry_json).collect::<Vec<_>>() }),
+		Response::Updates(updates) => json!({ "type": "updates", "updates": updates.iter().map(update_json).collect::<Vec<_>>() }),
+		Response::DuplicateMod(filename) => json!({ "type": "duplicate_mod", "filename": filename }),
+		Response::ModsRemoved(mod_ids) => json!({ "type": "mods_removed", "mod_ids": mod_ids }),
+	}
+}
+
+pub fn status_json(status: &ServerStatus) -> Value {
+	json!({
+		"name": status.name,
+		"path": status.path,
+		"status": status.status.to_string(),
+	})
+}
+
+pub fn mod_json(info: &ModInfo) -> Value {
+	json!({
+		"mod_id": info.mod_id,
+		"name": info.name,
+		"filename": info.filename,
+		"version": info.version.to_string(),
+		"description": info.description,
+		"url": info.url,
+		"credits": info.credits,
+		"authors": info.authors,
+		"has_logo": info.logo.is_some(),
+		"dependencies": info.dependencies.iter().map(dependency_json).collect::<Vec<_>>(),
+		"provides": info.provides.iter().map(|x| json!({ "mod_id": x.mod_id, "version": x.version.to_string() })).collect::<Vec<_>>(),
+		"loader": info.loader,
+		"loader_version": info.loader_version.as_ref().map(|x| x.to_string()),
+		"license": info.license,
+		"issue_tracker": info.issue_tracker,
+		"update_json": info.update_json,
+		"display_test": info.display_test.map(|x| format!("{x:?}")),
+		"client_side_only": info.client_side_only,
+		"hashes": info.hashes.as_ref().map(|x| json!({ "sha1": x.sha1, "sha512": x.sha512, "murmur2": x.murmur2 })),
+	})
+}
+
+pub fn dependency_json(dep: &ModDependency) -> Value {
+	json!({
+		"mod_id": dep.mod_id,
+		"kind": format!("{:?}", dep.kind),
+		"mandatory": dep.kind.mandatory(),
+		"range": dep.range.to_string(),
+		"intervals": dep.range.intervals().iter().map(|x| json!({
+			"min_version": bound_json(&x.min),
+			"max_version": bound_json(&x.max),
+		})).collect::<Vec<_>>(),
+		"side": format!("{:?}", dep.side),
+		"ordering": dep.ordering.map(|x| format!("{x:?}")),
+		// Any one of these does just as well as `mod_id`
+		"alternatives": dep.alternatives.iter().map(|(mod_id, range)| json!({ "mod_id": mod_id, "range": range.to_string() })).collect::<Vec<_>>(),
+	})
+}
+
+fn bound_json(bound: &Bound<ComparableVersion>) -> Value {
+	match bound {
+		Bound::Included(version) => json!({ "version": version.to_string(), "inclusive": true }),
+		Bound::Excluded(version) => json!({ "version": version.to_string(), "inclusive": false }),
+		Bound::Unbounded => Value::Null,
+	}
+}
+
+pub fn update_json(update: &ModUpdate) -> Value {
+	json!({
+		"mod_id": update.mod_id,
+		"current": update.current,
+		"latest": update.latest,
+		"recommended": update.recommended,
+		"homepage": update.homepage,
+	})
+}
+
+fn dep_state_json(state: &DepState) -> Value {
+	match state {
+		DepState::VersionMismatch(version) => json!({ "state": "version_mismatch", "got": version.to_string() }),
+		DepState::Incompatible(version) => json!({ "state": "incompatible", "got": version.to_string() }),
+		DepState::Discouraged(version) => json!({ "state": "discouraged", "got": version.to_string() }),
+		DepState::LoaderMismatch(range, version) => json!({ "state": "loader_mismatch", "needs": range.to_string(), "got": version.to_string() }),
+		any => json!({ "state": any.to_string() }),
+	}
+}
+
+pub fn notification_json(notif: &Notification) -> Value {
+	let details = match notif {
+		Notification::BackupFailed(_, reason)
+		| Notification::RestoreFailed(_, reason)
+		| Notification::ZipFailed(_, reason) => json!({ "reason": reason }),
+		Notification::StatusChanged(_, from, to) => json!({ "from": from.to_string(), "to": to.to_string() }),
+		Notification::BackupProgress(_, done, total)
+		| Notification::RestoreProgress(_, done, total) => json!({ "done": done, "total": total }),
+		Notification::ZipProgress(_, ZipProgress::Zipping(done, total)) => json!({ "stage": "zipping", "done": done, "total": total }),
+		Notification::ZipProgress(_, ZipProgress::Uploading(done, total)) => json!({ "stage": "uploading", "done": done, "total": total }),
+		Notification::ZipFile(_, url) => json!({ "url": url }),
+		Notification::ModsOutdated(_, updates) => json!({ "updates": updates.iter().map(update_json).collect::<Vec<_>>() }),
+	};
+
+	json!({
+		"kind": notif.kind().to_string(),
+		"server": notif.server(),
+		"details": details,
+	})
+}
+
+pub fn entry_json(entry: &NotificationEntry) -> Value {
+	json!({
+		"id": entry.id,
+		"time": entry.time,
+		"severity": entry.severity.to_string(),
+		"notification": notification_json(&entry.notif),
+	})
+}
@@ -1,27 +1,43 @@
 #![feature(try_blocks)]
 #![feature(let_chains)]
 
+mod auth;
 pub mod conf;
+mod crypt;
+pub mod json;
 mod mod_parser;
+mod session;
+mod version_range;
+#[cfg(feature = "async")]
+mod async_client;
+#[cfg(feature = "testing")]
+pub mod testing;
 
+pub use auth::*;
+pub use crypt::*;
 pub use mod_parser::*;
+pub use session::*;
+pub use version_range::*;
+#[cfg(feature = "async")]
+pub use async_client::*;
 
+use std::cell::Cell;
 use std::collections::HashMap;
 use std::fmt::Formatter;
 use std::io::{Read, Write};
 use std::mem;
 use std::net::TcpStream;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 use anyhow::{anyhow, bail, Result};
 use base64::Engine;
 use base64::engine::general_purpose;
+use bitflags::bitflags;
 use bytemuck::NoUninit;
-use ende::{Context, Decode, Encode, Encoder};
+use ende::{BinSettings, BitWidth, Context, Decode, Encode, Encoder, NumEncoding, SizeRepr, VariantRepr};
 use ende::io::{SizeLimit, Std, VecStream};
 use mvn_version::ComparableVersion;
-use openssl::rand::rand_bytes;
-use openssl::symm;
-use openssl::symm::Cipher;
+use openssl::rsa::{Padding, Rsa};
 use parse_display::Display;
 use serde::{Deserialize, Serialize};
 use sha2::digest::typenum::private::Trim;
@@ -86,6 +102,7 @@ pub fn dispatch_debug<T: core::fmt::Debug>(t: T) {
 	println!("{t:?}")
 }
 
+// The client side secret. Never stored on the server as is, see `StoredCredentials`
 pub fn hash_pw(pw: &str) -> [u8; 32] {
 	use sha2::Digest;
 	let mut hasher = Sha256::new();
@@ -116,7 +133,14 @@ pub enum Command {
 pub enum NetCommand {
 	ListServers,
 	ServerCommand(String, ServerCommand),
-	Notifications
+	Notifications,
+	// Switches the connection to session mode, see `SessionPacket`
+	Session,
+	// Session mode only, pushes matching notifications as `SessionEvent::Notification`
+	Subscribe(NotificationFilter),
+	// Everything in the persistent log with an id greater than this, oldest first.
+	// Unlike `Notifications` it doesn't consume anything, so any number of clients can catch up
+	NotificationsAfter(u64),
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Encode, Decode)]
@@ -135,12 +159,57 @@ pub enum ServerCommand {
 	UpdateMod(String, String),
 	GenerateModsZip,
 	ResolveDeps(DepResolveMode, Vec<ModInfo>),
+	// Looks up every installed mod's update JSON
+	CheckUpdates,
 }
 
 impl Packet for NetCommand {
 	type Response = Response;
 }
 
+impl NetCommand {
+	// What the peer must have advertised in its `HelloPacket` for this command to be understood
+	pub fn required_capabilities(&self) -> Capabilities {
+		match self {
+			NetCommand::ListServers => Capabilities::empty(),
+			NetCommand::ServerCommand(_, cmd) => cmd.required_capabilities(),
+			NetCommand::Notifications => Capabilities::empty(),
+			NetCommand::Session => Capabilities::Session,
+			NetCommand::Subscribe(_) => Capabilities::Session | Capabilities::Subscribe,
+			NetCommand::NotificationsAfter(_) => Capabilities::NotificationLog,
+		}
+	}
+}
+
+impl ServerCommand {
+	pub fn required_capabilities(&self) -> Capabilities {
+		match self {
+			ServerCommand::CheckUpdates => Capabilities::UpdateCheck,
+			// Everything else predates capabilities
+			_ => Capabilities::empty(),
+		}
+	}
+}
+
+// Client -> server once a connection is in session mode
+#[derive(Debug, Clone, Eq, PartialEq, Encode, Decode)]
+pub enum SessionPacket {
+	Request(u64, NetCommand),
+	Ping(u64),
+	Close,
+	Unsubscribe(u64),
+}
+
+// Server -> client once a connection is in session mode.
+// Responses carry the id of their request and may arrive in any order
+#[derive(Debug, Clone, Eq, PartialEq, Encode, Decode)]
+pub enum SessionEvent {
+	Response(u64, Response),
+	Pong(u64),
+	// Tagged with the id of the `Subscribe` request
+	Notification(u64, Notification),
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Encode, Decode, Serialize, Deserialize)]
 pub enum Notification {
 	BackupFailed(String, String),
@@ -151,6 +220,79 @@ pub enum Notification {
 	ZipProgress(String, ZipProgress),
 	ZipFailed(String, String),
 	ZipFile(String, String),
+	ModsOutdated(String, Vec<ModUpdate>),
+}
+
+// An installed mod whose update JSON has something newer for the server's minecraft version
+#[derive(Debug, Clone, Eq, PartialEq, Encode, Decode, Serialize, Deserialize)]
+pub struct ModUpdate {
+	pub mod_id: String,
+	pub current: String,
+	// The `<mc>-latest` and `<mc>-recommended` promos, only if they're newer than `current`
+	pub latest: Option<String>,
+	pub recommended: Option<String>,
+	pub homepage: Option<String>,
+}
+
+impl ModUpdate {
+	// What to update to, recommended versions first like forge's own checker
+	pub fn target(&self) -> &str {
+		self.recommended.as_deref().or(self.latest.as_deref()).unwrap_or(&self.current)
+	}
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Display, Encode, Decode, Serialize, Deserialize)]
+pub enum Severity {
+	Info,
+	Warning,
+	Error,
+}
+
+// A notification as kept in sv_manage's log
+#[derive(Debug, Clone, Eq, PartialEq, Encode, Decode, Serialize, Deserialize)]
+pub struct NotificationEntry {
+	// Increases with every notification of an account, also across restarts
+	pub id: u64,
+	// Unix seconds, UTC
+	pub time: u64,
+	pub severity: Severity,
+	pub notif: Notification,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Display, Encode, Decode, Serialize, Deserialize)]
+pub enum NotificationKind {
+	BackupFailed,
+	RestoreFailed,
+	StatusChanged,
+	BackupProgress,
+	RestoreProgress,
+	ZipProgress,
+	ZipFailed,
+	ZipFile,
+	ModsOutdated,
+}
+
+// `None` means "everything"
+#[derive(Debug, Clone, Default, Eq, PartialEq, Encode, Decode)]
+pub struct NotificationFilter {
+	pub servers: Option<Vec<String>>,
+	pub kinds: Option<Vec<NotificationKind>>,
+}
+
+impl NotificationFilter {
+	pub fn all() -> Self {
+		Self::default()
+	}
+
+	pub fn matches(&self, notif: &Notification) -> bool {
+		let server_ok = self.servers
+			.as_ref()
+			.is_none_or(|servers| servers.iter().any(|x| x == notif.server()));
+		let kind_ok = self.kinds
+			.as_ref()
+			.is_none_or(|kinds| kinds.contains(&notif.kind()));
+		server_ok && kind_ok
+	}
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Encode, Decode, Serialize, Deserialize)]
@@ -183,6 +325,61 @@ impl core::fmt::Display for ZipProgress {
 }
 
 impl Notification {
+	pub fn kind(&self) -> NotificationKind {
+		match self {
+			Notification::BackupFailed(..) => NotificationKind::BackupFailed,
+			Notification::RestoreFailed(..) => NotificationKind::RestoreFailed,
+			Notification::StatusChanged(..) => NotificationKind::StatusChanged,
+			Notification::BackupProgress(..) => NotificationKind::BackupProgress,
+			Notification::RestoreProgress(..) => NotificationKind::RestoreProgress,
+			Notification::ZipProgress(..) => NotificationKind::ZipProgress,
+			Notification::ZipFailed(..) => NotificationKind::ZipFailed,
+			Notification::ZipFile(..) => NotificationKind::ZipFile,
+			Notification::ModsOutdated(..) => NotificationKind::ModsOutdated,
+		}
+	}
+
+	pub fn server(&self) -> &str {
+		match self {
+			Notification::BackupFailed(server, ..) |
+			Notification::RestoreFailed(server, ..) |
+			Notification::StatusChanged(server, ..) |
+			Notification::BackupProgress(server, ..) |
+			Notification::RestoreProgress(server, ..) |
+			Notification::ZipProgress(server, ..) |
+			Notification::ZipFailed(server, ..) |
+			Notification::ZipFile(server, ..) |
+			Notification::ModsOutdated(server, ..) => server,
+		}
+	}
+
+	pub fn severity(&self) -> Severity {
+		match self {
+			Notification::BackupFailed(..) |
+			Notification::RestoreFailed(..) |
+			Notification::ZipFailed(..) => Severity::Error,
+			Notification::ModsOutdated(..) => Severity::Warning,
+			Notification::StatusChanged(..) |
+			Notification::BackupProgress(..) |
+			Notification::RestoreProgress(..) |
+			Notification::ZipProgress(..) |
+			Notification::ZipFile(..) => Severity::Info,
+		}
+	}
+
+	// Peers without these can't decode it
+	pub fn required_capabilities(&self) -> Capabilities {
+		match self {
+			Notification::ModsOutdated(..) => Capabilities::UpdateCheck,
+			_ => Capabilities::empty(),
+		}
+	}
+
+	// Progress updates replace each other and aren't worth keeping around
+	pub fn is_progress(&self) -> bool {
+		self.is_backup_progress() || self.is_restore_progress() || self.is_package_progress()
+	}
+
 	pub fn is_backup_progress(&self) -> bool {
 		match self {
 			Notification::BackupProgress(..) => true,
@@ -253,6 +450,13 @@ impl core::fmt::Display for Notification {
 				       escape_discord(server)
 				)
 			}
+			Notification::ModsOutdated(server, updates) => {
+				write!(f, "Server `{}` has {} outdated mods:", escape_discord(server), updates.len())?;
+				for update in updates {
+					write!(f, "\n{} {} -> {}", escape_discord(&update.mod_id), escape_discord(&update.current), escape_discord(update.target()))?;
+				}
+				Ok(())
+			}
 		}
 	}
 }
@@ -281,7 +485,110 @@ pub enum Response {
 	#[display("DepUnsatisfied({0:?})")]
 	DepUnsatisfied(Vec<(String, DepState)>),
 	#[display("DepSatisfied")]
-	DepSatisfied
+	DepSatisfied,
+	// The command needs capabilities that weren't negotiated
+	#[display("Unsupported({0:?})")]
+	Unsupported(Capabilities),
+	// What went wrong, from the outermost context down to the root cause
+	#[display("Error({kind}): {message}")]
+	Error {
+		kind: ErrorKind,
+		message: String,
+		context: Vec<String>,
+	},
+	#[display("NotificationLog({0:?})")]
+	NotificationLog(Vec<NotificationEntry>),
+	#[display("Updates({0:?})")]
+	Updates(Vec<ModUpdate>),
+	// Byte for byte the same as this installed jar
+	#[display("DuplicateMod({0:?})")]
+	DuplicateMod(String),
+	// Done, but these other mods were in the same jar and went with it
+	#[display("ModsRemoved({0:?})")]
+	ModsRemoved(Vec<String>),
+}
+
+impl Response {
+	pub fn error(err: &anyhow::Error) -> Self {
+		Self::error_kind(ErrorKind::of(err), err)
+	}
+
+	pub fn error_kind(kind: ErrorKind, err: &anyhow::Error) -> Self {
+		Self::Error {
+			kind,
+			message: err.to_string(),
+			context: err.chain().skip(1).map(|cause| cause.to_string()).collect(),
+		}
+	}
+
+	// Peers without `StructuredErrors` only know the old bare variants,
+	// notifications they can't decode are left out,
+	// and anything newer than the negotiated `version` is mapped to what they know
+	pub fn downgrade(self, version: u16, capabilities: Capabilities) -> Self {
+		match self {
+			Response::Error { kind, .. } if !capabilities.contains(Capabilities::StructuredErrors) => match kind {
+				ErrorKind::InvalidState => Response::InvalidState,
+				_ => Response::Err,
+			},
+			Response::Notifications(mut notifs) => {
+				notifs.retain(|notif| capabilities.contains(notif.required_capabilities()));
+				Response::Notifications(notifs)
+			}
+			Response::NotificationLog(mut entries) => {
+				entries.retain(|entry| capabilities.contains(entry.notif.required_capabilities()));
+				Response::NotificationLog(entries)
+			}
+			Response::Mods(mods, more) => Response::Mods(mods.into_iter().map(|x| x.downgrade(version)).collect(), more),
+			Response::Mod(info) => Response::Mod(info.downgrade(version)),
+			Response::DuplicateMod(_) if version < 6 => Response::ModConflict,
+			Response::ModsRemoved(_) if version < 8 => Response::Ok,
+			Response::DepUnsatisfied(states) => {
+				let states: Vec<_> = states
+					.into_iter()
+					.filter_map(|(mod_id, state)| Some((mod_id, state.downgrade(version)?)))
+					.collect();
+				// Nothing left but warnings they can't be shown
+				if states.is_empty() {
+					Response::DepSatisfied
+				} else {
+					Response::DepUnsatisfied(states)
+				}
+			}
+			any => any,
+		}
+	}
+}
+
+/// Machine readable half of [`Response::Error`]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Display, Encode, Decode)]
+pub enum ErrorKind {
+	Internal,
+	Io,
+	Timeout,
+	NotFound,
+	InvalidState,
+	InvalidInput,
+	Unsupported,
+}
+
+impl ErrorKind {
+	// Best guess from whatever is in the chain
+	pub fn of(err: &anyhow::Error) -> Self {
+		for cause in err.chain() {
+			if let Some(err) = cause.downcast_ref::<std::io::Error>() {
+				return match err.kind() {
+					std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock => ErrorKind::Timeout,
+					std::io::ErrorKind::NotFound => ErrorKind::NotFound,
+					std::io::ErrorKind::InvalidInput | std::io::ErrorKind::InvalidData => ErrorKind::InvalidInput,
+					_ => ErrorKind::Io,
+				};
+			}
+			if cause.is::<std::sync::mpsc::RecvTimeoutError>() {
+				return ErrorKind::Timeout;
+			}
+		}
+		ErrorKind::Internal
+	}
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Display, Encode, Decode)]
@@ -301,6 +608,38 @@ pub enum DepState {
 	),
 	AlreadyInstalled,
 	InvalidInput,
+	#[display("Incompatible: got {0}")]
+	Incompatible(
+		#[ende(into: WrappedComparableVersion)]
+		ComparableVersion
+	),
+	// Only a warning, nothing stops you from installing it anyway
+	#[display("Discouraged: got {0}")]
+	Discouraged(
+		#[ende(into: WrappedComparableVersion)]
+		ComparableVersion
+	),
+	// The mod itself needs another forge, keyed by the mod's id
+	#[display("LoaderMismatch: needs {0}, got {1}")]
+	LoaderMismatch(
+		VersionRange,
+		#[ende(into: WrappedComparableVersion)]
+		ComparableVersion
+	),
+}
+
+impl DepState {
+	// `None` if it's only a warning the peer has no way to show
+	pub fn downgrade(self, version: u16) -> Option<Self> {
+		match self {
+			// Installed in a range it must not be, as close to a conflict as they get
+			DepState::Incompatible(got) if version < 3 => Some(DepState::VersionMismatch(got)),
+			DepState::Discouraged(_) if version < 3 => None,
+			// Close enough, they'll see it needs a different version of the loader
+			DepState::LoaderMismatch(_, got) if version < 5 => Some(DepState::VersionMismatch(got)),
+			any => Some(any),
+		}
+	}
 }
 
 #[repr(transparent)]
@@ -383,13 +722,165 @@ impl core::fmt::Display for ServerStatus {
 	}
 }
 
+// Bumped whenever the wire format changes in a way capabilities can't express.
+// Older peers get the layout of their version, see `wire_version` and `Response::downgrade`
+// 3 added `provides` to ModInfo, incompatible and discouraged dependencies
+// 4 made dependency ranges unions of intervals, older peers get the interval covering all of them
+// 5 added the rest of mods.toml to ModInfo, load ordering and loader mismatches
+// 6 added hashes to ModInfo and duplicate mods
+// 7 added alternatives to dependencies
+// 8 added telling which other mods went along with an updated or removed one
+// 9 added recommended dependencies
+pub const PROTOCOL_VERSION: u16 = 9;
+// Oldest version we can still talk to
+// 1 sent the password hash itself on login, that's not accepted anymore
+pub const MIN_PROTOCOL_VERSION: u16 = 2;
+
+thread_local! {
+	static WIRE_VERSION: Cell<u16> = const { Cell::new(PROTOCOL_VERSION) };
+}
+
+/// The protocol version whatever is being encoded or decoded right now is for.
+/// Only frames set it, anything stored locally always has the newest layout
+pub fn wire_version() -> u16 {
+	WIRE_VERSION.get()
+}
+
+fn with_wire_version<T>(version: u16, f: impl FnOnce() -> T) -> T {
+	let prev = WIRE_VERSION.replace(version);
+	let result = f();
+	WIRE_VERSION.set(prev);
+	result
+}
+
+bitflags! {
+	#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+	pub struct Capabilities: u64 {
+		const Session = 0b00000001;
+		const Subscribe = 0b00000010;
+		const StructuredErrors = 0b00000100;
+		const NotificationLog = 0b00001000;
+		const UpdateCheck = 0b00010000;
+	}
+}
+
+impl<W: ende::io::Write> Encode<W> for Capabilities {
+	fn encode(&self, encoder: &mut Encoder<W>) -> ende::EncodingResult<()> {
+		self.bits().encode(encoder)
+	}
+}
+
+impl<R: ende::io::Read> Decode<R> for Capabilities {
+	fn decode(decoder: &mut Encoder<R>) -> ende::EncodingResult<Self> {
+		// Unknown bits from a newer peer are kept, they fall off when intersecting
+		Ok(Self::from_bits_retain(u64::decode(decoder)?))
+	}
+}
+
+// First packet after the key exchange, before logging in.
+// Its layout must never change
+#[derive(Debug, Clone, Eq, PartialEq, Encode, Decode)]
+pub struct HelloPacket {
+	pub version: u16,
+	pub min_version: u16,
+	pub capabilities: Capabilities,
+}
+
+impl HelloPacket {
+	pub fn ours() -> Self {
+		Self {
+			version: PROTOCOL_VERSION,
+			min_version: MIN_PROTOCOL_VERSION,
+			capabilities: Capabilities::all(),
+		}
+	}
+
+	// Picks the highest version both sides speak and the capabilities both sides have
+	pub fn negotiate(&self, other: &HelloPacket) -> HelloResponse {
+		let version = self.version.min(other.version);
+		if version < self.min_version.max(other.min_version) {
+			return HelloResponse::Incompatible {
+				version: self.version,
+				min_version: self.min_version,
+			};
+		}
+
+		HelloResponse::Ok {
+			version,
+			capabilities: self.capabilities & other.capabilities,
+		}
+	}
+}
+
+impl Packet for HelloPacket {
+	type Response = HelloResponse;
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Encode, Decode)]
+pub enum HelloResponse {
+	Ok {
+		version: u16,
+		capabilities: Capabilities,
+	},
+	// Carries the server's own range so the client can say what's wrong
+	Incompatible {
+		version: u16,
+		min_version: u16,
+	},
+}
+
+impl PacketResponse for HelloResponse {}
+
+impl HelloResponse {
+	// Turns the server's answer to `ours` into the negotiated version and capabilities
+	pub fn accept(self, ours: &HelloPacket) -> Result<(u16, Capabilities)> {
+		match self {
+			HelloResponse::Ok { version, capabilities } => {
+				// Don't take the server's word for it
+				Ok((version, capabilities & ours.capabilities))
+			}
+			HelloResponse::Incompatible { version, min_version } => bail!(
+				"Incompatible protocol: we speak {}..={}, server speaks {min_version}..={version}",
+				ours.min_version,
+				ours.version,
+			),
+		}
+	}
+}
+
+pub fn client_hello<S: Read + Write>(client: &mut S, crypt: &mut Crypt, ctxt: Context) -> Result<(u16, Capabilities)> {
+	let ours = HelloPacket::ours();
+	let (version, capabilities) = send_packet(client, crypt, ctxt, ours.clone())?.accept(&ours)?;
+	crypt.set_version(version);
+	Ok((version, capabilities))
+}
+
+// See auth.rs for how these fit together
 #[derive(Debug, Clone, Eq, PartialEq, Encode, Decode)]
 pub struct LoginPacket {
 	pub user: String,
-	pub password: [u8; 32],
+	pub nonce: [u8; 16],
 }
 
 impl Packet for LoginPacket {
+	type Response = LoginChallenge;
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Encode, Decode)]
+pub struct LoginChallenge {
+	pub salt: [u8; 16],
+	pub iterations: u32,
+	pub nonce: [u8; 16],
+}
+
+impl PacketResponse for LoginChallenge {}
+
+#[derive(Debug, Clone, Eq, PartialEq, Encode, Decode)]
+pub struct LoginProof {
+	pub proof: [u8; 32],
+}
+
+impl Packet for LoginProof {
 	type Response = LoginResponse;
 }
 
@@ -401,86 +892,170 @@ pub enum LoginResponse {
 
 impl PacketResponse for LoginResponse {}
 
-pub fn send_packet<T>(client: &mut TcpStream, key: &[u8], ctxt: Context, packet: T)
+/// How much a peer is allowed to make us read
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct FrameLimit {
+	// Length of a sealed frame body, checked before anything is allocated
+	pub max_frame: usize,
+	// Longest string, vec or map a decoded packet may declare
+	pub max_size: usize,
+}
+
+impl FrameLimit {
+	// Anyone can connect, so keep this tight: only the hello and login fit
+	pub const PRE_LOGIN: Self = Self {
+		max_frame: 4 * 1024,
+		max_size: 1024,
+	};
+
+	// Mod lists carry logos, which can get big
+	pub const SESSION: Self = Self {
+		max_frame: 64 * 1024 * 1024,
+		max_size: 16 * 1024 * 1024,
+	};
+}
+
+pub const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+pub const SESSION_TIMEOUT: Duration = Duration::from_secs(45);
+
+pub fn protocol_context() -> Context {
+	Context::new()
+		.settings(BinSettings::new()
+			.variant_repr(VariantRepr::new()
+				.bit_width(BitWidth::Bit8))
+			.size_repr(SizeRepr::new()
+				.num_encoding(NumEncoding::Leb128)))
+}
+
+pub fn client_handshake(mut client: TcpStream, pub_key: &[u8], ctxt: Context) -> Result<(TcpStream, Crypt)> {
+	let (crypt, encrypted) = handshake_key(pub_key, ctxt)?;
+
+	client.write_all(&encrypted)?;
+	client.flush()?;
+
+	Ok((client, crypt))
+}
+
+// Generates an aes key and returns it encrypted with the server's public key, encoded and ready to send
+pub fn handshake_key(pub_key: &[u8], ctxt: Context) -> Result<(Crypt, Vec<u8>)> {
+	// Generate aes key
+	let mut aes = [0u8; 16];
+	openssl::rand::rand_bytes(&mut aes)?;
+
+	// Encrypt it
+	let key = Rsa::public_key_from_pem(pub_key)?;
+	let mut encrypted = [0u8; 256];
+	key.public_encrypt(&aes, &mut encrypted, Padding::PKCS1)?;
+
+	let mut encoder = Encoder::new(VecStream::new(Vec::new(), 0), ctxt);
+	encoder.encode_value(encrypted)?;
+	let encoded = encoder.finish().0.into_inner();
+
+	Ok((Crypt::new(&aes, Role::Client)?, encoded))
+}
+
+pub fn server_handshake(client: TcpStream, private_key: &[u8], ctxt: Context) -> Result<(TcpStream, Crypt)> {
+	let mut encoder = Encoder::new(Std::new(client), ctxt);
+
+	// Expect an aes key encrypted with our public key
+	let buffer: [u8; 256] = encoder.decode_value()?;
+	let key = Rsa::private_key_from_pem(private_key)?;
+
+	let mut decrypted = [0u8; 256];
+	key.private_decrypt(&buffer, &mut decrypted, Padding::PKCS1)?;
+
+	// We got the key
+	let aes = &decrypted[..16];
+
+	let client = encoder.finish().0.into_inner();
+	Ok((client, Crypt::new(aes, Role::Server)?))
+}
+
+pub fn send_packet<T, S>(client: &mut S, crypt: &mut Crypt, ctxt: Context, packet: T)
 	-> Result<T::Response>
 where T: Packet,
+      S: Read + Write,
 {
-	send_thing(client, key, ctxt, packet)?;
-	recv_thing(client, key, ctxt)
+	send_thing(client, crypt.send_half(), ctxt, packet)?;
+	recv_thing(client, crypt.recv_half(), ctxt)
 }
 
-fn send_thing<T: Encode<VecStream>>(client: &mut TcpStream, key: &[u8], ctxt: Context, t: T) -> Result<()> {
-	// Encode to binary
-	let vec = Vec::new();
-	let mut encoder = Encoder::new(VecStream::new(vec, 0), ctxt);
-	t.encode(&mut encoder)?;
-	let mut vec = encoder.finish().0.into_inner();
-
-	// Pad output
-	let padding = 16 - (vec.len() % 16);
-	for _ in 0..padding {
-		vec.push(0);
-	}
-	// println!("SEND [PRE]: {vec:?}");
-	
-	// Gen IV
-	let mut iv = [0u8; 16];
-	rand_bytes(&mut iv)?;
-	
-	// Encrypt
-	let output_crypt = symm::encrypt(Cipher::aes_128_cbc(), key, Some(&iv), &vec)?;
+pub fn send_thing<T: Encode<VecStream>, S: Write>(client: &mut S, crypt: &mut CryptSend, ctxt: Context, t: T) -> Result<()> {
+	// Write length and contents in one go, so frames from different threads never interleave
+	let frame = encode_frame(crypt, ctxt, t)?;
+	client.write_all(&frame)?;
+	client.flush()?;
 
-	// println!("SEND [POST]: {output_crypt:?}");
-	
-	// Write length to a buffer
-	let len = (output_crypt.len() as u32).to_be_bytes();
-	
-	client.write_all(&iv)?;
-	client.write_all(&len)?;
-	client.write_all(&output_crypt)?;
-	
 	Ok(())
 }
 
-fn recv_thing<T: Decode<VecStream>>(client: &mut TcpStream, key: &[u8], ctxt: Context) -> Result<T> {
-	let mut iv = [0u8; 16];
-	client.read_exact(&mut iv)?;
-
+pub fn recv_thing<T: Decode<VecStream>, S: Read>(client: &mut S, crypt: &mut CryptRecv, ctxt: Context) -> Result<T> {
 	let mut len = [0u8; 4];
 	client.read_exact(&mut len)?;
 	let len = u32::from_be_bytes(len) as usize;
+	crypt.check_len(len)?;
 
-	// Decrypt the contents
+	// Read the sealed contents
 	let mut vec = vec![0u8; len];
 	client.read_exact(&mut vec)?;
 
-	// println!("RECV [PRE]: {vec:?}");
-	
-	let decrypted_vec = symm::decrypt(Cipher::aes_128_cbc(), key, Some(&iv), &vec)?;
+	decode_frame(crypt, ctxt, &vec)
+}
+
+// Encodes, seals and prefixes with the length, ready to be written out
+pub fn encode_frame<T: Encode<VecStream>>(crypt: &mut CryptSend, ctxt: Context, t: T) -> Result<Vec<u8>> {
+	// Encode to binary
+	let vec = Vec::new();
+	let mut encoder = Encoder::new(VecStream::new(vec, 0), ctxt);
+	with_wire_version(crypt.version(), || t.encode(&mut encoder))?;
+	let vec = encoder.finish().0.into_inner();
+
+	// println!("SEND [PRE]: {vec:?}");
+
+	// Encrypt and authenticate
+	let output_crypt = crypt.seal(&vec)?;
+
+	// println!("SEND [POST]: {output_crypt:?}");
+
+	let mut frame = Vec::with_capacity(4 + output_crypt.len());
+	frame.extend_from_slice(&(output_crypt.len() as u32).to_be_bytes());
+	frame.extend_from_slice(&output_crypt);
+	Ok(frame)
+}
+
+// Opens and decodes a frame body (without the length prefix)
+pub fn decode_frame<T: Decode<VecStream>>(crypt: &mut CryptRecv, ctxt: Context, body: &[u8]) -> Result<T> {
+	// println!("RECV [PRE]: {body:?}");
+
+	// Anything tampered with or replayed is rejected here, before decoding
+	let decrypted_vec = crypt.open(body)?;
 
 	// println!("RECV [POST]: {decrypted_vec:?}");
-	
-	// Decode
+
+	// Decode, not trusting any length in there to be sane either
+	let mut ctxt = ctxt;
+	ctxt.settings.size_repr.max_size = crypt.limit().max_size;
 	let mut decoder = Encoder::new(VecStream::new(decrypted_vec, 0), ctxt);
-	let decoded = T::decode(&mut decoder)?;
+	let decoded = with_wire_version(crypt.version(), || T::decode(&mut decoder))?;
 
 	Ok(decoded)
 }
 
-pub fn recv_packet<T, F, Err: Into<anyhow::Error>>(client: &mut TcpStream, key: &[u8], ctxt: Context, f: F)
-                                               -> Result<()>
+pub fn recv_packet<T, S, F, Err: Into<anyhow::Error>>(client: &mut S, crypt: &mut Crypt, ctxt: Context, f: F)
+                                                  -> Result<()>
 where T: Packet,
+      S: Read + Write,
       F: FnOnce(T) -> core::result::Result<T::Response, (Err, T::Response)>,
 {
-	let msg = recv_thing(client, key, ctxt)?;
+	let msg = recv_thing(client, crypt.recv_half(), ctxt)?;
 	match f(msg) {
 		Ok(resp) => {
-			send_thing(client, key, ctxt, resp)?;
+			send_thing(client, crypt.send_half(), ctxt, resp)?;
 			Ok(())
 		}
 		Err((err, resp)) => {
-			send_thing(client, key, ctxt, resp)?;
+			send_thing(client, crypt.send_half(), ctxt, resp)?;
 			Err(err.into())
 		}
 	}
-}
\ No newline at end of file
+}
@@ -1,6 +1,5 @@
 use std::collections::HashMap;
-use std::io::Read;
-use std::ops::Bound;
+use std::io::{Read, Seek};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::LazyLock;
@@ -12,11 +11,19 @@ use mvn_version::ComparableVersion;
 use regex::Regex;
 use semver::{Version, VersionReq};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use crate::{base64_decode, base64_encode};
+use zip::ZipArchive;
+use crate::{base64_decode, base64_encode, Interval, PROTOCOL_VERSION, VersionRange, wire_version};
+pub use hashes::Hashes;
+
+mod fabric;
+mod hashes;
+mod jarjar;
+mod mcmod;
+mod quilt;
 
 pub fn reserved_mod_id(string: impl AsRef<str>) -> bool {
 	let string = string.as_ref();
-	string == "minecraft" || string == "forge"
+	matches!(string, "minecraft" | "forge" | "neoforge" | "fabricloader" | "quilt_loader")
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd)]
@@ -48,7 +55,7 @@ impl Into<ComparableVersion> for WrappedComparableVersion {
 	}
 }
 
-#[derive(Clone, Eq, PartialEq, Encode, Decode)]
+#[derive(Clone, Eq, PartialEq)]
 #[derive_ex(Debug)]
 pub struct ModInfo {
 	pub filename: String,
@@ -56,7 +63,6 @@ pub struct ModInfo {
 	pub mod_id: String,
 	pub name: Option<String>,
 	pub description: Option<String>,
-	#[ende(into: WrappedComparableVersion)]
 	pub version: ComparableVersion,
 	#[debug(ignore)]
 	pub logo: Option<Vec<u8>>,
@@ -64,12 +70,168 @@ pub struct ModInfo {
 	pub credits: Option<String>,
 	pub authors: Option<Vec<String>>,
 	pub dependencies: Vec<ModDependency>,
+	/// Bundled inside the jar (Jar-in-Jar), these count as installed along with it
+	pub provides: Vec<ProvidedMod>,
+	/// The language provider from mods.toml, like javafml
+	pub loader: Option<String>,
+	pub loader_version: Option<VersionRange>,
+	pub license: Option<String>,
+	pub issue_tracker: Option<String>,
+	/// Where forge's update checker looks for newer versions
+	pub update_json: Option<String>,
+	pub display_test: Option<DisplayTest>,
+	/// Only meant for clients, servers shouldn't load it at all
+	pub client_side_only: bool,
+	/// Of the whole jar, shared by every mod in it. `None` for minecraft, which has no jar of its own
+	pub hashes: Option<Hashes>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Encode, Decode)]
+pub struct ProvidedMod {
+	pub mod_id: String,
+	#[ende(into: WrappedComparableVersion)]
+	pub version: ComparableVersion,
 }
 
 impl ModInfo {
 	pub fn name(&self) -> &str {
 		self.name.as_ref().unwrap_or(&self.mod_id)
 	}
+
+	/// Only what a peer speaking `version` knows about
+	pub fn downgrade(mut self, version: u16) -> Self {
+		self.dependencies = self.dependencies_for(version);
+		if version < 3 {
+			self.provides.clear();
+		}
+		if version < 5 {
+			self.loader = None;
+			self.loader_version = None;
+			self.license = None;
+			self.issue_tracker = None;
+			self.update_json = None;
+			self.display_test = None;
+			self.client_side_only = false;
+		}
+		if version < 6 {
+			self.hashes = None;
+		}
+		self
+	}
+
+	// Dependencies the way a peer speaking `version` can understand them
+	fn dependencies_for(&self, version: u16) -> Vec<ModDependency> {
+		let mut dependencies = Vec::new();
+		for dep in &self.dependencies {
+			// Older peers would read a conflict as a requirement, they're better off not knowing
+			if !dep.kind.known_to(version) { continue }
+
+			let mut dep = dep.clone();
+			dep.kind = dep.kind_for(version);
+			if version < 7 {
+				dep.alternatives.clear();
+			}
+			if version < 5 {
+				dep.ordering = None;
+			}
+			if version < 4 {
+				if matches!(dep.kind, DepKind::Required | DepKind::Optional | DepKind::Recommended) {
+					let hull = dep.range.hull();
+					dep.range = VersionRange::between(hull.min, hull.max);
+				} else {
+					// The hull would conflict with versions in the gaps, so one conflict per interval instead
+					for interval in dep.range.intervals() {
+						dependencies.push(ModDependency {
+							range: VersionRange::between(interval.min.clone(), interval.max.clone()),
+							..dep.clone()
+						});
+					}
+					continue;
+				}
+			}
+			dependencies.push(dep);
+		}
+		dependencies
+	}
+}
+
+// Fields only go over the wire if the peer's protocol version has them, see `wire_version`
+impl<W: ende::io::Write> Encode<W> for ModInfo {
+	fn encode(&self, encoder: &mut Encoder<W>) -> EncodingResult<()> {
+		let version = wire_version();
+		self.filename.encode(encoder)?;
+		self.path.encode(encoder)?;
+		self.mod_id.encode(encoder)?;
+		self.name.encode(encoder)?;
+		self.description.encode(encoder)?;
+		WrappedComparableVersion::from(self.version.clone()).encode(encoder)?;
+		self.logo.encode(encoder)?;
+		self.url.encode(encoder)?;
+		self.credits.encode(encoder)?;
+		self.authors.encode(encoder)?;
+		if version < PROTOCOL_VERSION {
+			self.dependencies_for(version).encode(encoder)?;
+		} else {
+			self.dependencies.encode(encoder)?;
+		}
+		if version >= 3 {
+			self.provides.encode(encoder)?;
+		}
+		if version >= 5 {
+			self.loader.encode(encoder)?;
+			self.loader_version.encode(encoder)?;
+			self.license.encode(encoder)?;
+			self.issue_tracker.encode(encoder)?;
+			self.update_json.encode(encoder)?;
+			self.display_test.encode(encoder)?;
+			self.client_side_only.encode(encoder)?;
+		}
+		if version >= 6 {
+			self.hashes.encode(encoder)?;
+		}
+		Ok(())
+	}
+}
+
+impl<R: ende::io::Read> Decode<R> for ModInfo {
+	fn decode(decoder: &mut Encoder<R>) -> EncodingResult<Self> {
+		let version = wire_version();
+		let mut info = Self {
+			filename: Decode::decode(decoder)?,
+			path: Decode::decode(decoder)?,
+			mod_id: Decode::decode(decoder)?,
+			name: Decode::decode(decoder)?,
+			description: Decode::decode(decoder)?,
+			version: WrappedComparableVersion::decode(decoder)?.into(),
+			logo: Decode::decode(decoder)?,
+			url: Decode::decode(decoder)?,
+			credits: Decode::decode(decoder)?,
+			authors: Decode::decode(decoder)?,
+			dependencies: Decode::decode(decoder)?,
+			provides: if version >= 3 { Decode::decode(decoder)? } else { Vec::new() },
+			loader: None,
+			loader_version: None,
+			license: None,
+			issue_tracker: None,
+			update_json: None,
+			display_test: None,
+			client_side_only: false,
+			hashes: None,
+		};
+		if version >= 5 {
+			info.loader = Decode::decode(decoder)?;
+			info.loader_version = Decode::decode(decoder)?;
+			info.license = Decode::decode(decoder)?;
+			info.issue_tracker = Decode::decode(decoder)?;
+			info.update_json = Decode::decode(decoder)?;
+			info.display_test = Decode::decode(decoder)?;
+			info.client_side_only = Decode::decode(decoder)?;
+		}
+		if version >= 6 {
+			info.hashes = Decode::decode(decoder)?;
+		}
+		Ok(info)
+	}
 }
 
 impl Serialize for ModInfo {
@@ -90,63 +252,123 @@ impl<'de> Deserialize<'de> for ModInfo {
 	{
 		let base64 = String::deserialize(deserializer)?;
 		let encoded = base64_decode(&base64);
-		Ok(ende::decode_bytes(&encoded).unwrap())
+		ende::decode_bytes(&encoded).map_err(serde::de::Error::custom)
+	}
+}
+
+/// How a mod relates to one of its dependencies
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DepKind {
+	/// Checked against the range only if it's installed
+	Optional,
+	Required,
+	/// Must not be installed in the given range
+	Incompatible,
+	/// Works, but shouldn't be installed in the given range
+	Discouraged,
+	/// Should be installed in the given range, but only warned about otherwise
+	Recommended,
+}
+
+impl DepKind {
+	pub fn mandatory(self) -> bool {
+		self == DepKind::Required
+	}
+
+	// Peers before protocol 3 only know optional and required, and can't be told about conflicts
+	pub fn known_to(self, version: u16) -> bool {
+		version >= 3 || !matches!(self, DepKind::Incompatible | DepKind::Discouraged)
 	}
 }
 
+/// Whether a mod loads before or after one of its dependencies
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Encode, Decode)]
+pub enum LoadOrder {
+	Before,
+	After,
+}
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct ModDependency {
 	pub mod_id: String,
-	pub mandatory: bool,
-	pub min_version: Bound<ComparableVersion>,
-	pub max_version: Bound<ComparableVersion>,
-	pub side: Side
+	pub kind: DepKind,
+	pub range: VersionRange,
+	pub side: Side,
+	pub ordering: Option<LoadOrder>,
+	/// Other mods that do just as well, with their own ranges. Quilt lets a mod depend on any one of a list
+	pub alternatives: Vec<(String, VersionRange)>,
+}
+
+impl ModDependency {
+	// Before protocol 7 there were no alternatives, requiring only the first one would be wrong.
+	// Recommendations came with 9, optional is the closest
+	fn kind_for(&self, version: u16) -> DepKind {
+		match self.kind {
+			DepKind::Required if version < 7 && !self.alternatives.is_empty() => DepKind::Optional,
+			DepKind::Recommended if version < 9 => DepKind::Optional,
+			kind => kind,
+		}
+	}
 }
 
 impl<W: ende::io::Write> Encode<W> for ModDependency {
 	fn encode(&self, encoder: &mut Encoder<W>) -> EncodingResult<()> {
+		let version = wire_version();
 		self.mod_id.encode(encoder)?;
-		self.mandatory.encode(encoder)?;
-		let min_version: Bound<WrappedComparableVersion> = match &self.min_version {
-			Bound::Included(x) => Bound::Included(x.clone().into()),
-			Bound::Excluded(x) => Bound::Excluded(x.clone().into()),
-			Bound::Unbounded => Bound::Unbounded,
-		};
-		let max_version: Bound<WrappedComparableVersion> = match &self.max_version {
-			Bound::Included(x) => Bound::Included(x.clone().into()),
-			Bound::Excluded(x) => Bound::Excluded(x.clone().into()),
-			Bound::Unbounded => Bound::Unbounded,
-		};
-		min_version.encode(encoder)?;
-		max_version.encode(encoder)?;
+		// Used to be `mandatory: bool`, 0 and 1 still mean the same thing
+		(self.kind_for(version) as u8).encode(encoder)?;
+		if version >= 4 {
+			self.range.encode(encoder)?;
+		} else {
+			self.range.hull().encode(encoder)?;
+		}
 		self.side.encode(encoder)?;
+		if version >= 5 {
+			self.ordering.encode(encoder)?;
+		}
+		if version >= 7 {
+			self.alternatives.encode(encoder)?;
+		}
 		Ok(())
 	}
 }
 
 impl<R: ende::io::Read> Decode<R> for ModDependency {
 	fn decode(decoder: &mut Encoder<R>) -> EncodingResult<Self> {
+		let version = wire_version();
 		let mod_id = String::decode(decoder)?;
-		let mandatory = bool::decode(decoder)?;
-		let min_version = Bound::<WrappedComparableVersion>::decode(decoder)?;
-		let max_version = Bound::<WrappedComparableVersion>::decode(decoder)?;
-		let min_version: Bound<ComparableVersion> = match min_version {
-			Bound::Included(x) => Bound::Included(x.into()),
-			Bound::Excluded(x) => Bound::Excluded(x.into()),
-			Bound::Unbounded => Bound::Unbounded,
+		let kind = match u8::decode(decoder)? {
+			0 => DepKind::Optional,
+			1 => DepKind::Required,
+			2 => DepKind::Incompatible,
+			3 => DepKind::Discouraged,
+			4 => DepKind::Recommended,
+			other => return Err(val_error!("Unknown dependency kind {other}")),
 		};
-		let max_version: Bound<ComparableVersion> = match max_version {
-			Bound::Included(x) => Bound::Included(x.into()),
-			Bound::Excluded(x) => Bound::Excluded(x.into()),
-			Bound::Unbounded => Bound::Unbounded,
+		let range = if version >= 4 {
+			VersionRange::decode(decoder)?
+		} else {
+			let hull = Interval::decode(decoder)?;
+			VersionRange::between(hull.min, hull.max)
 		};
 		let side = Side::decode(decoder)?;
+		let ordering = if version >= 5 {
+			Option::<LoadOrder>::decode(decoder)?
+		} else {
+			None
+		};
+		let alternatives = if version >= 7 {
+			Decode::decode(decoder)?
+		} else {
+			Vec::new()
+		};
 		Ok(Self {
 			mod_id,
-			mandatory,
-			min_version,
-			max_version,
+			kind,
+			range,
 			side,
+			ordering,
+			alternatives,
 		})
 	}
 }
@@ -155,13 +377,65 @@ pub fn parse_mod(path: &Path) -> anyhow::Result<ModInfo> {
 	parse_mod_ext(path, None)
 }
 
+/// The first mod declared in the jar, which is the jar's own in library bundles
 pub fn parse_mod_ext(path: &Path, forge_ver: Option<String>) -> anyhow::Result<ModInfo> {
+	// Never empty
+	Ok(parse_mods_ext(path, forge_ver)?.remove(0))
+}
+
+pub fn parse_mods(path: &Path) -> anyhow::Result<Vec<ModInfo>> {
+	parse_mods_ext(path, None)
+}
+
+/// Every mod declared in the jar, sharing its `path` and `filename`
+pub fn parse_mods_ext(path: &Path, forge_ver: Option<String>) -> anyhow::Result<Vec<ModInfo>> {
+	use anyhow::Context;
+	let data = std::fs::read(path).context("Failed to reopen the newly downloaded mod")?;
+	let hashes = Hashes::of(&data);
+	let mut archive = zip::ZipArchive::new(std::io::Cursor::new(data)).context("Failed to parse zip file")?;
+
+	let mut mods = parse_archive(path, &mut archive, forge_ver)?;
+	// Bundled mods go on the jar's own. They're a bonus, a broken bundle doesn't make the mod unusable
+	mods[0].provides = match jarjar::provided(path, &mut archive, 0) {
+		Ok(provides) => provides,
+		Err(err) => {
+			crate::dispatch_debug(err.context(format!("Couldn't read the mods bundled in {}", path.display())));
+			Vec::new()
+		}
+	};
+	for info in mods.iter_mut() {
+		info.hashes = Some(hashes.clone());
+	}
+	Ok(mods)
+}
+
+// `path` is only used for `ModInfo::path` and `filename`, nested jars get their outer jar's
+fn parse_archive<R: Read + Seek>(path: &Path, archive: &mut ZipArchive<R>, forge_ver: Option<String>) -> anyhow::Result<Vec<ModInfo>> {
 	use anyhow::Context;
-	let file = std::fs::File::open(path).context("Failed to reopen the newly downloaded mod")?;
-	let mut archive = zip::ZipArchive::new(file).context("Failed to parse zip file")?;
+
+	// NeoForge renamed the file in 1.20.5
+	let metadata = ["META-INF/neoforge.mods.toml", "META-INF/mods.toml"]
+		.into_iter()
+		.find(|x| archive.index_for_name(x).is_some());
+
+	// Some jars carry metadata for several loaders. (Neo)Forge's wins, then quilt's,
+	// which is more specific than the fabric.mod.json quilt would also accept
+	let Some(metadata) = metadata else {
+		if archive.index_for_name("quilt.mod.json").is_some() {
+			return Ok(vec![quilt::parse(path, archive)?]);
+		}
+		if archive.index_for_name("fabric.mod.json").is_some() {
+			return Ok(vec![fabric::parse(path, archive)?]);
+		}
+		// Before 1.13
+		if archive.index_for_name("mcmod.info").is_some() {
+			return mcmod::parse(path, archive);
+		}
+		bail!("Couldn't find mod metadata")
+	};
 
 	let mut mod_info = archive
-		.by_name("META-INF/mods.toml")
+		.by_name(metadata)
 		.context("Couldn't find mod metadata")?;
 	let mut mods_toml = String::new();
 	mod_info
@@ -185,6 +459,8 @@ pub fn parse_mod_ext(path: &Path, forge_ver: Option<String>) -> anyhow::Result<M
 					display_url,
 					credits,
 					authors,
+					update_json_url,
+					display_test,
 				}) = x else { unreachable!() };
 
 				let credits = if let Some(credits) = credits {
@@ -210,252 +486,258 @@ pub fn parse_mod_ext(path: &Path, forge_ver: Option<String>) -> anyhow::Result<M
 					display_url,
 					credits,
 					authors,
+					update_json_url,
+					display_test,
 				}))
 			}
-			ModsToml { logo_file: data.logo_file, mods: convert, dependencies: data.dependencies }
+			ModsToml {
+				mod_loader: data.mod_loader,
+				loader_version: data.loader_version,
+				license: data.license,
+				issue_tracker_url: data.issue_tracker_url,
+				client_side_only: data.client_side_only,
+				logo_file: data.logo_file,
+				mods: convert,
+				dependencies: data.dependencies,
+			}
 		}
 	};
 
-	if data.mods.len() != 1 {
-		bail!(
-            "Expected to find metadata for 1 mod but found metadata for {}",
-            data.mods.len()
-        );
+	if data.mods.is_empty() {
+		bail!("Found no mods in {metadata}");
 	}
 
-	let mods = data.mods.remove(0);
+	// Shared by every mod in the file
+	let loader_version: Option<VersionRange> = match &data.loader_version {
+		Some(range) => Some(range.parse().context("Unparseable loaderVersion")?),
+		None => None,
+	};
+
+	// Every mod has its own dependency table. Jars with one mod sometimes
+	// get the key wrong, so that mod gets all of them
+	let single = data.mods.len() == 1;
+	let mut infos = Vec::new();
+	for mods in data.mods {
+		let deps: Vec<Dependencies> = if single {
+			data.dependencies.drain().flat_map(|(_, x)| x).collect()
+		} else {
+			data.dependencies.remove(&mods.mod_id()).unwrap_or_default()
+		};
 
-	let logo_file = mods.logo_file().or(data.logo_file);
-	let logo_data = if let Some(logo) = logo_file {
-		let x: Result<Vec<u8>> = try {
-			let mut logo_file = archive.by_name(&logo);
-			if logo_file.is_err() {
-				drop(logo_file);
+		let logo_file = mods.logo_file().or(data.logo_file.clone());
+		let logo_data = if let Some(logo) = logo_file {
+			let x: Result<Vec<u8>> = try {
+				let mut logo_file = archive.by_name(&logo);
+				if logo_file.is_err() {
+					drop(logo_file);
 
-				logo_file = archive.by_name(&format!("META-INF/{logo}"))
-			}
-			let mut logo_file = logo_file?;
+					logo_file = archive.by_name(&format!("META-INF/{logo}"))
+				}
+				let mut logo_file = logo_file?;
 
-			let mut logo_data = Vec::new();
+				let mut logo_data = Vec::new();
 
-			// 10 MB limit
-			// let mut logo_file = Std::new(SizeLimit::new(Std::new(logo_file), 0, 1024 * 1024 * 10));
+				// 10 MB limit
+				// let mut logo_file = Std::new(SizeLimit::new(Std::new(logo_file), 0, 1024 * 1024 * 10));
 
-			logo_file
-				.read_to_end(&mut logo_data)
-				.context(format!("Failed to read logo data: {logo}"))?;
-			logo_data
-		};
-		x.ok()
-	} else { None };
-
-	// let img = image::io::Reader::new(Cursor::new(&logo_data)).decode().context("Failed to load logo image")?;
-	// let mut png = Vec::new();
-	// img.write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png).context("Failed to convert image to png")?;
-
-	let version = {
-		let x: Result<String> = try {
-			let mut the_version = mods.version().to_owned();
-			if the_version.trim() == "${file.jarVersion}" {
-				let mut manifest = archive
-					.by_name("META-INF/MANIFEST.MF")
-					.context("Couldn't open jar manifest")?;
-				let mut manifest_data = String::new();
-				manifest
-					.read_to_string(&mut manifest_data)
-					.context("Failed to read manifest data")?;
-				for line in manifest_data.split("\n") {
-					if line.starts_with("Implementation-Version: ") {
-						if let Some(version) = line.trim().split(" ").nth(1) {
-							the_version = version.to_owned();
-							break;
+				logo_file
+					.read_to_end(&mut logo_data)
+					.context(format!("Failed to read logo data: {logo}"))?;
+				logo_data
+			};
+			x.ok()
+		} else { None };
+
+		// let img = image::io::Reader::new(Cursor::new(&logo_data)).decode().context("Failed to load logo image")?;
+		// let mut png = Vec::new();
+		// img.write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png).context("Failed to convert image to png")?;
+
+		let version = {
+			let x: Result<String> = try {
+				let mut the_version = mods.version().to_owned();
+				if the_version.trim() == "${file.jarVersion}" {
+					let mut manifest = archive
+						.by_name("META-INF/MANIFEST.MF")
+						.context("Couldn't open jar manifest")?;
+					let mut manifest_data = String::new();
+					manifest
+						.read_to_string(&mut manifest_data)
+						.context("Failed to read manifest data")?;
+					for line in manifest_data.split("\n") {
+						if line.starts_with("Implementation-Version: ") {
+							if let Some(version) = line.trim().split(" ").nth(1) {
+								the_version = version.to_owned();
+								break;
+							}
 						}
 					}
+				} else if matches!(the_version.trim(), "${global.forgeVersion}" | "${global.neoForgeVersion}") {
+					the_version = forge_ver.clone().ok_or(anyhow!("Forge version needed but not provided"))?;
 				}
-			} else if the_version.trim() == "${global.forgeVersion}" {
-				the_version = forge_ver.ok_or(anyhow!("Forge version needed but not provided"))?;
-			}
-			the_version
+				the_version
+			};
+			x.context("Failed to get version")?
+		};
+		let version = {
+			ComparableVersion::new(version.trim())
+			// // If the version contains a dash, then it's probably formatted as MC_VERSION-ACTUAL_VERSION
+			// let slice = if let Some((_, second)) = version.split_once("-") {
+			// 	second
+			// } else {
+			// 	&version
+			// };
+			//
+			// fn is_number(ch: char) -> bool {
+			// 	match ch {
+			// 		'0' | '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9' => true,
+			// 		_ => false,
+			// 	}
+			// }
+			//
+			// let pieces: Vec<&str> = slice.split(".").collect();
+			// let mut major = None;
+			// let mut minor = None;
+			// let mut patch = None;
+			// let mut build = None;
+			// // let mut pre_release = None;
+			// // let mut build_meta = None;
+			// for i in 0..pieces.len() {
+			// 	let validate_piece = |piece: &str| -> Result<()> {
+			// 		if !piece.chars().all(is_number) {
+			// 			bail!("Version piece contained something that wasn't a number: (piece){piece}")
+			// 		}
+			// 		Ok(())
+			// 	};
+			//
+			// 	if i == pieces.len() - 1 /* is_last */ {
+			// 		let last_piece = pieces[i];
+			//
+			// 		// May contain additional stuff like symbols
+			//
+			// 		// Special case letters (1.2.c => 1.2.3)
+			// 		if let mut chars = last_piece.chars() &&
+			// 			let Some(ch) = chars.next() &&
+			// 			let None = chars.next() &&
+			// 			ch >= 'a' && ch <= 'z' {
+			// 			let piece = (ch as u8 - 'a' as u8).to_string();
+			// 			let piece = String::leak(piece) as &str;
+			//
+			// 			match i {
+			// 				0 => { major = Some(piece); }
+			// 				1 => { minor = Some(piece); }
+			// 				2 => { patch = Some(piece); }
+			// 				3 => { build = Some(piece); }
+			// 				_ => bail!("Malformed version string (too long!)")
+			// 			}
+			// 		}
+			//
+			// 	} else {
+			// 		let piece = pieces[i];
+			// 		validate_piece(piece)?;
+			//
+			// 		match i {
+			// 			0 => { major = Some(piece); }
+			// 			1 => { minor = Some(piece); }
+			// 			2 => { patch = Some(piece); }
+			// 			_ => bail!("Malformed version string (too long!)")
+			// 		}
+			// 	}
+			// }
+			//
+			// Version {
+			// 	major: major.and_then(|x| FromStr::from_str(x).ok()).unwrap_or_default(),
+			// 	minor: minor.and_then(|x| FromStr::from_str(x).ok()).unwrap_or_default(),
+			// 	patch: patch.and_then(|x| FromStr::from_str(x).ok()).unwrap_or_default(),
+			// 	pre: Default::default(),
+			// 	build: Default::default(),
+			// }
 		};
-		x.context("Failed to get version")?
-	};
-	let version = {
-		ComparableVersion::new(version.trim())
-		// // If the version contains a dash, then it's probably formatted as MC_VERSION-ACTUAL_VERSION
-		// let slice = if let Some((_, second)) = version.split_once("-") {
-		// 	second
-		// } else {
-		// 	&version
-		// };
-		//
-		// fn is_number(ch: char) -> bool {
-		// 	match ch {
-		// 		'0' | '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' | '9' => true,
-		// 		_ => false,
-		// 	}
-		// }
-		//
-		// let pieces: Vec<&str> = slice.split(".").collect();
-		// let mut major = None;
-		// let mut minor = None;
-		// let mut patch = None;
-		// let mut build = None;
-		// // let mut pre_release = None;
-		// // let mut build_meta = None;
-		// for i in 0..pieces.len() {
-		// 	let validate_piece = |piece: &str| -> Result<()> {
-		// 		if !piece.chars().all(is_number) {
-		// 			bail!("Version piece contained something that wasn't a number: (piece){piece}")
-		// 		}
-		// 		Ok(())
-		// 	};
-		//
-		// 	if i == pieces.len() - 1 /* is_last */ {
-		// 		let last_piece = pieces[i];
-		//
-		// 		// May contain additional stuff like symbols
-		//
-		// 		// Special case letters (1.2.c => 1.2.3)
-		// 		if let mut chars = last_piece.chars() &&
-		// 			let Some(ch) = chars.next() &&
-		// 			let None = chars.next() &&
-		// 			ch >= 'a' && ch <= 'z' {
-		// 			let piece = (ch as u8 - 'a' as u8).to_string();
-		// 			let piece = String::leak(piece) as &str;
-		//
-		// 			match i {
-		// 				0 => { major = Some(piece); }
-		// 				1 => { minor = Some(piece); }
-		// 				2 => { patch = Some(piece); }
-		// 				3 => { build = Some(piece); }
-		// 				_ => bail!("Malformed version string (too long!)")
-		// 			}
-		// 		}
-		//
-		// 	} else {
-		// 		let piece = pieces[i];
-		// 		validate_piece(piece)?;
-		//
-		// 		match i {
-		// 			0 => { major = Some(piece); }
-		// 			1 => { minor = Some(piece); }
-		// 			2 => { patch = Some(piece); }
-		// 			_ => bail!("Malformed version string (too long!)")
-		// 		}
-		// 	}
-		// }
-		//
-		// Version {
-		// 	major: major.and_then(|x| FromStr::from_str(x).ok()).unwrap_or_default(),
-		// 	minor: minor.and_then(|x| FromStr::from_str(x).ok()).unwrap_or_default(),
-		// 	patch: patch.and_then(|x| FromStr::from_str(x).ok()).unwrap_or_default(),
-		// 	pre: Default::default(),
-		// 	build: Default::default(),
-		// }
-	};
 
-	let filename: Result<String> = try {
-		path
-			.file_name()
-			.ok_or(anyhow!("Couldn't get file name"))?
-			.to_str()
-			.ok_or(anyhow!("Couldn't convert to string"))?
-			.to_string()
-	};
-	let filename = filename.unwrap_or(format!("{}.jar", mods.mod_id()));
+		let filename = jar_filename(path, &mods.mod_id());
 
-	let authors = mods.authors().map(|authors| {
-		authors
-			.split(",")
-			.map(|string| string.trim())
-			.map(|string| string.to_owned())
-			.collect()
-	});
+		let authors = mods.authors().map(|authors| {
+			authors
+				.split(",")
+				.map(|string| string.trim())
+				.map(|string| string.to_owned())
+				.collect()
+		});
 
-	let mut dependencies: Vec<ModDependency> = Vec::new();
+		let mut dependencies: Vec<ModDependency> = Vec::new();
 
-	for (_, dep) in data.dependencies {
-		for dep in dep {
+		for dep in deps {
 			if dep.mod_id == mods.mod_id() { continue }
 			if dependencies.iter().any(|x| x.mod_id == dep.mod_id) {
 				bail!("Dependency declared twice!")
 			}
 
-			// Parse version requirement
-			let (lo_bound, hi_bound) = if let Some(range) = &dep.version_range && !range.is_empty() {
-				if let Some((lo, hi)) = range.split_once(",") {
-					let lo = if lo.starts_with("[") {
-						if lo == "[" {
-							Bound::Unbounded
-						} else {
-							Bound::Included(ComparableVersion::new(&lo[1..]))
-						}
-					} else if lo.starts_with("(") {
-						if lo == "[" {
-							Bound::Unbounded
-						} else {
-							Bound::Excluded(ComparableVersion::new(&lo[1..]))
-						}
-					} else {
-						bail!("Unparseable version range")
-					};
-
-					let hi = if hi.ends_with("]") {
-						if hi == "]" {
-							Bound::Unbounded
-						} else {
-							Bound::Included(ComparableVersion::new(&hi[..hi.len() - 1]))
-						}
-					} else if hi.ends_with(")") {
-						if hi == ")" {
-							Bound::Unbounded
-						} else {
-							Bound::Excluded(ComparableVersion::new(&hi[..hi.len() - 1]))
-						}
-					} else {
-						bail!("Unparseable version range")
-					};
-
-					(lo, hi)
-				} else {
-					if range.starts_with("[") && range.ends_with("]") {
-						let ver = ComparableVersion::new(&range[1..range.len() - 1]);
-						(Bound::Included(ver.clone()), Bound::Included(ver))
-					} else {
-						bail!("Unparseable version range")
-					}
-				}
-			} else {
-				(Bound::Unbounded, Bound::Unbounded)
+			let range = match &dep.version_range {
+				Some(range) => range.parse().with_context(|| format!("Unparseable version range for {}", dep.mod_id))?,
+				None => VersionRange::any(),
 			};
 
 			dependencies.push(ModDependency {
 				mod_id: dep.mod_id.clone(),
-				mandatory: dep.mandatory,
-				min_version: lo_bound,
-				max_version: hi_bound,
+				kind: dep.kind()?,
+				range,
 				side: dep.side,
+				ordering: dep.ordering()?,
+				alternatives: Vec::new(),
 			});
 		}
+
+		infos.push(ModInfo {
+			filename,
+			path: path.canonicalize().context("Couldn't canonicalize path")?,
+			mod_id: mods.mod_id(),
+			name: mods.display_name(),
+			description: mods.description(),
+			version,
+			logo: logo_data,
+			url: mods.display_url(),
+			credits: mods.credits(),
+			authors,
+			dependencies,
+			provides: Vec::new(),
+			loader: data.mod_loader.clone(),
+			loader_version: loader_version.clone(),
+			license: data.license.clone(),
+			issue_tracker: data.issue_tracker_url.clone(),
+			update_json: mods.update_json_url(),
+			display_test: mods.display_test(),
+			client_side_only: data.client_side_only,
+			hashes: None,
+		});
 	}
 
-	Ok(ModInfo {
-		filename,
-		path: path.canonicalize().context("Couldn't canonicalize path")?,
-		mod_id: mods.mod_id(),
-		name: mods.display_name(),
-		description: mods.description(),
-		version,
-		logo: logo_data,
-		url: mods.display_url(),
-		credits: mods.credits(),
-		authors,
-		dependencies
-	})
+	Ok(infos)
+}
+
+fn jar_filename(path: &Path, mod_id: &str) -> String {
+	let filename: Result<String> = try {
+		path
+			.file_name()
+			.ok_or(anyhow!("Couldn't get file name"))?
+			.to_str()
+			.ok_or(anyhow!("Couldn't convert to string"))?
+			.to_string()
+	};
+	filename.unwrap_or(format!("{mod_id}.jar"))
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct ModsToml<const VARIANT: usize> {
+	#[serde(rename = "modLoader")]
+	mod_loader: Option<String>,
+	#[serde(rename = "loaderVersion")]
+	loader_version: Option<String>,
+	#[serde(rename = "license")]
+	license: Option<String>,
+	#[serde(rename = "issueTrackerURL")]
+	issue_tracker_url: Option<String>,
+	#[serde(rename = "clientSideOnly")]
+	#[serde(default)]
+	client_side_only: bool,
 	#[serde(rename = "logoFile")]
 	logo_file: Option<String>,
 	mods: Vec<Mods<VARIANT>>,
@@ -538,6 +820,20 @@ impl<const VARIANT: usize> Mods<VARIANT> {
 			Mods::WithCreditList(x) => x.authors.as_ref().map(|x| x as _),
 		}
 	}
+
+	pub fn update_json_url(&self) -> Option<String> {
+		match self {
+			Mods::Default(x) => x.update_json_url.clone(),
+			Mods::WithCreditList(x) => x.update_json_url.clone(),
+		}
+	}
+
+	pub fn display_test(&self) -> Option<DisplayTest> {
+		match self {
+			Mods::Default(x) => x.display_test,
+			Mods::WithCreditList(x) => x.display_test,
+		}
+	}
 }
 
 impl<const VARIANT: usize> Serialize for Mods<VARIANT> {
@@ -582,7 +878,11 @@ struct ModsDefault {
 	#[serde(rename = "credits")]
 	credits: Option<String>,
 	#[serde(rename = "authors")]
-	authors: Option<String>
+	authors: Option<String>,
+	#[serde(rename = "updateJSONURL")]
+	update_json_url: Option<String>,
+	#[serde(rename = "displayTest")]
+	display_test: Option<DisplayTest>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Encode, Decode)]
@@ -602,7 +902,11 @@ struct ModsWithCreditsList {
 	#[serde(rename = "credits")]
 	credits: Option<Vec<String>>,
 	#[serde(rename = "authors")]
-	authors: Option<String>
+	authors: Option<String>,
+	#[serde(rename = "updateJSONURL")]
+	update_json_url: Option<String>,
+	#[serde(rename = "displayTest")]
+	display_test: Option<DisplayTest>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Copy, Encode, Decode)]
@@ -615,6 +919,23 @@ pub enum Side {
 	Both
 }
 
+/// How the mod shows up in the server list on clients
+#[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq, Copy, Encode, Decode)]
+pub enum DisplayTest {
+	/// Red X unless the client has the same version
+	#[serde(rename = "MATCH_VERSION")]
+	MatchVersion,
+	/// Fine if the server doesn't have it
+	#[serde(rename = "IGNORE_SERVER_VERSION")]
+	IgnoreServerVersion,
+	/// Fine whatever the server has
+	#[serde(rename = "IGNORE_ALL_VERSION")]
+	IgnoreAllVersion,
+	/// The mod sets it up in code
+	#[serde(rename = "NONE")]
+	Custom,
+}
+
 fn side_both() -> Side {
 	Side::Both
 }
@@ -623,11 +944,43 @@ fn side_both() -> Side {
 struct Dependencies {
 	#[serde(rename = "modId")]
 	mod_id: String,
+	// Forge
 	#[serde(rename = "mandatory")]
-	mandatory: bool,
+	mandatory: Option<bool>,
+	// NeoForge
+	#[serde(rename = "type")]
+	kind: Option<String>,
 	#[serde(rename = "versionRange")]
 	version_range: Option<String>,
 	#[serde(rename = "side")]
 	#[serde(default = "side_both")]
-	side: Side
-}
\ No newline at end of file
+	side: Side,
+	#[serde(rename = "ordering")]
+	ordering: Option<String>,
+}
+
+impl Dependencies {
+	fn kind(&self) -> Result<DepKind> {
+		if let Some(kind) = &self.kind {
+			return Ok(match kind.to_ascii_lowercase().as_str() {
+				"required" => DepKind::Required,
+				"optional" => DepKind::Optional,
+				"incompatible" => DepKind::Incompatible,
+				"discouraged" => DepKind::Discouraged,
+				_ => bail!("Unknown dependency type {kind:?} for {}", self.mod_id),
+			});
+		}
+		// NeoForge defaults to required, forge always says
+		Ok(if self.mandatory.unwrap_or(true) { DepKind::Required } else { DepKind::Optional })
+	}
+
+	fn ordering(&self) -> Result<Option<LoadOrder>> {
+		let Some(ordering) = &self.ordering else { return Ok(None) };
+		Ok(match ordering.to_ascii_uppercase().as_str() {
+			"NONE" => None,
+			"BEFORE" => Some(LoadOrder::Before),
+			"AFTER" => Some(LoadOrder::After),
+			_ => bail!("Unknown dependency ordering {ordering:?} for {}", self.mod_id),
+		})
+	}
+}
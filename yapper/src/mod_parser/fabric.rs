@@ -0,0 +1,259 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Seek};
+use std::ops::Bound;
+use std::path::Path;
+use anyhow::{anyhow, bail, Context, Result};
+use mvn_version::ComparableVersion;
+use serde::Deserialize;
+use zip::ZipArchive;
+use crate::VersionRange;
+use super::{jar_filename, DepKind, ModDependency, ModInfo, Side};
+
+// https://wiki.fabricmc.net/documentation:fabric_mod_json
+
+#[derive(Debug, Deserialize)]
+struct FabricMod {
+	id: String,
+	version: String,
+	name: Option<String>,
+	description: Option<String>,
+	icon: Option<Icon>,
+	license: Option<License>,
+	#[serde(default)]
+	authors: Vec<Person>,
+	#[serde(default)]
+	contributors: Vec<Person>,
+	#[serde(default)]
+	contact: HashMap<String, String>,
+	#[serde(default)]
+	environment: Environment,
+	#[serde(default)]
+	depends: BTreeMap<String, Predicates>,
+	#[serde(default)]
+	recommends: BTreeMap<String, Predicates>,
+	#[serde(default)]
+	breaks: BTreeMap<String, Predicates>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub(super) enum Icon {
+	Path(String),
+	// Width in pixels to path
+	Sizes(HashMap<String, String>),
+}
+
+impl Icon {
+	pub(super) fn largest(self) -> Option<String> {
+		match self {
+			Icon::Path(path) => Some(path),
+			Icon::Sizes(sizes) => sizes
+				.into_iter()
+				.max_by_key(|(size, _)| size.parse::<u32>().unwrap_or(0))
+				.map(|(_, path)| path),
+		}
+	}
+}
+
+// SPDX ids, quilt also allows objects
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub(super) enum License {
+	Id(String),
+	Object { name: String },
+	Many(Vec<License>),
+}
+
+impl License {
+	pub(super) fn joined(self) -> Option<String> {
+		let names = match self {
+			License::Id(name) | License::Object { name } => vec![name],
+			License::Many(list) => list.into_iter().filter_map(License::joined).collect(),
+		};
+		(!names.is_empty()).then(|| names.join(", "))
+	}
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Person {
+	Name(String),
+	Object { name: String },
+}
+
+impl Person {
+	fn name(self) -> String {
+		match self {
+			Person::Name(name) | Person::Object { name } => name,
+		}
+	}
+}
+
+#[derive(Debug, Default, Deserialize)]
+enum Environment {
+	#[default]
+	#[serde(rename = "*")]
+	Any,
+	#[serde(rename = "client")]
+	Client,
+	#[serde(rename = "server")]
+	Server,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Predicates {
+	One(String),
+	// Any of them
+	Any(Vec<String>),
+}
+
+impl Predicates {
+	fn range(&self) -> Result<VersionRange> {
+		match self {
+			Predicates::One(x) => all_of(x),
+			Predicates::Any(list) => any_of(list.iter().map(|x| all_of(x))),
+		}
+	}
+}
+
+// Alternatives, no alternatives at all is taken as anything
+pub(super) fn any_of(mut ranges: impl Iterator<Item = Result<VersionRange>>) -> Result<VersionRange> {
+	let Some(first) = ranges.next() else { return Ok(VersionRange::any()) };
+	ranges.try_fold(first?, |range, x| Ok(range.union(x?)))
+}
+
+// Space separated predicates that must all hold, like ">=1.2 <2"
+pub(super) fn all_of(string: &str) -> Result<VersionRange> {
+	string.split_whitespace().try_fold(VersionRange::any(), |range, x| Ok(range.intersect(&predicate(x)?)))
+}
+
+fn predicate(string: &str) -> Result<VersionRange> {
+	if string == "*" {
+		return Ok(VersionRange::any());
+	}
+
+	let (op, version) = [">=", "<=", ">", "<", "=", "~", "^"]
+		.into_iter()
+		.find_map(|op| string.strip_prefix(op).map(|rest| (op, rest)))
+		.unwrap_or(("", string));
+	if version.is_empty() {
+		bail!("Missing version in {string:?}");
+	}
+
+	// 1.20.x is everything starting with 1.20
+	let parts: Vec<&str> = version.split('.').collect();
+	if let Some(wildcard) = parts.iter().position(|x| matches!(*x, "x" | "X" | "*")) {
+		if !matches!(op, "" | "=") {
+			bail!("Can't combine {op} with a wildcard in {string:?}");
+		}
+		if wildcard == 0 {
+			return Ok(VersionRange::any());
+		}
+		let prefix = parts[..wildcard].join(".");
+		return Ok(VersionRange::between(Bound::Included(ComparableVersion::new(&prefix)), Bound::Excluded(bump(&prefix, wildcard)?)));
+	}
+
+	let parsed = ComparableVersion::new(version);
+	Ok(match op {
+		">=" => VersionRange::at_least(parsed),
+		">" => VersionRange::between(Bound::Excluded(parsed), Bound::Unbounded),
+		"<=" => VersionRange::between(Bound::Unbounded, Bound::Included(parsed)),
+		"<" => VersionRange::between(Bound::Unbounded, Bound::Excluded(parsed)),
+		// Same major and minor
+		"~" => VersionRange::between(Bound::Included(parsed), Bound::Excluded(bump(version, 2)?)),
+		// Same major
+		"^" => VersionRange::between(Bound::Included(parsed), Bound::Excluded(bump(version, 1)?)),
+		_ => VersionRange::exactly(parsed),
+	})
+}
+
+// The first version past everything that shares the first `len` numbers with `version`,
+// so bump("1.2.3", 2) is 1.3
+fn bump(version: &str, len: usize) -> Result<ComparableVersion> {
+	let release = version.split(['-', '+']).next().unwrap_or(version);
+	let mut numbers: Vec<u64> = release
+		.split('.')
+		.map_while(|x| x.parse().ok())
+		.take(len)
+		.collect();
+	let last = numbers.last_mut().ok_or(anyhow!("{version:?} doesn't start with a number"))?;
+	*last += 1;
+	let bumped: Vec<String> = numbers.iter().map(|x| x.to_string()).collect();
+	Ok(ComparableVersion::new(&bumped.join(".")))
+}
+
+pub(super) fn read(archive: &mut ZipArchive<impl Read + Seek>, name: &str) -> Result<Vec<u8>> {
+	let mut file = archive.by_name(name.trim_start_matches('/'))?;
+	let mut data = Vec::new();
+	file.read_to_end(&mut data)?;
+	Ok(data)
+}
+
+pub(super) fn parse(path: &Path, archive: &mut ZipArchive<impl Read + Seek>) -> Result<ModInfo> {
+	let json = read(archive, "fabric.mod.json").context("Failed to read fabric.mod.json")?;
+	let data: FabricMod = serde_json::from_slice(&json).context("Failed to parse fabric.mod.json")?;
+
+	// Fabric dependencies have no side of their own, they matter wherever the mod does
+	let side = match data.environment {
+		Environment::Any => Side::Both,
+		Environment::Client => Side::Client,
+		Environment::Server => Side::Server,
+	};
+
+	let mut dependencies = Vec::new();
+	for (deps, kind) in [
+		(data.depends, DepKind::Required),
+		(data.recommends, DepKind::Recommended),
+		(data.breaks, DepKind::Incompatible),
+	] {
+		for (mod_id, predicates) in deps {
+			// Not a mod, the server runs on whatever java it runs on
+			if mod_id == data.id || mod_id == "java" { continue }
+			let range = predicates
+				.range()
+				.with_context(|| format!("Unparseable version range for {mod_id}"))?;
+			dependencies.push(ModDependency {
+				mod_id,
+				kind,
+				range,
+				side,
+				ordering: None,
+				alternatives: Vec::new(),
+			});
+		}
+	}
+
+	let logo = data.icon
+		.and_then(Icon::largest)
+		.and_then(|icon| read(archive, &icon).ok());
+
+	let authors: Vec<String> = data.authors.into_iter().map(Person::name).collect();
+	let contributors: Vec<String> = data.contributors.into_iter().map(Person::name).collect();
+	let mut contact = data.contact;
+	let url = contact.remove("homepage").or_else(|| contact.remove("sources"));
+	let client_side_only = side == Side::Client;
+
+	Ok(ModInfo {
+		filename: jar_filename(path, &data.id),
+		path: path.canonicalize().context("Couldn't canonicalize path")?,
+		version: ComparableVersion::new(data.version.trim()),
+		mod_id: data.id,
+		name: data.name,
+		description: data.description,
+		logo,
+		url,
+		credits: (!contributors.is_empty()).then(|| contributors.join(", ")),
+		authors: (!authors.is_empty()).then_some(authors),
+		dependencies,
+		provides: Vec::new(),
+		loader: None,
+		loader_version: None,
+		license: data.license.and_then(License::joined),
+		issue_tracker: contact.remove("issues"),
+		update_json: None,
+		display_test: None,
+		client_side_only,
+		hashes: None,
+	})
+}
@@ -0,0 +1,59 @@
+use ende::{Decode, Encode};
+use openssl::sha::{sha1, sha512};
+
+/// What a jar's bytes hash to, for telling identical uploads apart and looking mods up elsewhere
+#[derive(Debug, Clone, Eq, PartialEq, Encode, Decode)]
+pub struct Hashes {
+	/// Lowercase hex, what Modrinth indexes files by
+	pub sha1: String,
+	pub sha512: String,
+	/// CurseForge's fingerprint
+	pub murmur2: u32,
+}
+
+impl Hashes {
+	pub fn of(data: &[u8]) -> Self {
+		Self {
+			sha1: hex(&sha1(data)),
+			sha512: hex(&sha512(data)),
+			murmur2: fingerprint(data),
+		}
+	}
+}
+
+fn hex(bytes: &[u8]) -> String {
+	bytes.iter().map(|x| format!("{x:02x}")).collect()
+}
+
+// MurmurHash2 with seed 1 over the file minus tabs, newlines, carriage returns and spaces.
+// That's how CurseForge does it, for whatever reason
+fn fingerprint(data: &[u8]) -> u32 {
+	const M: u32 = 0x5bd1e995;
+	const R: u32 = 24;
+
+	let data: Vec<u8> = data.iter().copied().filter(|x| !matches!(x, b'\t' | b'\n' | b'\r' | b' ')).collect();
+	let mut h = 1 ^ data.len() as u32;
+
+	let mut chunks = data.chunks_exact(4);
+	for chunk in chunks.by_ref() {
+		let mut k = u32::from_le_bytes(chunk.try_into().unwrap());
+		k = k.wrapping_mul(M);
+		k ^= k >> R;
+		k = k.wrapping_mul(M);
+		h = h.wrapping_mul(M);
+		h ^= k;
+	}
+
+	let tail = chunks.remainder();
+	if !tail.is_empty() {
+		for (i, byte) in tail.iter().enumerate() {
+			h ^= (*byte as u32) << (8 * i);
+		}
+		h = h.wrapping_mul(M);
+	}
+
+	h ^= h >> 13;
+	h = h.wrapping_mul(M);
+	h ^= h >> 15;
+	h
+}
@@ -0,0 +1,79 @@
+use std::io::{Cursor, Read, Seek};
+use std::path::Path;
+use anyhow::{bail, Context, Result};
+use mvn_version::ComparableVersion;
+use serde::Deserialize;
+use zip::ZipArchive;
+use super::fabric::read;
+use super::{parse_archive, ProvidedMod};
+
+// Forge and NeoForge's Jar-in-Jar, https://docs.neoforged.net/toolchain/docs/dependencies/jarinjar/
+
+const METADATA: &str = "META-INF/jarjar/metadata.json";
+
+// Bundles of bundles exist, bundles of bundles of bundles of bundles shouldn't
+const MAX_DEPTH: usize = 4;
+// Nested jars are read into memory
+const MAX_SIZE: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug, Deserialize)]
+struct Metadata {
+	jars: Vec<Jar>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Jar {
+	identifier: Identifier,
+	version: Version,
+	path: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Identifier {
+	artifact: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Version {
+	#[serde(rename = "artifactVersion")]
+	artifact_version: String,
+}
+
+fn nested(archive: &mut ZipArchive<impl Read + Seek>, name: &str) -> Result<ZipArchive<Cursor<Vec<u8>>>> {
+	let size = archive.by_name(name)?.size();
+	if size > MAX_SIZE {
+		bail!("{name} is {size} bytes, too big to look into");
+	}
+	Ok(ZipArchive::new(Cursor::new(read(archive, name)?))?)
+}
+
+/// Every mod bundled in `archive`, recursively. `path` is the outermost jar
+pub(super) fn provided(path: &Path, archive: &mut ZipArchive<impl Read + Seek>, depth: usize) -> Result<Vec<ProvidedMod>> {
+	if depth >= MAX_DEPTH || archive.index_for_name(METADATA).is_none() {
+		return Ok(Vec::new());
+	}
+	let metadata: Metadata = serde_json::from_slice(&read(archive, METADATA)?)
+		.context("Failed to parse the Jar-in-Jar metadata")?;
+
+	let mut provides = Vec::new();
+	for jar in metadata.jars {
+		let mods: Result<Vec<ProvidedMod>> = try {
+			let mut inner = nested(archive, &jar.path)?;
+			let mut mods: Vec<ProvidedMod> = parse_archive(path, &mut inner, None)?
+				.into_iter()
+				.map(|x| ProvidedMod { mod_id: x.mod_id, version: x.version })
+				.collect();
+			mods.extend(provided(path, &mut inner, depth + 1)?);
+			mods
+		};
+		match mods {
+			Ok(mods) => provides.extend(mods),
+			// A plain library, it goes by its artifact name
+			Err(_) => provides.push(ProvidedMod {
+				mod_id: jar.identifier.artifact,
+				version: ComparableVersion::new(&jar.version.artifact_version),
+			}),
+		}
+	}
+	Ok(provides)
+}
@@ -0,0 +1,144 @@
+use std::io::{Read, Seek};
+use std::path::Path;
+use anyhow::{bail, Context, Result};
+use mvn_version::ComparableVersion;
+use serde::Deserialize;
+use zip::ZipArchive;
+use super::fabric::read;
+use crate::VersionRange;
+use super::{jar_filename, DepKind, LoadOrder, ModDependency, ModInfo, Side};
+
+// mcmod.info from before 1.13, https://docs.minecraftforge.net/en/1.12.x/gettingstarted/structuring/
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum McmodInfo {
+	List(Vec<Mcmod>),
+	// modListVersion 2
+	Wrapped {
+		#[serde(rename = "modList")]
+		mod_list: Vec<Mcmod>,
+	},
+}
+
+#[derive(Debug, Deserialize)]
+struct Mcmod {
+	modid: String,
+	name: Option<String>,
+	description: Option<String>,
+	version: Option<String>,
+	url: Option<String>,
+	credits: Option<String>,
+	#[serde(rename = "authorList", alias = "authors", default)]
+	author_list: Vec<String>,
+	#[serde(rename = "logoFile")]
+	logo_file: Option<String>,
+	#[serde(rename = "requiredMods", default)]
+	required_mods: Vec<String>,
+	#[serde(default)]
+	dependencies: Vec<String>,
+	#[serde(rename = "updateJSON")]
+	update_json: Option<String>,
+}
+
+// FML's `required-after:forge@[14.23,)`, where the `required-after:` part is optional
+fn dependency(string: &str, required: bool) -> Result<Option<ModDependency>> {
+	let (kind, rest) = string.split_once(':').unwrap_or(("", string));
+	let (kind, side, ordering) = match kind {
+		"" => (if required { DepKind::Required } else { DepKind::Optional }, Side::Both, None),
+		"required-after" => (DepKind::Required, Side::Both, Some(LoadOrder::After)),
+		"required-before" => (DepKind::Required, Side::Both, Some(LoadOrder::Before)),
+		"required-client" => (DepKind::Required, Side::Client, None),
+		"required-server" => (DepKind::Required, Side::Server, None),
+		"after" => (DepKind::Optional, Side::Both, Some(LoadOrder::After)),
+		"before" => (DepKind::Optional, Side::Both, Some(LoadOrder::Before)),
+		"client" => (DepKind::Optional, Side::Client, None),
+		"server" => (DepKind::Optional, Side::Server, None),
+		_ => bail!("Unknown dependency type in {string:?}"),
+	};
+
+	let (mod_id, range) = match rest.split_once('@') {
+		Some((mod_id, range)) => (mod_id.trim(), Some(range.trim())),
+		None => (rest.trim(), None),
+	};
+	// `after:*` only orders loading
+	if mod_id.is_empty() || mod_id == "*" {
+		return Ok(None);
+	}
+
+	// FML reads a bare version as that version or newer
+	let range = match range {
+		Some(range) if !range.starts_with(['[', '(']) => VersionRange::at_least(ComparableVersion::new(range)),
+		Some(range) => range.parse().with_context(|| format!("Unparseable version range for {mod_id}"))?,
+		None => VersionRange::any(),
+	};
+	Ok(Some(ModDependency {
+		mod_id: mod_id.to_owned(),
+		kind,
+		range,
+		side,
+		ordering,
+		alternatives: Vec::new(),
+	}))
+}
+
+pub(super) fn parse(path: &Path, archive: &mut ZipArchive<impl Read + Seek>) -> Result<Vec<ModInfo>> {
+	let json = read(archive, "mcmod.info").context("Failed to read mcmod.info")?;
+	// Plenty of these have raw newlines and tabs in their strings, which gson lets slide
+	let json = String::from_utf8_lossy(&json).replace(['\n', '\r', '\t'], " ");
+	let mods = match serde_json::from_str::<McmodInfo>(&json).context("Failed to parse mcmod.info")? {
+		McmodInfo::List(mods) => mods,
+		McmodInfo::Wrapped { mod_list } => mod_list,
+	};
+	if mods.is_empty() {
+		bail!("Found no mods in mcmod.info");
+	}
+
+	let mut infos = Vec::new();
+	for data in mods {
+		let mut dependencies: Vec<ModDependency> = Vec::new();
+		let declared = data.required_mods.iter().map(|x| (x, true))
+			.chain(data.dependencies.iter().map(|x| (x, false)));
+		for (string, required) in declared {
+			// The annotation syntax separates them with semicolons, some people paste that in whole
+			for string in string.split(';').map(str::trim).filter(|x| !x.is_empty()) {
+				let Some(dep) = dependency(string, required)? else { continue };
+				if dep.mod_id == data.modid { continue }
+				// Usually listed in both, the required one wins
+				match dependencies.iter_mut().find(|x| x.mod_id == dep.mod_id) {
+					Some(existing) if dep.kind == DepKind::Required => *existing = dep,
+					Some(_) => {}
+					None => dependencies.push(dep),
+				}
+			}
+		}
+
+		let logo = data.logo_file
+			.filter(|x| !x.is_empty())
+			.and_then(|logo| read(archive, &logo).ok());
+
+		infos.push(ModInfo {
+			filename: jar_filename(path, &data.modid),
+			path: path.canonicalize().context("Couldn't canonicalize path")?,
+			version: ComparableVersion::new(data.version.as_deref().unwrap_or("0").trim()),
+			mod_id: data.modid,
+			name: data.name,
+			description: data.description,
+			logo,
+			url: data.url.filter(|x| !x.is_empty()),
+			credits: data.credits.filter(|x| !x.is_empty()),
+			authors: (!data.author_list.is_empty()).then_some(data.author_list),
+			dependencies,
+			provides: Vec::new(),
+			loader: None,
+			loader_version: None,
+			license: None,
+			issue_tracker: None,
+			update_json: data.update_json.filter(|x| !x.is_empty()),
+			display_test: None,
+			client_side_only: false,
+			hashes: None,
+		});
+	}
+	Ok(infos)
+}
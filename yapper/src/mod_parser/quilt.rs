@@ -0,0 +1,272 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Seek};
+use std::path::Path;
+use anyhow::{Context, Result};
+use mvn_version::ComparableVersion;
+use serde::Deserialize;
+use zip::ZipArchive;
+use crate::VersionRange;
+use super::fabric::{all_of, any_of, read, Icon, License};
+use super::{jar_filename, DepKind, ModDependency, ModInfo, ProvidedMod, Side};
+
+// https://github.com/QuiltMC/rfcs/blob/main/specification/0002-quilt.mod.json.md
+
+#[derive(Debug, Deserialize)]
+struct QuiltMod {
+	quilt_loader: Loader,
+	#[serde(default)]
+	minecraft: Minecraft,
+}
+
+#[derive(Debug, Deserialize)]
+struct Loader {
+	id: String,
+	version: String,
+	#[serde(default)]
+	metadata: Metadata,
+	#[serde(default)]
+	depends: Vec<Dependency>,
+	#[serde(default)]
+	breaks: Vec<Dependency>,
+	// Mods this one stands in for
+	#[serde(default)]
+	provides: Vec<Provides>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Provides {
+	Id(String),
+	Object {
+		id: String,
+		// Defaults to the providing mod's own version
+		version: Option<String>,
+	},
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Metadata {
+	name: Option<String>,
+	description: Option<String>,
+	// Name to role, or to a list of roles
+	#[serde(default)]
+	contributors: BTreeMap<String, Roles>,
+	#[serde(default)]
+	contact: HashMap<String, String>,
+	icon: Option<Icon>,
+	license: Option<License>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Roles {
+	One(String),
+	Many(Vec<String>),
+}
+
+impl Roles {
+	fn list(&self) -> Vec<&str> {
+		match self {
+			Roles::One(role) => vec![role.as_str()],
+			Roles::Many(roles) => roles.iter().map(|x| x.as_str()).collect(),
+		}
+	}
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Minecraft {
+	#[serde(default)]
+	environment: Environment,
+}
+
+#[derive(Debug, Default, Deserialize)]
+enum Environment {
+	#[default]
+	#[serde(rename = "*")]
+	Any,
+	#[serde(rename = "client")]
+	Client,
+	#[serde(rename = "dedicated_server")]
+	Server,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Dependency {
+	Id(String),
+	Object {
+		id: String,
+		#[serde(default)]
+		versions: Option<Versions>,
+		#[serde(default)]
+		optional: bool,
+	},
+	// Any one of them is enough, which a flat list of dependencies can't say
+	Alternatives(Vec<Dependency>),
+}
+
+impl Dependency {
+	// Every mod this could be satisfied by, nested lists flattened
+	fn members(self) -> Vec<(String, Option<Versions>, bool)> {
+		match self {
+			Dependency::Id(id) => vec![(id, None, false)],
+			Dependency::Object { id, versions, optional } => vec![(id, versions, optional)],
+			Dependency::Alternatives(list) => list.into_iter().flat_map(Dependency::members).collect(),
+		}
+	}
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Versions {
+	One(String),
+	// Any of them
+	Any(Vec<String>),
+	Object(VersionsObject),
+}
+
+#[derive(Debug, Deserialize)]
+enum VersionsObject {
+	#[serde(rename = "any")]
+	Any(Vec<Versions>),
+	#[serde(rename = "all")]
+	All(Vec<Versions>),
+}
+
+impl Versions {
+	fn range(&self) -> Result<VersionRange> {
+		match self {
+			Versions::One(x) => all_of(x),
+			Versions::Any(list) => any_of(list.iter().map(|x| all_of(x))),
+			Versions::Object(VersionsObject::Any(list)) => any_of(list.iter().map(Versions::range)),
+			Versions::Object(VersionsObject::All(list)) => list
+				.iter()
+				.try_fold(VersionRange::any(), |range, x| Ok(range.intersect(&x.range()?))),
+		}
+	}
+}
+
+// Ids may be qualified with a maven group, like org.quiltmc:quilt_loader
+fn mod_id(id: &str) -> &str {
+	id.rsplit_once(':').map(|(_, id)| id).unwrap_or(id)
+}
+
+pub(super) fn parse(path: &Path, archive: &mut ZipArchive<impl Read + Seek>) -> Result<ModInfo> {
+	let json = read(archive, "quilt.mod.json").context("Failed to read quilt.mod.json")?;
+	let data: QuiltMod = serde_json::from_slice(&json).context("Failed to parse quilt.mod.json")?;
+	let loader = data.quilt_loader;
+
+	// Same as fabric, dependencies matter wherever the mod does
+	let side = match data.minecraft.environment {
+		Environment::Any => Side::Both,
+		Environment::Client => Side::Client,
+		Environment::Server => Side::Server,
+	};
+
+	let mut dependencies = Vec::new();
+	for (deps, incompatible) in [(loader.depends, false), (loader.breaks, true)] {
+		for dep in deps {
+			let mut members = Vec::new();
+			for (id, versions, optional) in dep.members() {
+				let id = mod_id(&id);
+				// Not a mod, the server runs on whatever java it runs on
+				if id == loader.id || id == "java" { continue }
+
+				let range = match versions {
+					Some(versions) => versions.range().with_context(|| format!("Unparseable version range for {id}"))?,
+					None => VersionRange::any(),
+				};
+				members.push((id.to_owned(), range, optional));
+			}
+			if members.is_empty() { continue }
+
+			if incompatible {
+				// The spec doesn't say whether a list in breaks means all of them or any of them together,
+				// so only one is a hard incompatibility and a list of them just gets warned about
+				let kind = if members.len() == 1 { DepKind::Incompatible } else { DepKind::Discouraged };
+				for (mod_id, range, _) in members {
+					dependencies.push(ModDependency {
+						mod_id,
+						kind,
+						range,
+						side,
+						ordering: None,
+						alternatives: Vec::new(),
+					});
+				}
+				continue;
+			}
+
+			let kind = if members.iter().all(|(_, _, optional)| *optional) {
+				DepKind::Optional
+			} else {
+				DepKind::Required
+			};
+			let mut members = members.into_iter().map(|(mod_id, range, _)| (mod_id, range));
+			let (mod_id, range) = members.next().unwrap();
+			dependencies.push(ModDependency {
+				mod_id,
+				kind,
+				range,
+				side,
+				ordering: None,
+				alternatives: members.collect(),
+			});
+		}
+	}
+
+	let own_version = ComparableVersion::new(loader.version.trim());
+	let provides = loader.provides
+		.into_iter()
+		.map(|x| match x {
+			Provides::Id(id) => ProvidedMod { mod_id: mod_id(&id).to_owned(), version: own_version.clone() },
+			Provides::Object { id, version } => ProvidedMod {
+				mod_id: mod_id(&id).to_owned(),
+				version: version.map(|x| ComparableVersion::new(x.trim())).unwrap_or_else(|| own_version.clone()),
+			},
+		})
+		.collect();
+
+	let metadata = loader.metadata;
+	let logo = metadata.icon
+		.and_then(Icon::largest)
+		.and_then(|icon| read(archive, &icon).ok());
+
+	// Owners are the authors, everyone else gets credited with their role
+	let mut authors = Vec::new();
+	let mut credits = Vec::new();
+	for (name, roles) in metadata.contributors.iter() {
+		let roles = roles.list();
+		if roles.iter().any(|x| x.eq_ignore_ascii_case("owner") || x.eq_ignore_ascii_case("author")) {
+			authors.push(name.clone());
+		} else {
+			credits.push(format!("{name} ({})", roles.join(", ")));
+		}
+	}
+	let mut contact = metadata.contact;
+	let url = contact.remove("homepage").or_else(|| contact.remove("sources"));
+	let issue_tracker = contact.remove("issues");
+
+	Ok(ModInfo {
+		filename: jar_filename(path, &loader.id),
+		path: path.canonicalize().context("Couldn't canonicalize path")?,
+		version: own_version,
+		mod_id: loader.id,
+		name: metadata.name,
+		description: metadata.description,
+		logo,
+		url,
+		credits: (!credits.is_empty()).then(|| credits.join(", ")),
+		authors: (!authors.is_empty()).then_some(authors),
+		dependencies,
+		provides,
+		loader: None,
+		loader_version: None,
+		license: metadata.license.and_then(License::joined),
+		issue_tracker,
+		update_json: None,
+		display_test: None,
+		client_side_only: side == Side::Client,
+		hashes: None,
+	})
+}
@@ -0,0 +1,314 @@
+use std::collections::HashMap;
+use std::net::{Shutdown, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+use anyhow::{bail, Context, Result};
+use crate::{Capabilities, client_handshake, client_hello, client_login, CryptSend, dispatch_debug, FrameLimit, KEEPALIVE_INTERVAL, NetCommand, Notification, NotificationFilter, protocol_context, recv_thing, Response, send_packet, send_thing, SESSION_TIMEOUT, SessionEvent, SessionPacket};
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Credentials {
+	pub addr: String,
+	pub pub_key: Vec<u8>,
+	pub user: String,
+	pub password: [u8; 32],
+}
+
+// Where a subscription's notifications go. Shared between the session and its connection,
+// so a receiver found dropped on one connection isn't brought over to the next
+#[derive(Clone)]
+struct Sink {
+	send: Sender<Notification>,
+	closed: Arc<AtomicBool>,
+}
+
+impl Sink {
+	fn new(send: Sender<Notification>) -> Self {
+		Self { send, closed: Arc::new(AtomicBool::new(false)) }
+	}
+
+	// False once the receiver is gone, it doesn't come back
+	fn send(&self, notif: Notification) -> bool {
+		let sent = self.send.send(notif).is_ok();
+		if !sent {
+			self.closed.store(true, Ordering::Release);
+		}
+		sent
+	}
+
+	fn closed(&self) -> bool {
+		self.closed.load(Ordering::Acquire)
+	}
+}
+
+struct Connection {
+	writer: Mutex<(TcpStream, CryptSend)>,
+	pending: Mutex<HashMap<u64, Sender<Response>>>,
+	subscriptions: Mutex<HashMap<u64, Sink>>,
+	alive: AtomicBool,
+	capabilities: Capabilities,
+}
+
+impl Connection {
+	fn send(&self, packet: SessionPacket) -> Result<()> {
+		let result = {
+			let mut writer = self.writer.lock().unwrap();
+			let (client, crypt) = &mut *writer;
+			send_thing(client, crypt, protocol_context(), packet)
+		};
+		if result.is_err() {
+			self.kill();
+		}
+		result
+	}
+
+	fn kill(&self) {
+		self.alive.store(false, Ordering::Release);
+		let _ = self.writer.lock().unwrap().0.shutdown(Shutdown::Both);
+		// Dropping the senders wakes up everyone still waiting
+		self.pending.lock().unwrap().clear();
+		self.subscriptions.lock().unwrap().clear();
+	}
+
+	fn request(&self, id: u64, cmd: NetCommand, timeout: Duration) -> Result<Response> {
+		let missing = cmd.required_capabilities() - self.capabilities;
+		if !missing.is_empty() {
+			bail!("Server doesn't support {missing:?}")
+		}
+
+		let (send, recv) = channel();
+		self.pending.lock().unwrap().insert(id, send);
+
+		if let Err(err) = self.send(SessionPacket::Request(id, cmd)) {
+			self.pending.lock().unwrap().remove(&id);
+			return Err(err.context("Failed to send request"));
+		}
+
+		match recv.recv_timeout(timeout) {
+			Ok(response) => Ok(response),
+			Err(RecvTimeoutError::Timeout) => {
+				self.pending.lock().unwrap().remove(&id);
+				bail!("Timed out waiting for response to request {id}")
+			}
+			Err(RecvTimeoutError::Disconnected) => bail!("Connection lost while waiting for response to request {id}"),
+		}
+	}
+
+	fn subscribe(&self, id: u64, filter: NotificationFilter, sink: Sink, timeout: Duration) -> Result<()> {
+		// Registered before asking, the first notification may beat the response
+		self.subscriptions.lock().unwrap().insert(id, sink);
+
+		match self.request(id, NetCommand::Subscribe(filter), timeout) {
+			Ok(Response::Ok) => Ok(()),
+			Ok(any) => {
+				self.subscriptions.lock().unwrap().remove(&id);
+				bail!("Server refused subscription: {any}")
+			}
+			Err(err) => {
+				self.subscriptions.lock().unwrap().remove(&id);
+				Err(err)
+			}
+		}
+	}
+}
+
+/// A long-lived, authenticated connection to sv_manage carrying many requests at once.
+/// The connection is re-established transparently when it drops.
+pub struct Session {
+	creds: Credentials,
+	next_id: AtomicU64,
+	conn: Mutex<Option<Arc<Connection>>>,
+	// Replayed on every reconnect, as long as someone is still listening
+	subscriptions: Mutex<Vec<(NotificationFilter, Sink)>>,
+}
+
+impl Session {
+	pub fn new(creds: Credentials) -> Self {
+		Self {
+			creds,
+			next_id: AtomicU64::new(0),
+			conn: Mutex::new(None),
+			subscriptions: Mutex::new(Vec::new()),
+		}
+	}
+
+	pub fn credentials(&self) -> &Credentials {
+		&self.creds
+	}
+
+	pub fn request(&self, cmd: NetCommand, timeout: Duration) -> Result<Response> {
+		// One retry with a fresh connection in case the old one went stale
+		match self.request_once(cmd.clone(), timeout) {
+			Ok(response) => Ok(response),
+			Err(err) if self.connection_dead() => {
+				dispatch_debug(err.context("Session dropped, reconnecting"));
+				self.request_once(cmd, timeout)
+			}
+			Err(err) => Err(err),
+		}
+	}
+
+	/// Notifications matching `filter` are pushed into the returned channel as they happen.
+	/// The subscription lives until the receiver is dropped and survives reconnects,
+	/// though anything that happens while disconnected is missed.
+	pub fn subscribe(&self, filter: NotificationFilter, timeout: Duration) -> Result<Receiver<Notification>> {
+		let (send, recv) = channel();
+		let sink = Sink::new(send);
+		let conn = self.connection()?;
+		let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+		conn.subscribe(id, filter.clone(), sink.clone(), timeout)?;
+
+		let mut subscriptions = self.subscriptions.lock().unwrap();
+		subscriptions.retain(|(_, sink)| !sink.closed());
+		subscriptions.push((filter, sink));
+		Ok(recv)
+	}
+
+	/// Reconnects right away if the connection dropped, so subscriptions keep flowing
+	/// even when no requests are being made
+	pub fn ensure_connected(&self) -> Result<()> {
+		self.connection().map(|_| ())
+	}
+
+	/// What both ends agreed on during the hello, connecting first if needed
+	pub fn capabilities(&self) -> Result<Capabilities> {
+		self.connection().map(|conn| conn.capabilities)
+	}
+
+	pub fn close(&self) {
+		if let Some(conn) = self.conn.lock().unwrap().take() {
+			let _ = conn.send(SessionPacket::Close);
+			conn.kill();
+		}
+	}
+
+	fn connection_dead(&self) -> bool {
+		self.conn.lock().unwrap().as_ref().is_none_or(|conn| !conn.alive.load(Ordering::Acquire))
+	}
+
+	fn request_once(&self, cmd: NetCommand, timeout: Duration) -> Result<Response> {
+		let conn = self.connection()?;
+		let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+		conn.request(id, cmd, timeout)
+	}
+
+	fn connection(&self) -> Result<Arc<Connection>> {
+		let mut conn = self.conn.lock().unwrap();
+		if let Some(existing) = &*conn && existing.alive.load(Ordering::Acquire) {
+			return Ok(existing.clone());
+		}
+
+		let new = connect(&self.creds).context("Failed to open session")?;
+
+		// Bring the subscriptions over to the new connection
+		let resubscribed: Result<()> = try {
+			let mut subscriptions = self.subscriptions.lock().unwrap();
+			// Receivers dropped in the meantime are left behind
+			subscriptions.retain(|(_, sink)| !sink.closed());
+			for (filter, sink) in subscriptions.iter() {
+				let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+				new.subscribe(id, filter.clone(), sink.clone(), SESSION_TIMEOUT)?;
+			}
+		};
+		if let Err(err) = resubscribed {
+			new.kill();
+			return Err(err.context("Failed to resubscribe"));
+		}
+
+		*conn = Some(new.clone());
+		Ok(new)
+	}
+}
+
+impl Drop for Session {
+	fn drop(&mut self) {
+		self.close();
+	}
+}
+
+fn connect(creds: &Credentials) -> Result<Arc<Connection>> {
+	let ctxt = protocol_context();
+	let client = TcpStream::connect(&creds.addr)?;
+	let (mut client, mut crypt) = client_handshake(client, &creds.pub_key, ctxt)?;
+
+	let (_, capabilities) = client_hello(&mut client, &mut crypt, ctxt)?;
+	if !capabilities.contains(Capabilities::Session) {
+		bail!("Server doesn't support sessions")
+	}
+
+	client_login(&mut client, &mut crypt, ctxt, &creds.user, &creds.password)?;
+
+	match send_packet(&mut client, &mut crypt, ctxt, NetCommand::Session)? {
+		Response::Ok => {}
+		any => bail!("Server refused session mode: {any}"),
+	}
+
+	crypt.set_limit(FrameLimit::SESSION);
+	let mut reader = client.try_clone().context("Failed to clone socket")?;
+	reader.set_read_timeout(Some(SESSION_TIMEOUT))?;
+	let (send, mut recv) = crypt.split();
+
+	let conn = Arc::new(Connection {
+		writer: Mutex::new((client, send)),
+		pending: Mutex::new(HashMap::new()),
+		subscriptions: Mutex::new(HashMap::new()),
+		alive: AtomicBool::new(true),
+		capabilities,
+	});
+
+	// Reader: routes responses back to whoever is waiting for them
+	let reader_conn = conn.clone();
+	thread::spawn(move || {
+		let result: Result<()> = try {
+			loop {
+				match recv_thing(&mut reader, &mut recv, ctxt)? {
+					SessionEvent::Response(id, response) => {
+						if let Some(waiting) = reader_conn.pending.lock().unwrap().remove(&id) {
+							let _ = waiting.send(response);
+						}
+					}
+					SessionEvent::Pong(_) => {}
+					SessionEvent::Notification(id, notif) => {
+						let sink = reader_conn.subscriptions.lock().unwrap().get(&id).cloned();
+						if let Some(sink) = sink && !sink.send(notif) {
+							// Nobody is listening anymore
+							reader_conn.subscriptions.lock().unwrap().remove(&id);
+							reader_conn.send(SessionPacket::Unsubscribe(id))?;
+						}
+					}
+				}
+			}
+		};
+		if reader_conn.alive.load(Ordering::Acquire) && let Err(err) = result {
+			dispatch_debug(err);
+		}
+		reader_conn.kill();
+	});
+
+	// Keepalive: the server drops sessions that stay silent for too long
+	let ping_conn = Arc::downgrade(&conn);
+	thread::spawn(move || {
+		let mut seq = 0;
+		loop {
+			thread::sleep(KEEPALIVE_INTERVAL);
+			let Some(conn) = ping_conn.upgrade() else { break };
+			if !conn.alive.load(Ordering::Acquire) { break }
+			if conn.send(SessionPacket::Ping(seq)).is_err() { break }
+			seq += 1;
+		}
+	});
+
+	Ok(conn)
+}
+
+impl core::fmt::Debug for Session {
+	fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+		f.debug_struct("Session")
+			.field("addr", &self.creds.addr)
+			.field("user", &self.creds.user)
+			.finish()
+	}
+}
+
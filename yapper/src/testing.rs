@@ -0,0 +1,174 @@
+use std::fs;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use anyhow::{Context, Result};
+use openssl::rsa::Rsa;
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+// Building blocks for integration tests: throwaway directories, keys,
+// and server folders and mod jars that look real enough to sv_manage
+
+pub const MC_VERSION: &str = "1.20.1";
+pub const FORGE_VERSION: &str = "47.2.0";
+// For minecraft 1.20.4
+pub const NEOFORGE_VERSION: &str = "20.4.80-beta";
+pub const FABRIC_LOADER_VERSION: &str = "0.15.0";
+pub const LEGACY_MC_VERSION: &str = "1.12.2";
+pub const LEGACY_FORGE_VERSION: &str = "14.23.5.2859";
+
+/// What a `fake_server` has installed
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Loader {
+	/// `FORGE_VERSION` for `MC_VERSION`
+	Forge,
+	/// `NEOFORGE_VERSION`
+	NeoForge,
+	/// `FABRIC_LOADER_VERSION` for `MC_VERSION`, launched from fabric-server-launch.jar
+	Fabric,
+	/// `LEGACY_FORGE_VERSION` for `LEGACY_MC_VERSION`, launched from the forge jar itself
+	LegacyForge,
+}
+
+static NEXT_DIR: AtomicU64 = AtomicU64::new(0);
+
+// Removed with everything in it when dropped
+#[derive(Debug)]
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+	pub fn new(prefix: &str) -> Result<Self> {
+		let path = std::env::temp_dir().join(format!(
+			"{prefix}-{}-{}",
+			std::process::id(),
+			NEXT_DIR.fetch_add(1, Ordering::Relaxed),
+		));
+		let _ = fs::remove_dir_all(&path);
+		fs::create_dir_all(&path).with_context(|| format!("Failed to create {path:?}"))?;
+		Ok(Self(path))
+	}
+
+	pub fn path(&self) -> &Path {
+		&self.0
+	}
+}
+
+impl Drop for TempDir {
+	fn drop(&mut self) {
+		let _ = fs::remove_dir_all(&self.0);
+	}
+}
+
+// A fresh (private, public) pem pair, the size the handshake expects
+pub fn keypair() -> Result<(Vec<u8>, Vec<u8>)> {
+	let key = Rsa::generate(2048)?;
+	Ok((key.private_key_to_pem()?, key.public_key_to_pem()?))
+}
+
+/// A zip at `path` holding `files`, as (name inside the jar, contents) pairs
+pub fn write_jar(path: &Path, files: &[(&str, &[u8])]) -> Result<()> {
+	if let Some(parent) = path.parent() {
+		fs::create_dir_all(parent)?;
+	}
+	let mut zip = ZipWriter::new(File::create(path).with_context(|| format!("Failed to create {path:?}"))?);
+	for (name, data) in files {
+		zip.start_file(*name, SimpleFileOptions::default())?;
+		zip.write_all(data)?;
+	}
+	zip.finish()?;
+	Ok(())
+}
+
+// Not a real png, nobody decodes it
+const LOGO: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// A forge mod jar with just enough metadata for `parse_mod`.
+/// `deps` are (mod id, version range) pairs, all mandatory
+pub fn mod_jar(path: &Path, mod_id: &str, version: &str, deps: &[(&str, &str)]) -> Result<PathBuf> {
+	let mut toml = format!(
+		"modLoader = \"javafml\"\nloaderVersion = \"[47,)\"\nlicense = \"MIT\"\n\n\
+		[[mods]]\nmodId = \"{mod_id}\"\nversion = \"{version}\"\ndisplayName = \"{mod_id}\"\nlogoFile = \"logo.png\"\n"
+	);
+	for (dep, range) in deps {
+		toml.push_str(&format!(
+			"\n[[dependencies.{mod_id}]]\nmodId = \"{dep}\"\nmandatory = true\nversionRange = \"{range}\"\nside = \"BOTH\"\n"
+		));
+	}
+
+	write_jar(path, &[
+		("META-INF/mods.toml", toml.as_bytes()),
+		("logo.png", LOGO),
+	])?;
+	Ok(path.to_owned())
+}
+
+/// A forge server folder under `root` that sv_manage can run, see `fake_server_ext`
+pub fn fake_server(root: &Path, name: &str) -> Result<PathBuf> {
+	fake_server_ext(root, name, Loader::Forge)
+}
+
+/// A server folder under `root` that sv_manage can run: the loader's universal jar where
+/// `run.sh` says it is, and a `run.sh` that echoes its console and exits on `stop`
+pub fn fake_server_ext(root: &Path, name: &str, loader: Loader) -> Result<PathBuf> {
+	let path = root.join(name);
+	fs::create_dir_all(path.join("mods"))?;
+
+	let launch = match loader {
+		Loader::Forge => {
+			let forge_dir = format!("libraries/net/minecraftforge/forge/{MC_VERSION}-{FORGE_VERSION}");
+			let forge_toml = "modLoader = \"javafml\"\nloaderVersion = \"[47,)\"\nlicense = \"LGPL\"\n\n\
+				[[mods]]\nmodId = \"forge\"\nversion = \"${global.forgeVersion}\"\ndisplayName = \"Forge\"\n";
+			write_jar(&path.join(&forge_dir).join(format!("forge-{MC_VERSION}-{FORGE_VERSION}-universal.jar")), &[
+				("META-INF/mods.toml", forge_toml.as_bytes()),
+				("mcplogo.png", LOGO),
+			])?;
+			format!("java @user_jvm_args.txt @{forge_dir}/unix_args.txt")
+		}
+		Loader::NeoForge => {
+			let neoforge_dir = format!("libraries/net/neoforged/neoforge/{NEOFORGE_VERSION}");
+			let neoforge_toml = "modLoader = \"javafml\"\nloaderVersion = \"[1,)\"\nlicense = \"LGPL\"\n\n\
+				[[mods]]\nmodId = \"neoforge\"\nversion = \"${global.neoForgeVersion}\"\ndisplayName = \"NeoForge\"\n";
+			write_jar(&path.join(&neoforge_dir).join(format!("neoforge-{NEOFORGE_VERSION}-universal.jar")), &[
+				("META-INF/mods.toml", neoforge_toml.as_bytes()),
+			])?;
+			format!("java @user_jvm_args.txt @{neoforge_dir}/unix_args.txt")
+		}
+		Loader::Fabric => {
+			write_jar(&path.join("fabric-server-launch.jar"), &[])?;
+			write_jar(&path.join(format!(
+				"libraries/net/fabricmc/fabric-loader/{FABRIC_LOADER_VERSION}/fabric-loader-{FABRIC_LOADER_VERSION}.jar"
+			)), &[])?;
+			write_jar(&path.join(format!(
+				"libraries/net/fabricmc/intermediary/{MC_VERSION}/intermediary-{MC_VERSION}.jar"
+			)), &[])?;
+			"java -jar fabric-server-launch.jar nogui".to_owned()
+		}
+		Loader::LegacyForge => {
+			let jar = format!("forge-{LEGACY_MC_VERSION}-{LEGACY_FORGE_VERSION}.jar");
+			let version_json = format!(
+				"{{\"id\": \"{LEGACY_MC_VERSION}-forge-{LEGACY_FORGE_VERSION}\", \"inheritsFrom\": \"{LEGACY_MC_VERSION}\"}}"
+			);
+			write_jar(&path.join(&jar), &[("version.json", version_json.as_bytes())])?;
+			format!("java -jar {jar} nogui")
+		}
+	};
+
+	let run = path.join("run.sh");
+	fs::write(&run, format!(
+		"#!/bin/sh\n\
+		# {launch} \"$@\"\n\
+		while read -r line; do\n\
+		\techo \"$line\"\n\
+		\t[ \"$line\" = \"stop\" ] && exit 0\n\
+		done\n"
+	))?;
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::PermissionsExt;
+		fs::set_permissions(&run, fs::Permissions::from_mode(0o755))?;
+	}
+
+	Ok(path)
+}
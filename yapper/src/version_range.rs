@@ -0,0 +1,320 @@
+use std::cmp::Ordering;
+use std::fmt::Formatter;
+use std::ops::Bound;
+use std::str::FromStr;
+use anyhow::{anyhow, bail};
+use ende::{Decode, Encode, Encoder, EncodingResult, val_error};
+use mvn_version::ComparableVersion;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use crate::WrappedComparableVersion;
+
+/// A maven version range like `[1.0,2.0),[3.0,)`, matching a version in any of its intervals.
+/// Exclusions are written as unions, `(,1.5),(1.5,)` is anything but 1.5
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct VersionRange(Vec<Interval>);
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Interval {
+	pub min: Bound<ComparableVersion>,
+	pub max: Bound<ComparableVersion>,
+}
+
+impl Interval {
+	pub fn contains(&self, version: &ComparableVersion) -> bool {
+		let above = match &self.min {
+			Bound::Included(min) => version >= min,
+			Bound::Excluded(min) => version > min,
+			Bound::Unbounded => true,
+		};
+		let below = match &self.max {
+			Bound::Included(max) => version <= max,
+			Bound::Excluded(max) => version < max,
+			Bound::Unbounded => true,
+		};
+		above && below
+	}
+
+	fn is_empty(&self) -> bool {
+		match (bound_version(&self.min), bound_version(&self.max)) {
+			(Some(min), Some(max)) => match min.cmp(max) {
+				Ordering::Less => false,
+				Ordering::Equal => !matches!((&self.min, &self.max), (Bound::Included(_), Bound::Included(_))),
+				Ordering::Greater => true,
+			},
+			_ => false,
+		}
+	}
+
+	fn intersect(&self, other: &Interval) -> Interval {
+		Interval {
+			min: std::cmp::max_by(&self.min, &other.min, |a, b| cmp_lower(a, b)).clone(),
+			max: std::cmp::min_by(&self.max, &other.max, |a, b| cmp_upper(a, b)).clone(),
+		}
+	}
+}
+
+impl VersionRange {
+	pub fn any() -> Self {
+		Self(vec![Interval { min: Bound::Unbounded, max: Bound::Unbounded }])
+	}
+
+	pub fn exactly(version: ComparableVersion) -> Self {
+		Self(vec![Interval { min: Bound::Included(version.clone()), max: Bound::Included(version) }])
+	}
+
+	pub fn at_least(version: ComparableVersion) -> Self {
+		Self(vec![Interval { min: Bound::Included(version), max: Bound::Unbounded }])
+	}
+
+	pub fn between(min: Bound<ComparableVersion>, max: Bound<ComparableVersion>) -> Self {
+		Self(vec![Interval { min, max }])
+	}
+
+	pub fn intervals(&self) -> &[Interval] {
+		&self.0
+	}
+
+	pub fn contains(&self, version: &ComparableVersion) -> bool {
+		self.0.iter().any(|x| x.contains(version))
+	}
+
+	pub fn is_any(&self) -> bool {
+		self.0.iter().any(|x| x.min == Bound::Unbounded && x.max == Bound::Unbounded)
+	}
+
+	/// Versions in either range
+	pub fn union(mut self, other: VersionRange) -> Self {
+		self.0.extend(other.0);
+		self
+	}
+
+	/// Versions in both ranges
+	pub fn intersect(&self, other: &VersionRange) -> Self {
+		let mut intervals: Vec<Interval> = self.0
+			.iter()
+			.flat_map(|x| other.0.iter().map(|y| x.intersect(y)))
+			.collect();
+		// Keep one empty interval if that's all there is, a range always has at least one
+		if intervals.iter().any(|x| !x.is_empty()) {
+			intervals.retain(|x| !x.is_empty());
+		} else {
+			intervals.truncate(1);
+		}
+		Self(intervals)
+	}
+
+	/// The one interval covering all of this, how it looked before ranges could be unions.
+	/// Only a safe stand-in for versions that are wanted, it also covers the gaps between intervals
+	pub fn hull(&self) -> Interval {
+		let min = self.0.iter().map(|x| &x.min).min_by(|a, b| cmp_lower(a, b)).cloned();
+		let max = self.0.iter().map(|x| &x.max).max_by(|a, b| cmp_upper(a, b)).cloned();
+		Interval { min: min.unwrap_or(Bound::Unbounded), max: max.unwrap_or(Bound::Unbounded) }
+	}
+}
+
+fn bound_version(bound: &Bound<ComparableVersion>) -> Option<&ComparableVersion> {
+	match bound {
+		Bound::Included(x) | Bound::Excluded(x) => Some(x),
+		Bound::Unbounded => None,
+	}
+}
+
+// Lower bounds, from the one that lets the most versions through
+fn cmp_lower(a: &Bound<ComparableVersion>, b: &Bound<ComparableVersion>) -> Ordering {
+	match (bound_version(a), bound_version(b)) {
+		(None, None) => Ordering::Equal,
+		(None, Some(_)) => Ordering::Less,
+		(Some(_), None) => Ordering::Greater,
+		(Some(x), Some(y)) => x.cmp(y).then(matches!(a, Bound::Excluded(_)).cmp(&matches!(b, Bound::Excluded(_)))),
+	}
+}
+
+// Upper bounds, from the one that lets the fewest versions through
+fn cmp_upper(a: &Bound<ComparableVersion>, b: &Bound<ComparableVersion>) -> Ordering {
+	match (bound_version(a), bound_version(b)) {
+		(None, None) => Ordering::Equal,
+		(None, Some(_)) => Ordering::Greater,
+		(Some(_), None) => Ordering::Less,
+		(Some(x), Some(y)) => x.cmp(y).then(matches!(a, Bound::Included(_)).cmp(&matches!(b, Bound::Included(_)))),
+	}
+}
+
+impl FromStr for VersionRange {
+	type Err = anyhow::Error;
+
+	// Like forge, an empty range or a bare version (maven's "recommended" version) matches anything.
+	// Unlike maven, intervals that overlap or are out of order aren't an error, they just match what they match
+	fn from_str(string: &str) -> Result<Self, Self::Err> {
+		let string = string.trim();
+		if string.is_empty() || !string.starts_with(['[', '(']) {
+			return Ok(Self::any());
+		}
+
+		let mut intervals = Vec::new();
+		let mut rest = string;
+		while !rest.is_empty() {
+			let open = rest.chars().next().unwrap();
+			if !matches!(open, '[' | '(') {
+				bail!("Unparseable version range {string:?}: expected [ or ( at {rest:?}");
+			}
+			let close = rest.find([']', ')']).ok_or(anyhow!("Unparseable version range {string:?}: unclosed interval"))?;
+			let inner = rest[1..close].trim();
+			let close_char = rest[close..].chars().next().unwrap();
+
+			let interval = match inner.split_once(',') {
+				None => {
+					if open != '[' || close_char != ']' || inner.is_empty() {
+						bail!("Unparseable version range {string:?}: a single version has to be [x]");
+					}
+					let version = ComparableVersion::new(inner);
+					Interval { min: Bound::Included(version.clone()), max: Bound::Included(version) }
+				}
+				Some((min, max)) => {
+					let (min, max) = (min.trim(), max.trim());
+					let min = match (min.is_empty(), open) {
+						(true, _) => Bound::Unbounded,
+						(false, '[') => Bound::Included(ComparableVersion::new(min)),
+						(false, _) => Bound::Excluded(ComparableVersion::new(min)),
+					};
+					let max = match (max.is_empty(), close_char) {
+						(true, _) => Bound::Unbounded,
+						(false, ']') => Bound::Included(ComparableVersion::new(max)),
+						(false, _) => Bound::Excluded(ComparableVersion::new(max)),
+					};
+					Interval { min, max }
+				}
+			};
+			intervals.push(interval);
+
+			rest = rest[close + 1..].trim_start();
+			if let Some(next) = rest.strip_prefix(',') {
+				rest = next.trim_start();
+				if rest.is_empty() {
+					bail!("Unparseable version range {string:?}: trailing comma");
+				}
+			} else if !rest.is_empty() {
+				bail!("Unparseable version range {string:?}: expected , at {rest:?}");
+			}
+		}
+		Ok(Self(intervals))
+	}
+}
+
+impl core::fmt::Display for Interval {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		if let (Bound::Included(min), Bound::Included(max)) = (&self.min, &self.max) && min == max {
+			return write!(f, "[{min}]");
+		}
+		match &self.min {
+			Bound::Included(x) => write!(f, "[{x},")?,
+			Bound::Excluded(x) => write!(f, "({x},")?,
+			Bound::Unbounded => write!(f, "(,")?,
+		}
+		match &self.max {
+			Bound::Included(x) => write!(f, "{x}]"),
+			Bound::Excluded(x) => write!(f, "{x})"),
+			Bound::Unbounded => write!(f, ")"),
+		}
+	}
+}
+
+impl core::fmt::Display for VersionRange {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		for (i, interval) in self.0.iter().enumerate() {
+			if i != 0 {
+				write!(f, ",")?;
+			}
+			write!(f, "{interval}")?;
+		}
+		Ok(())
+	}
+}
+
+// The wire form, versions need wrapping to be encoded
+#[derive(Encode, Decode)]
+struct WireInterval {
+	min: Bound<WrappedComparableVersion>,
+	max: Bound<WrappedComparableVersion>,
+}
+
+fn wrap(bound: &Bound<ComparableVersion>) -> Bound<WrappedComparableVersion> {
+	match bound {
+		Bound::Included(x) => Bound::Included(x.clone().into()),
+		Bound::Excluded(x) => Bound::Excluded(x.clone().into()),
+		Bound::Unbounded => Bound::Unbounded,
+	}
+}
+
+fn unwrap(bound: Bound<WrappedComparableVersion>) -> Bound<ComparableVersion> {
+	match bound {
+		Bound::Included(x) => Bound::Included(x.into()),
+		Bound::Excluded(x) => Bound::Excluded(x.into()),
+		Bound::Unbounded => Bound::Unbounded,
+	}
+}
+
+impl From<&Interval> for WireInterval {
+	fn from(value: &Interval) -> Self {
+		Self { min: wrap(&value.min), max: wrap(&value.max) }
+	}
+}
+
+impl From<WireInterval> for Interval {
+	fn from(value: WireInterval) -> Self {
+		Self { min: unwrap(value.min), max: unwrap(value.max) }
+	}
+}
+
+// Bare min and max bounds, which is all peers before protocol 4 know
+impl<W: ende::io::Write> Encode<W> for Interval {
+	fn encode(&self, encoder: &mut Encoder<W>) -> EncodingResult<()> {
+		WireInterval::from(self).encode(encoder)
+	}
+}
+
+impl<R: ende::io::Read> Decode<R> for Interval {
+	fn decode(decoder: &mut Encoder<R>) -> EncodingResult<Self> {
+		Ok(WireInterval::decode(decoder)?.into())
+	}
+}
+
+impl<W: ende::io::Write> Encode<W> for VersionRange {
+	fn encode(&self, encoder: &mut Encoder<W>) -> EncodingResult<()> {
+		let intervals: Vec<WireInterval> = self.0.iter().map(WireInterval::from).collect();
+		intervals.encode(encoder)
+	}
+}
+
+impl<R: ende::io::Read> Decode<R> for VersionRange {
+	fn decode(decoder: &mut Encoder<R>) -> EncodingResult<Self> {
+		let intervals = Vec::<WireInterval>::decode(decoder)?;
+		// A range always has at least one interval, with none it wouldn't even survive being printed
+		if intervals.is_empty() {
+			return Err(val_error!("Empty version range"));
+		}
+		Ok(Self(intervals.into_iter().map(Interval::from).collect()))
+	}
+}
+
+impl Serialize for VersionRange {
+	fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+	where
+		S: Serializer,
+	{
+		self.to_string().serialize(serializer)
+	}
+}
+
+impl<'de> Deserialize<'de> for VersionRange {
+	fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+	where
+		D: Deserializer<'de>,
+	{
+		let string = String::deserialize(deserializer)?;
+		// Unlike in mods.toml, nothing serializes to that, `(,)` is any version
+		if string.trim().is_empty() {
+			return Err(serde::de::Error::custom("Empty version range"));
+		}
+		string.parse().map_err(serde::de::Error::custom)
+	}
+}
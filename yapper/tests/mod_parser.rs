@@ -0,0 +1,538 @@
+use anyhow::Result;
+use mvn_version::ComparableVersion;
+use yapper::{Capabilities, Crypt, decode_frame, DepKind, DisplayTest, encode_frame, FrameLimit, Hashes, json, LoadOrder, MIN_PROTOCOL_VERSION, ModDependency, ModInfo, parse_mod, parse_mods, protocol_context, PROTOCOL_VERSION, ProvidedMod, Response, Role, Side, VersionRange};
+use yapper::testing::{mod_jar, TempDir, write_jar};
+
+fn v(version: &str) -> ComparableVersion {
+	ComparableVersion::new(version)
+}
+
+fn r(range: &str) -> VersionRange {
+	range.parse().unwrap()
+}
+
+fn dep<'a>(info: &'a ModInfo, mod_id: &str) -> &'a ModDependency {
+	info.dependencies.iter().find(|x| x.mod_id == mod_id).unwrap_or_else(|| panic!("{mod_id} missing"))
+}
+
+#[test]
+fn fabric() -> Result<()> {
+	let dir = TempDir::new("mod_parser")?;
+	let path = dir.path().join("example-fabric.jar");
+	let json = r#"{
+		"schemaVersion": 1,
+		"id": "example",
+		"version": "1.2.3+mc1.20.1",
+		"name": "Example",
+		"description": "Does things",
+		"icon": { "16": "assets/example/small.png", "128": "assets/example/big.png" },
+		"authors": ["Someone", { "name": "Someone Else", "contact": {} }],
+		"contributors": ["Helper"],
+		"contact": { "sources": "https://example.com/src", "homepage": "https://example.com" },
+		"environment": "client",
+		"depends": {
+			"fabricloader": ">=0.15.0",
+			"minecraft": "~1.20.1",
+			"fabric-api": "*",
+			"java": ">=17"
+		},
+		"recommends": { "modmenu": ["7.x", "8.x"] },
+		"breaks": { "optifabric": "<1.14 >=1.0" }
+	}"#;
+	write_jar(&path, &[
+		("fabric.mod.json", json.as_bytes()),
+		("assets/example/small.png", b"small"),
+		("assets/example/big.png", b"big"),
+	])?;
+
+	let info = parse_mod(&path)?;
+	assert_eq!(info.mod_id, "example");
+	assert_eq!(info.filename, "example-fabric.jar");
+	assert_eq!(info.name(), "Example");
+	assert_eq!(info.version, v("1.2.3+mc1.20.1"));
+	assert_eq!(info.logo.as_deref(), Some(&b"big"[..]));
+	assert_eq!(info.url.as_deref(), Some("https://example.com"));
+	assert_eq!(info.authors, Some(vec!["Someone".to_owned(), "Someone Else".to_owned()]));
+	assert_eq!(info.credits.as_deref(), Some("Helper"));
+
+	assert!(info.dependencies.iter().all(|x| x.mod_id != "java"));
+	assert!(info.dependencies.iter().all(|x| x.side == Side::Client));
+
+	let loader = dep(&info, "fabricloader");
+	assert_eq!(loader.kind, DepKind::Required);
+	assert_eq!(loader.range, r("[0.15.0,)"));
+
+	let mc = dep(&info, "minecraft");
+	assert_eq!(mc.range, r("[1.20.1,1.21)"));
+
+	let api = dep(&info, "fabric-api");
+	assert_eq!(api.range, VersionRange::any());
+
+	let menu = dep(&info, "modmenu");
+	assert_eq!(menu.kind, DepKind::Recommended);
+	assert_eq!(menu.range, r("[7,8),[8,9)"));
+	assert_eq!(dep(&info.clone().downgrade(8), "modmenu").kind, DepKind::Optional);
+
+	let breaks = dep(&info, "optifabric");
+	assert_eq!(breaks.kind, DepKind::Incompatible);
+	assert_eq!(breaks.range, r("[1.0,1.14)"));
+	Ok(())
+}
+
+#[test]
+fn quilt() -> Result<()> {
+	let dir = TempDir::new("mod_parser")?;
+	let path = dir.path().join("example-quilt.jar");
+	let json = r#"{
+		"schema_version": 1,
+		"quilt_loader": {
+			"group": "com.example",
+			"id": "example",
+			"version": "2.0.0",
+			"metadata": {
+				"name": "Example",
+				"contributors": { "Someone": "Owner", "Helper": ["Artist", "Translator"] },
+				"contact": { "homepage": "https://example.com" },
+				"icon": "assets/example/icon.png"
+			},
+			"depends": [
+				"quilt_base",
+				{ "id": "org.quiltmc:quilt_loader", "versions": ">=0.19.0" },
+				{ "id": "minecraft", "versions": { "any": ["1.20.x", { "all": [">=1.19.4", "<1.20"] }] } },
+				{ "id": "modmenu", "versions": "^7.0.0", "optional": true },
+				[{ "id": "either" }, { "id": "or" }]
+			],
+			"breaks": [{ "id": "optifabric", "versions": ["<1.0", "=1.5.0"] }],
+			"provides": ["org.quiltmc:example_api", { "id": "example_old", "version": "1.9" }]
+		},
+		"minecraft": { "environment": "dedicated_server" }
+	}"#;
+	write_jar(&path, &[
+		("quilt.mod.json", json.as_bytes()),
+		("fabric.mod.json", br#"{ "id": "wrong", "version": "0" }"#),
+		("assets/example/icon.png", b"icon"),
+	])?;
+
+	let info = parse_mod(&path)?;
+	assert_eq!(info.mod_id, "example");
+	assert_eq!(info.version, v("2.0.0"));
+	assert_eq!(info.logo.as_deref(), Some(&b"icon"[..]));
+	assert_eq!(info.url.as_deref(), Some("https://example.com"));
+	assert_eq!(info.authors, Some(vec!["Someone".to_owned()]));
+	assert_eq!(info.credits.as_deref(), Some("Helper (Artist, Translator)"));
+
+	let ids: Vec<&str> = info.dependencies.iter().map(|x| x.mod_id.as_str()).collect();
+	assert_eq!(ids, ["quilt_base", "quilt_loader", "minecraft", "modmenu", "either", "optifabric"]);
+	assert!(info.dependencies.iter().all(|x| x.side == Side::Server));
+
+	let base = dep(&info, "quilt_base");
+	assert_eq!((base.kind, &base.range), (DepKind::Required, &VersionRange::any()));
+
+	let mc = dep(&info, "minecraft");
+	assert_eq!(mc.range, r("[1.20,1.21),[1.19.4,1.20)"));
+
+	let menu = dep(&info, "modmenu");
+	assert_eq!(menu.kind, DepKind::Optional);
+	assert_eq!(menu.range, r("[7.0.0,8)"));
+
+	let either = dep(&info, "either");
+	assert_eq!(either.kind, DepKind::Required);
+	assert_eq!(either.alternatives, [("or".to_owned(), VersionRange::any())]);
+	assert_eq!(json::dependency_json(either)["alternatives"], serde_json::json!([{ "mod_id": "or", "range": "(,)" }]));
+
+	let breaks = dep(&info, "optifabric");
+	assert_eq!(breaks.kind, DepKind::Incompatible);
+	assert_eq!(breaks.range, r("(,1.0),[1.5.0]"));
+	// Used to be widened to everything up to 1.5.0
+	assert!(!breaks.range.contains(&v("1.2")));
+
+	let provides: Vec<(&str, &ComparableVersion)> = info.provides.iter().map(|x| (x.mod_id.as_str(), &x.version)).collect();
+	assert_eq!(provides, [("example_api", &v("2.0.0")), ("example_old", &v("1.9"))]);
+	Ok(())
+}
+
+#[test]
+fn neoforge() -> Result<()> {
+	let dir = TempDir::new("mod_parser")?;
+	let path = dir.path().join("example-neoforge.jar");
+	let toml = r#"
+		modLoader = "javafml"
+		loaderVersion = "[4,)"
+		license = "MIT"
+
+		[[mods]]
+		modId = "example"
+		version = "3.1.0"
+
+		[[dependencies.example]]
+		modId = "neoforge"
+		type = "required"
+		versionRange = "[21.0,)"
+		side = "BOTH"
+
+		[[dependencies.example]]
+		modId = "jei"
+		type = "optional"
+		versionRange = "[19,)"
+		side = "CLIENT"
+
+		[[dependencies.example]]
+		modId = "optifine"
+		type = "incompatible"
+		side = "BOTH"
+
+		[[dependencies.example]]
+		modId = "rubidium"
+		type = "DISCOURAGED"
+		versionRange = "[0.7,)"
+		side = "CLIENT"
+
+		[[dependencies.example]]
+		modId = "minecraft"
+		side = "BOTH"
+	"#;
+	write_jar(&path, &[("META-INF/neoforge.mods.toml", toml.as_bytes())])?;
+
+	let info = parse_mod(&path)?;
+	assert_eq!(info.mod_id, "example");
+	assert_eq!(info.version, v("3.1.0"));
+
+	let kinds: Vec<(&str, DepKind)> = info.dependencies.iter().map(|x| (x.mod_id.as_str(), x.kind)).collect();
+	assert_eq!(kinds, [
+		("neoforge", DepKind::Required),
+		("jei", DepKind::Optional),
+		("optifine", DepKind::Incompatible),
+		("rubidium", DepKind::Discouraged),
+		// Neither `type` nor `mandatory` means required
+		("minecraft", DepKind::Required),
+	]);
+	assert_eq!(dep(&info, "jei").side, Side::Client);
+	assert_eq!(dep(&info, "rubidium").range, r("[0.7,)"));
+	Ok(())
+}
+
+#[test]
+fn mods_toml_metadata() -> Result<()> {
+	let dir = TempDir::new("mod_parser")?;
+	let path = dir.path().join("example-forge.jar");
+	let toml = r#"
+		modLoader = "javafml"
+		loaderVersion = "[47,48)"
+		license = "All rights reserved"
+		issueTrackerURL = "https://example.com/issues"
+		clientSideOnly = true
+
+		[[mods]]
+		modId = "example"
+		version = "1.0"
+		updateJSONURL = "https://example.com/update.json"
+		displayTest = "IGNORE_ALL_VERSION"
+
+		[[dependencies.example]]
+		modId = "forge"
+		mandatory = true
+		versionRange = "[47,)"
+		ordering = "NONE"
+		side = "BOTH"
+
+		[[dependencies.example]]
+		modId = "jei"
+		mandatory = false
+		ordering = "AFTER"
+		side = "CLIENT"
+	"#;
+	write_jar(&path, &[("META-INF/mods.toml", toml.as_bytes())])?;
+
+	let info = parse_mod(&path)?;
+	assert_eq!(info.loader.as_deref(), Some("javafml"));
+	assert_eq!(info.loader_version, Some(r("[47,48)")));
+	assert_eq!(info.license.as_deref(), Some("All rights reserved"));
+	assert_eq!(info.issue_tracker.as_deref(), Some("https://example.com/issues"));
+	assert_eq!(info.update_json.as_deref(), Some("https://example.com/update.json"));
+	assert_eq!(info.display_test, Some(DisplayTest::IgnoreAllVersion));
+	assert!(info.client_side_only);
+	assert_eq!(dep(&info, "forge").ordering, None);
+	assert_eq!(dep(&info, "jei").ordering, Some(LoadOrder::After));
+	Ok(())
+}
+
+#[test]
+fn mcmod_info() -> Result<()> {
+	let dir = TempDir::new("mod_parser")?;
+	let path = dir.path().join("example-1.12.2.jar");
+	let json = "{
+		\"modListVersion\": 2,
+		\"modList\": [{
+			\"modid\": \"example\",
+			\"name\": \"Example\",
+			\"description\": \"Spans
+two lines\",
+			\"version\": \"1.4.2\",
+			\"mcversion\": \"1.12.2\",
+			\"url\": \"\",
+			\"authorList\": [\"Someone\"],
+			\"logoFile\": \"/assets/example/logo.png\",
+			\"requiredMods\": [\"required-after:forge@[14.23,)\", \"baubles@1.5\"],
+			\"dependencies\": [\"baubles\", \"after:jei\", \"required-client:ctm@[1.0,2.0)\", \"after:*\"],
+			\"useDependencyInformation\": true
+		}]
+	}";
+	write_jar(&path, &[
+		("mcmod.info", json.as_bytes()),
+		("assets/example/logo.png", b"logo"),
+	])?;
+
+	let info = parse_mod(&path)?;
+	assert_eq!(info.mod_id, "example");
+	assert_eq!(info.version, v("1.4.2"));
+	assert_eq!(info.description.as_deref(), Some("Spans two lines"));
+	assert_eq!(info.url, None);
+	assert_eq!(info.authors, Some(vec!["Someone".to_owned()]));
+	assert_eq!(info.logo.as_deref(), Some(&b"logo"[..]));
+
+	let kinds: Vec<(&str, DepKind)> = info.dependencies.iter().map(|x| (x.mod_id.as_str(), x.kind)).collect();
+	assert_eq!(kinds, [
+		("forge", DepKind::Required),
+		("baubles", DepKind::Required),
+		("jei", DepKind::Optional),
+		("ctm", DepKind::Required),
+	]);
+
+	let forge = dep(&info, "forge");
+	assert_eq!(forge.range, r("[14.23,)"));
+	// A bare version is a minimum
+	assert_eq!(dep(&info, "baubles").range, r("[1.5,)"));
+	assert_eq!(dep(&info, "forge").ordering, Some(LoadOrder::After));
+	let ctm = dep(&info, "ctm");
+	assert_eq!((ctm.side, &ctm.range), (Side::Client, &r("[1.0,2.0)")));
+	Ok(())
+}
+
+#[test]
+fn several_mods_in_one_jar() -> Result<()> {
+	let dir = TempDir::new("mod_parser")?;
+	let path = dir.path().join("bundle.jar");
+	let toml = r#"
+		modLoader = "javafml"
+		loaderVersion = "[47,)"
+		license = "MIT"
+		logoFile = "shared.png"
+
+		[[mods]]
+		modId = "bundle"
+		version = "1.0"
+
+		[[mods]]
+		modId = "bundle_lib"
+		version = "0.3"
+		logoFile = "lib.png"
+
+		[[dependencies.bundle]]
+		modId = "bundle_lib"
+		mandatory = true
+		versionRange = "[0.3,)"
+		side = "BOTH"
+
+		[[dependencies.bundle_lib]]
+		modId = "forge"
+		mandatory = true
+		versionRange = "[47,)"
+		side = "BOTH"
+	"#;
+	write_jar(&path, &[
+		("META-INF/mods.toml", toml.as_bytes()),
+		("shared.png", b"shared"),
+		("lib.png", b"lib"),
+	])?;
+
+	let mods = parse_mods(&path)?;
+	let ids: Vec<&str> = mods.iter().map(|x| x.mod_id.as_str()).collect();
+	assert_eq!(ids, ["bundle", "bundle_lib"]);
+	assert!(mods.iter().all(|x| x.path == mods[0].path && x.filename == "bundle.jar"));
+
+	// Each gets its own dependencies and falls back to the shared logo
+	assert_eq!(mods[0].dependencies.iter().map(|x| x.mod_id.as_str()).collect::<Vec<_>>(), ["bundle_lib"]);
+	assert_eq!(mods[1].dependencies.iter().map(|x| x.mod_id.as_str()).collect::<Vec<_>>(), ["forge"]);
+	assert_eq!(mods[0].logo.as_deref(), Some(&b"shared"[..]));
+	assert_eq!(mods[1].logo.as_deref(), Some(&b"lib"[..]));
+
+	// The single mod api gets the jar's own
+	assert_eq!(parse_mod(&path)?.mod_id, "bundle");
+	Ok(())
+}
+
+#[test]
+fn jar_in_jar() -> Result<()> {
+	let dir = TempDir::new("mod_parser")?;
+	let lib = std::fs::read(mod_jar(&dir.path().join("lib.jar"), "some_lib", "2.3.1", &[])?)?;
+	let plain = dir.path().join("plain.jar");
+	write_jar(&plain, &[("com/example/Plain.class", b"")])?;
+	let plain = std::fs::read(plain)?;
+
+	let metadata = r#"{ "jars": [
+		{
+			"identifier": { "group": "com.example", "artifact": "some_lib" },
+			"version": { "range": "[2.3,3)", "artifactVersion": "2.3.1" },
+			"path": "META-INF/jarjar/lib.jar",
+			"isObfuscated": false
+		},
+		{
+			"identifier": { "group": "com.example", "artifact": "plain" },
+			"version": { "range": "[1.0,)", "artifactVersion": "1.0.4" },
+			"path": "META-INF/jarjar/plain.jar",
+			"isObfuscated": false
+		}
+	] }"#;
+	let path = dir.path().join("outer.jar");
+	let toml = "modLoader = \"javafml\"\nloaderVersion = \"[47,)\"\nlicense = \"MIT\"\n\n[[mods]]\nmodId = \"outer\"\nversion = \"1.0\"\n";
+	write_jar(&path, &[
+		("META-INF/mods.toml", toml.as_bytes()),
+		("META-INF/jarjar/metadata.json", metadata.as_bytes()),
+		("META-INF/jarjar/lib.jar", &lib),
+		("META-INF/jarjar/plain.jar", &plain),
+	])?;
+
+	let info = parse_mod(&path)?;
+	let provides: Vec<(&str, &ComparableVersion)> = info.provides.iter().map(|x| (x.mod_id.as_str(), &x.version)).collect();
+	// The mod is read from its own metadata, the library goes by its artifact
+	assert_eq!(provides, [("some_lib", &v("2.3.1")), ("plain", &v("1.0.4"))]);
+
+	// A broken bundle only loses what it bundles
+	let broken = dir.path().join("broken.jar");
+	write_jar(&broken, &[
+		("META-INF/mods.toml", toml.as_bytes()),
+		("META-INF/jarjar/metadata.json", b"{ \"jars\": "),
+	])?;
+	let info = parse_mod(&broken)?;
+	assert_eq!(info.mod_id, "outer");
+	assert!(info.provides.is_empty());
+	Ok(())
+}
+
+#[test]
+fn version_range() -> Result<()> {
+	let union: VersionRange = "[1.0,2.0), [3.0,)".parse()?;
+	assert!(union.contains(&v("1.5")));
+	assert!(!union.contains(&v("2.0")));
+	assert!(!union.contains(&v("2.5")));
+	assert!(union.contains(&v("3.0")));
+	assert_eq!(union.to_string(), "[1.0,2.0),[3.0,)");
+
+	// Unbounded below, inclusive above
+	let upto = r("(,1.0]");
+	assert!(upto.contains(&v("0.1")));
+	assert!(upto.contains(&v("1.0")));
+	assert!(!upto.contains(&v("1.0.1")));
+	assert_eq!(upto.to_string(), "(,1.0]");
+
+	// Everything but 1.5
+	let exclusion = r("(,1.5),(1.5,)");
+	assert!(exclusion.contains(&v("1.4")));
+	assert!(!exclusion.contains(&v("1.5")));
+	assert!(exclusion.contains(&v("1.6")));
+
+	let exact = r("[1.2]");
+	assert!(exact.contains(&v("1.2")));
+	assert!(!exact.contains(&v("1.2.1")));
+	assert_eq!(exact.to_string(), "[1.2]");
+
+	// Like forge, missing and bare versions don't restrict anything
+	assert_eq!(r(""), VersionRange::any());
+	assert_eq!(r("1.0"), VersionRange::any());
+
+	for bad in ["[1.0,2.0", "(1.0)", "[1.0,2.0),", "[1.0,2.0) [3.0,)"] {
+		assert!(bad.parse::<VersionRange>().is_err(), "{bad:?} parsed");
+	}
+
+	let round_trip: VersionRange = serde_json::from_value(serde_json::to_value(&union)?)?;
+	assert_eq!(round_trip, union);
+	// Nothing prints as that, it's not "any version"
+	assert!(serde_json::from_str::<VersionRange>("\"\"").is_err());
+
+	// Would print as "" and couldn't be downgraded
+	let mut client = Crypt::new(&[7; 16], Role::Client)?;
+	let mut server = Crypt::new(&[7; 16], Role::Server)?;
+	let frame = encode_frame(client.send_half(), protocol_context(), Vec::<String>::new())?;
+	assert!(decode_frame::<VersionRange>(server.recv_half(), protocol_context(), &frame[4..]).is_err());
+	assert_eq!(union.hull().to_string(), "[1.0,)");
+	Ok(())
+}
+
+#[test]
+fn hashes() -> Result<()> {
+	let hashes = Hashes::of(b"Hello, world!\n");
+	assert_eq!(hashes.sha1, "09fac8dbfd27bd9b4d23a00eb648aa751789536d");
+	assert_eq!(hashes.sha512.len(), 128);
+	assert_eq!(hashes.murmur2, 2173663876);
+	// Whitespace doesn't count towards the fingerprint
+	assert_eq!(Hashes::of(b"Hello,\tworld!\r\n").murmur2, hashes.murmur2);
+	assert_ne!(Hashes::of(b"Hello,\tworld!\r\n").sha1, hashes.sha1);
+
+	let dir = TempDir::new("mod_parser")?;
+	let path = mod_jar(&dir.path().join("example.jar"), "example", "1.0", &[])?;
+	let info = parse_mod(&path)?;
+	assert_eq!(info.hashes, Some(Hashes::of(&std::fs::read(&path)?)));
+	Ok(())
+}
+
+// Older peers get their own layout, without whatever their version didn't have yet
+#[test]
+fn wire_versions() -> Result<()> {
+	let dir = TempDir::new("mod_parser")?;
+	let path = mod_jar(&dir.path().join("example.jar"), "example", "1.0", &[])?;
+	let mut info = parse_mod(&path)?;
+	info.dependencies.push(ModDependency {
+		mod_id: "other".to_owned(),
+		kind: DepKind::Incompatible,
+		range: r("[1.0,2.0),[3.0,4.0)"),
+		side: Side::Both,
+		ordering: Some(LoadOrder::After),
+		alternatives: Vec::new(),
+	});
+	info.dependencies.push(ModDependency {
+		mod_id: "either".to_owned(),
+		kind: DepKind::Required,
+		range: VersionRange::any(),
+		side: Side::Both,
+		ordering: None,
+		alternatives: vec![("or".to_owned(), r("[1.0,)"))],
+	});
+	info.provides.push(ProvidedMod { mod_id: "bundled".to_owned(), version: v("1.0") });
+
+	let send = |version: u16| -> Result<ModInfo> {
+		let mut client = Crypt::new(&[7; 16], Role::Client)?;
+		let mut server = Crypt::new(&[7; 16], Role::Server)?;
+		client.set_version(version);
+		server.set_version(version);
+		server.set_limit(FrameLimit::SESSION);
+		let frame = encode_frame(client.send_half(), protocol_context(), info.clone())?;
+		decode_frame(server.recv_half(), protocol_context(), &frame[4..])
+	};
+
+	assert_eq!(send(PROTOCOL_VERSION)?, info);
+	for version in MIN_PROTOCOL_VERSION..PROTOCOL_VERSION {
+		assert_eq!(send(version)?, info.clone().downgrade(version), "protocol {version}");
+	}
+
+	let old = info.clone().downgrade(MIN_PROTOCOL_VERSION);
+	assert!(old.provides.is_empty());
+	assert_eq!(old.loader, None);
+	assert_eq!(old.hashes, None);
+	// It would read a conflict as a requirement
+	assert!(old.dependencies.iter().all(|x| x.mod_id != "other"));
+	// A single interval covering both would conflict with 2.5 too
+	let v3 = info.clone().downgrade(3);
+	let other: Vec<String> = v3.dependencies.iter().filter(|x| x.mod_id == "other").map(|x| x.range.to_string()).collect();
+	assert_eq!(other, ["[1.0,2.0)", "[3.0,4.0)"]);
+	// Requiring just the first one would turn away servers that have the other
+	let either = dep(&info.clone().downgrade(6), "either");
+	assert_eq!(either.kind, DepKind::Optional);
+	assert!(either.alternatives.is_empty());
+
+	let duplicate = Response::DuplicateMod("example.jar".to_owned());
+	assert_eq!(duplicate.clone().downgrade(5, Capabilities::all()), Response::ModConflict);
+	assert_eq!(duplicate.clone().downgrade(PROTOCOL_VERSION, Capabilities::all()), duplicate);
+	let removed = Response::ModsRemoved(vec!["bundle_lib".to_owned()]);
+	assert_eq!(removed.downgrade(7, Capabilities::all()), Response::Ok);
+	Ok(())
+}